@@ -1,11 +1,22 @@
 // WSL2/Ubuntu users: Make sure that you have pkg-config and libssl-dev installed!
 
 use exchange::request_data;
-use exchange::cli::{Cli, Command};
-use exchange::kafka::producer::push_to_kafka;
+use exchange::azure::encryption::{DecryptionKeys, KeyRing};
+use exchange::blobs::{download_blobs, format_blob, format_blob_info, rotate_keys};
+use exchange::cli::{BlobsCommand, Cli, Command};
+use exchange::kafka::producer::{push_record_to_kafka, read_records, new_kafka_sink, OutgoingRecord};
 use exchange::kafka::consumer::read_from_kafka;
-use exchange::azure::writer::push_to_azure;
-use exchange::azure::reader::pull_from_azure;
+use exchange::azure::reader::{write_output, Output};
+use exchange::azure::writer::WriteOptions;
+use exchange::export::metadata::{collect_tags, file_metadata, API_NAME_HEADER};
+use exchange::export::parquet::load_schema;
+use exchange::forward::{forward_to_store, ForwardOptions};
+use exchange::replay::{replay_to_kafka, ReplayOptions};
+use exchange::retention::{configured_rules, prune_blobs, PruneOptions, RetentionRule};
+use exchange::storage::{read_blob, write_file_blob};
+
+use std::sync::Arc;
+use std::time::Duration;
 
 use clap::*;
 use log::{info, warn, error};
@@ -25,55 +36,136 @@ async fn main() {
             info!("Producer selected");
             info!("Topic: {}, File: {}", topic, file);
 
-            let result = push_to_kafka(&topic, &file).await;
+            let records = match read_records(&file) {
+                Ok(records) => records,
+                Err(e) => {
+                    error!("Error while reading records from {}: {}", &file, e);
+                    return;
+                }
+            };
 
-            match result {
-                Ok(_) => {
-                    info!("Data pushed to Kafka");
-                    info!("Data: {:?}", result.unwrap());                
-            },
-                Err(e) => warn!("Error while pushing data to Kafka: {}", e)
+            info!("Record(s) read from {}: {}", &file, records.len());
+
+            let sink = match new_kafka_sink() {
+                Ok(sink) => sink,
+                Err(e) => {
+                    error!("Error while creating Kafka producer: {}", e);
+                    return;
+                }
+            };
+
+            // Produce one Kafka record per input record and summarize each result
+            let records: Vec<OutgoingRecord> = records.into_iter().map(OutgoingRecord::new).collect();
+            let report = sink.send_batch(&topic, &records).await;
+
+            // One line per record in input order, the position is 1-based
+            let mut lines: Vec<(usize, String)> = report.positions.iter().zip(&report.messages)
+                .map(|(index, message)| (*index, format!("Record {} (key: {}): sent (partition: {}, offset: {}, bytes: {})",
+                    index + 1, message.key.as_deref().unwrap_or_default(), message.partition, message.offset, message.size)))
+                .chain(report.failures.iter()
+                    .map(|failure| (failure.index, format!("Record {} (key: {}): failed (error: {})",
+                        failure.index + 1, failure.key.as_deref().unwrap_or_default(), failure.error))))
+                .collect();
+            lines.sort_by_key(|(index, _)| *index);
+            for (_, line) in lines {
+                println!("{}", line);
             }
 
+            println!("Records sent: {}, failed: {}, bytes sent: {}", report.total_messages, report.failures.len(), report.total_bytes);
+
         },
 
-        Command::Consume{topic, ttl} => {
+        Command::Consume{topic, ttl, max_retries, consumer} => {
             info!("Consumer selected");
-            info!("Topic: {}, TTL: {}", topic, ttl);
+            info!("Topic: {}, TTL: {}, Max retries: {}", topic, ttl, max_retries);
+
+            let options = consumer.options(Duration::from_secs(ttl), max_retries);
 
-            let result = read_from_kafka(&topic, ttl).await;
+            let result = read_from_kafka(&topic, &options).await;
 
             match result {
-                Ok(_) => {
+                Ok(report) => {
                     info!("Data read from Kafka");
-                    info!("Data: {:?}", result.unwrap());
+                    for message in &report.messages {
+                        info!("Data: {:?}", message);
+                    }
+                    info!("Message(s) read: {}, bytes read: {}", report.total_messages, report.total_bytes);
                 },
                 Err(e) => warn!("Error while reading data from Kafka: {}", e)
             }
         },
 
-        Command::Read{container_name, file} => {
+        Command::Read{container_name, file, output, force, sink} => {
             info!("Reader selected");
             info!("Container name: {}, File: {}", container_name, file);
-            
-            let result = pull_from_azure(&container_name, &file).await;
+
+            // Without an output, the content is stored in a file named after the blob
+            let output = Output::parse(output.as_deref().unwrap_or(&file));
+
+            let store = match sink.store() {
+                Ok(store) => store,
+                Err(e) => {
+                    error!("Error while opening the sink: {}", e);
+                    return;
+                }
+            };
+            let result = read_blob(store.as_ref(), &container_name, &file, &DecryptionKeys::default()).await;
 
             match result {
-                Ok(_) => info!("Data pulled from Azure Blob Storage {} successfully", &container_name),
-                Err(e) => error!("Error while pulling data from Azure Blob Storage {}: {}", &container_name, e)
+                Ok(content) => {
+                    match write_output(&content, &output, force) {
+                        Ok(_) => info!("Data pulled from container {} successfully ({} bytes)", &container_name, content.len()),
+                        Err(e) => error!("Error while writing output {:?}: {}", &output, e)
+                    }
+                },
+                Err(e) => error!("Error while pulling data from container {}: {}", &container_name, e)
             }
         },
 
-        Command::Write{container_name, file} => {
+        Command::Write{container_name, file, compression, upload, conflict, tags, encrypt, sink} => {
             info!("Writer selected");
             info!("Container name: {}, File: {}", container_name, file);
-            
+
+            let tags = match collect_tags(&tags) {
+                Ok(tags) => tags,
+                Err(e) => {
+                    error!("Error while parsing tags: {}", e);
+                    return;
+                }
+            };
+
+            // The keys are only needed to encrypt
+            let encryption = match encrypt.then(KeyRing::load).transpose() {
+                Ok(encryption) => encryption,
+                Err(e) => {
+                    error!("Error while loading the encryption keys: {}", e);
+                    return;
+                }
+            };
+
+            let options = WriteOptions {
+                compression,
+                conflict: conflict.policy(),
+                metadata: file_metadata(&file),
+                tags,
+                encryption,
+                ..Default::default()
+            };
+
             //TODO: blob_name should be optional and default to file
-            let result = push_to_azure(&container_name, &file, &file).await;
+            // Large files are streamed to the sink in blocks
+            let store = match sink.upload_store(&upload) {
+                Ok(store) => store,
+                Err(e) => {
+                    error!("Error while opening the sink: {}", e);
+                    return;
+                }
+            };
+            let result = write_file_blob(store.as_ref(), &container_name, &file, std::path::Path::new(&file), &options).await;
 
             match result {
-                Ok(_) => info!("Data push to Azure Blob Storage {} successfully", &container_name),
-                Err(e) => error!("Error while pushing data to Azure Blob Storage {}: {}", &container_name, e)
+                Ok(blob_name) => info!("Data push to container {} successfully (blob: {})", &container_name, blob_name),
+                Err(e) => error!("Error while pushing data to container {}: {}", &container_name, e)
             }
         },
 
@@ -94,63 +186,254 @@ async fn main() {
             info!("API: {}, Topic: {}", &api_name, &topic);
 
             let response = match request_data(&api_name).await {
-                Ok(response) => Some(response),
+                Ok(response) => response,
                 Err(e) => {
                     error!("Error while requesting data from API {}: {}", &api_name, e);
-                    None
+                    return;
                 }
             };
 
-            let write = match push_to_kafka(&topic, &response.unwrap().to_string()).await {
+            // The API name travels with the message, so that forward can record it in the blob metadata
+            let record = OutgoingRecord::new(response.to_string()).with_header(API_NAME_HEADER, &api_name);
+            match push_record_to_kafka(&topic, record).await {
                 Ok(_) => info!("Data pushed to Kafka"),
-                Err(e) => error!("Error while pushing data to Kafka: {}", e)
+                Err(e) => {
+                    error!("Error while pushing data to Kafka: {}", e);
+                    return;
+                }
             };
             info!("Data ingest from API {} complete", &api_name)
 
         },
 
-        Command::Forward{topic, container_name, filename} => {
+        Command::Forward{topic, container_name, filename, idle_timeout, max_retries, format, schema, fields, compression, upload_retries, consumer, roll, upload, conflict, tags, encrypt, append, sink} => {
             info!("Forwarder selected");
 
-            let blob_name = filename; // I find it confusing to call the cli with blob_name directly
-            let mut content = String::new();
+            let tags = match collect_tags(&tags) {
+                Ok(tags) => tags,
+                Err(e) => {
+                    error!("Error while parsing tags: {}", e);
+                    return;
+                }
+            };
 
-            let consumer = match read_from_kafka(&topic, 2).await {
-                Ok(consumer) => Some(consumer),
+            // The keys are only needed to encrypt
+            let encryption = match encrypt.then(KeyRing::load).transpose() {
+                Ok(encryption) => encryption,
                 Err(e) => {
-                    error!("Error while reading data from Kafka: {}", e);
-                    None
+                    error!("Error while loading the encryption keys: {}", e);
+                    return;
                 }
             };
 
-            let _result = match consumer {
-                Some(consumer) => {
-                    // Check if message count is 0
-                    if consumer.0 == 0 {
-                        // The content should not be pushed to Azure Blob Storage since it results in an overwrite by default
-                        warn!("No data received from Kafka: Skipping push to Azure Blob Storage");
-                        return;
-                    }
+            let schema = match schema.as_deref().map(load_schema).transpose() {
+                Ok(schema) => schema.map(Arc::new),
+                Err(e) => {
+                    error!("Error while loading the Parquet schema: {}", e);
+                    return;
+                }
+            };
 
-                    info!("Message(s) read from Kafka: {}", consumer.0);
-                    // Create json from hashmap
-                    content = serde_json::to_value(consumer.1).unwrap().to_string();
+            let blob_name = filename; // I find it confusing to call the cli with blob_name directly
 
-                },
-                
-                None => {
-                    error!("No data received from Kafka: Check Kafka logs for more information");
+            // In continuous mode the consumer never stops when idle (only on Ctrl-C or a bound)
+            let max_retries = if roll.continuous { u32::MAX } else { max_retries };
+
+            let options = ForwardOptions {
+                consumer: consumer.options(Duration::from_secs(idle_timeout), max_retries),
+                format,
+                compression,
+                roll: roll.policy(),
+                upload_retries,
+                conflict: conflict.policy(),
+                tags,
+                encryption,
+                append,
+                schema,
+                fields,
+                ..Default::default()
+            };
+
+            let store = match sink.upload_store(&upload) {
+                Ok(store) => store,
+                Err(e) => {
+                    error!("Error while opening the sink: {}", e);
                     return;
                 }
             };
+            match forward_to_store(store.as_ref(), &topic, &container_name, &blob_name, &options).await {
+                Ok(report) => info!("Message(s) forwarded: {}, bytes: {}, blob(s): {:?}", report.messages, report.bytes, report.blobs),
+                Err(e) => error!("Error while forwarding data from Kafka to container {}: {}", &container_name, e)
+            };
 
-            let _write = match push_to_azure(&container_name, &blob_name, &content).await {
-                Ok(_) => info!("Data pushed to Azure Blob Storage {} successfully", &container_name),
-                Err(e) => error!("Error while pushing data to Azure Blob Storage {}: {}", &container_name, e)
+        },
+
+
+        Command::Replay{container_name, prefix, topic, rate, batch_size, checkpoint, sink} => {
+            info!("Replay selected");
+            info!("Container name: {}, Prefix: {:?}, Topic: {}", container_name, prefix, topic);
+
+            let options = ReplayOptions {
+                batch_size,
+                rate,
+                checkpoint: checkpoint.map(std::path::PathBuf::from),
             };
 
+            let store = match sink.store() {
+                Ok(store) => store,
+                Err(e) => {
+                    error!("Error while opening the sink: {}", e);
+                    return;
+                }
+            };
+            match replay_to_kafka(store.as_ref(), &container_name, prefix.as_deref(), &topic, &options).await {
+                Ok(report) => println!("Blobs replayed: {}, records sent: {}, skipped (checkpoint): {}, bytes sent: {}",
+                    report.blobs, report.messages, report.skipped, report.bytes),
+                Err(e) => error!("Error while replaying container {} to Kafka: {}", &container_name, e)
+            }
         },
 
+        Command::Blobs{command} => match command {
+
+            BlobsCommand::List{container_name, prefix, sink} => {
+                info!("Blob list selected");
+
+                let store = match sink.store() {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!("Error while opening the sink: {}", e);
+                        return;
+                    }
+                };
+                match store.list(&container_name, prefix.as_deref()).await {
+                    Ok(blobs) => {
+                        for blob in &blobs {
+                            println!("{}", format_blob(blob));
+                        }
+                        info!("Blob(s) in container {}: {}", &container_name, blobs.len());
+                    },
+                    Err(e) => error!("Error while listing blobs of container {}: {}", &container_name, e)
+                }
+            },
+
+            BlobsCommand::Info{container_name, blob_name, sink} => {
+                info!("Blob info selected");
+
+                let store = match sink.store() {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!("Error while opening the sink: {}", e);
+                        return;
+                    }
+                };
+                match store.info(&container_name, &blob_name).await {
+                    Ok(blob) => println!("{}", format_blob_info(&blob)),
+                    Err(e) => error!("Error while reading blob {} of container {}: {}", &blob_name, &container_name, e)
+                }
+            },
+
+            BlobsCommand::Download{container_name, prefix, out, sink} => {
+                info!("Blob download selected");
+                info!("Container name: {}, Prefix: {:?}, Output directory: {}", container_name, prefix, out);
+
+                let store = match sink.store() {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!("Error while opening the sink: {}", e);
+                        return;
+                    }
+                };
+                match download_blobs(store.as_ref(), &container_name, prefix.as_deref(), std::path::Path::new(&out)).await {
+                    Ok(report) => {
+                        for (blob_name, e) in &report.failures {
+                            println!("Blob {}: failed ({})", blob_name, e);
+                        }
+                        println!("Blobs downloaded: {}, failed: {}, bytes written: {}", report.files.len(), report.failures.len(), report.bytes);
+                    },
+                    Err(e) => error!("Error while downloading blobs of container {}: {}", &container_name, e)
+                }
+            },
+
+            BlobsCommand::Prune{container_name, prefix, older_than, keep_last, dry_run, parallelism, sink} => {
+                info!("Blob prune selected");
+                info!("Container name: {}, Prefix: {:?}, Older than: {:?}, Keep last: {:?}", container_name, prefix, older_than, keep_last);
+
+                // The command line replaces the rules of retention_config.json
+                let rules = if older_than.is_some() || keep_last.is_some() {
+                    RetentionRule::new("", older_than, keep_last).map(|rule| vec![rule])
+                } else {
+                    configured_rules(&container_name)
+                };
+                let rules = match rules {
+                    Ok(rules) if rules.is_empty() => {
+                        error!("No retention rule for container {}: Use --older-than, --keep-last or retention_config.json", &container_name);
+                        return;
+                    },
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        error!("Error while loading the retention rules: {}", e);
+                        return;
+                    }
+                };
+
+                let options = PruneOptions {
+                    dry_run,
+                    parallelism: parallelism as usize,
+                };
+
+                let store = match sink.store() {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!("Error while opening the sink: {}", e);
+                        return;
+                    }
+                };
+                match prune_blobs(store.as_ref(), &container_name, prefix.as_deref(), &rules, &options).await {
+                    Ok(report) => {
+                        let action = if dry_run { "would be deleted" } else { "deleted" };
+                        for blob_name in &report.deleted {
+                            println!("Blob {}: {}", blob_name, action);
+                        }
+                        for (blob_name, e) in &report.failures {
+                            println!("Blob {}: failed ({})", blob_name, e);
+                        }
+                        println!("Blobs {}: {} ({} bytes), kept: {}, failed: {}", action, report.deleted.len(), report.bytes, report.kept, report.failures.len());
+                    },
+                    Err(e) => error!("Error while pruning blobs of container {}: {}", &container_name, e)
+                }
+            },
+
+            BlobsCommand::RotateKeys{container_name, prefix, sink} => {
+                info!("Key rotation selected");
+                info!("Container name: {}, Prefix: {:?}", container_name, prefix);
+
+                let keys = match KeyRing::load() {
+                    Ok(keys) => keys,
+                    Err(e) => {
+                        error!("Error while loading the encryption keys: {}", e);
+                        return;
+                    }
+                };
+
+                let store = match sink.store() {
+                    Ok(store) => store,
+                    Err(e) => {
+                        error!("Error while opening the sink: {}", e);
+                        return;
+                    }
+                };
+                match rotate_keys(store.as_ref(), &container_name, prefix.as_deref(), &keys).await {
+                    Ok(report) => {
+                        for (blob_name, e) in &report.failures {
+                            println!("Blob {}: failed ({})", blob_name, e);
+                        }
+                        println!("Blobs rotated to key {}: {}, already current: {}, unencrypted: {}, failed: {}",
+                            keys.active_key(), report.rotated.len(), report.current, report.unencrypted, report.failures.len());
+                    },
+                    Err(e) => error!("Error while rotating the keys of container {}: {}", &container_name, e)
+                }
+            },
+        },
 
         Command::Config { config_file } => {
             info!("Configurator selected");
@@ -0,0 +1,163 @@
+/*
+    This file contains the compression of blobs written to Azure Blob Storage
+    The compression is stored as Content-Encoding, so that it can be reversed on read
+    Encrypted blobs store it in the metadata instead (their content is no longer a valid gzip or zstd stream)
+*/
+
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use flate2::Compression as GzipLevel;
+use flate2::read::MultiGzDecoder;
+use flate2::write::GzEncoder;
+
+// Metadata entry of the compression of an encrypted blob (e.g. gzip)
+pub const COMPRESSION_METADATA: &str = "compression";
+
+/// Compression of a blob
+/// - None: The content is uploaded as is
+/// - Gzip: Content-Encoding gzip, extension .gz
+/// - Zstd: Content-Encoding zstd, extension .zst
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+
+    /// Returns the compression matching the Content-Encoding of a blob
+    /// - Unknown encodings are treated as not compressed
+    pub fn from_content_encoding(content_encoding: Option<&str>) -> Self {
+        match content_encoding.map(|encoding| encoding.trim().to_ascii_lowercase()).as_deref() {
+            Some("gzip") => Compression::Gzip,
+            Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+
+    /// Returns the compression of a blob
+    /// - Encrypted blobs have the compression in the metadata (see COMPRESSION_METADATA),
+    ///   all other blobs have it as Content-Encoding
+    pub fn from_properties(content_encoding: Option<&str>, metadata: &BTreeMap<String, String>) -> Self {
+        match metadata.get(COMPRESSION_METADATA) {
+            Some(compression) => Compression::from_content_encoding(Some(compression)),
+            None => Compression::from_content_encoding(content_encoding),
+        }
+    }
+
+    /// Returns the Content-Encoding header value
+    pub fn content_encoding(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gzip"),
+            Compression::Zstd => Some("zstd"),
+        }
+    }
+
+    /// Returns the file extension (without dot)
+    pub fn extension(&self) -> Option<&'static str> {
+        match self {
+            Compression::None => None,
+            Compression::Gzip => Some("gz"),
+            Compression::Zstd => Some("zst"),
+        }
+    }
+
+    /// Appends the extension to the blob name (if not already present)
+    pub fn blob_name(&self, blob_name: &str) -> String {
+        match self.extension() {
+            Some(extension) if !blob_name.ends_with(&format!(".{}", extension)) => format!("{}.{}", blob_name, extension),
+            _ => blob_name.to_string(),
+        }
+    }
+
+    /// Removes the extension from the blob name (e.g. for the decompressed local file)
+    pub fn file_name(&self, blob_name: &str) -> String {
+        match self.extension() {
+            Some(extension) => blob_name.strip_suffix(&format!(".{}", extension)).unwrap_or(blob_name).to_string(),
+            None => blob_name.to_string(),
+        }
+    }
+
+    /// Compresses the data
+    pub fn compress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), GzipLevel::default());
+                encoder.write_all(data)?;
+                encoder.finish()
+            },
+            Compression::Zstd => zstd::encode_all(data, 0),
+        }
+    }
+
+    /// Decompresses the data
+    /// - Concatenated gzip members and zstd frames (e.g. of an append blob) are decompressed as a whole
+    pub fn decompress(&self, data: &[u8]) -> std::io::Result<Vec<u8>> {
+        match self {
+            Compression::None => Ok(data.to_vec()),
+            Compression::Gzip => {
+                let mut decompressed = Vec::new();
+                MultiGzDecoder::new(data).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            },
+            Compression::Zstd => zstd::decode_all(data),
+        }
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEST_DATA : &str = "{ \"test\": \"test\" }\n{ \"test\": \"test\" }\n";
+
+    #[test]
+    fn test_roundtrip() {
+        for compression in [Compression::None, Compression::Gzip, Compression::Zstd] {
+            let compressed = compression.compress(TEST_DATA.as_bytes()).unwrap();
+            let decompressed = compression.decompress(&compressed).unwrap();
+            assert_eq!(decompressed, TEST_DATA.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_concatenated() {
+        for compression in [Compression::Gzip, Compression::Zstd] {
+            let mut compressed = compression.compress(b"{\"base\": \"EUR\"}\n").unwrap();
+            compressed.extend(compression.compress(b"{\"base\": \"USD\"}\n").unwrap());
+            assert_eq!(compression.decompress(&compressed).unwrap(), b"{\"base\": \"EUR\"}\n{\"base\": \"USD\"}\n");
+        }
+    }
+
+    #[test]
+    fn test_from_content_encoding() {
+        assert_eq!(Compression::from_content_encoding(Some("gzip")), Compression::Gzip);
+        assert_eq!(Compression::from_content_encoding(Some("ZSTD")), Compression::Zstd);
+        assert_eq!(Compression::from_content_encoding(Some("br")), Compression::None);
+        assert_eq!(Compression::from_content_encoding(None), Compression::None);
+    }
+
+    #[test]
+    fn test_from_properties() {
+        let metadata = BTreeMap::from([(COMPRESSION_METADATA.to_string(), "zstd".to_string())]);
+        assert_eq!(Compression::from_properties(None, &metadata), Compression::Zstd);
+        assert_eq!(Compression::from_properties(Some("gzip"), &BTreeMap::new()), Compression::Gzip);
+    }
+
+    #[test]
+    fn test_blob_name() {
+        assert_eq!(Compression::Gzip.blob_name("data/test.ndjson"), "data/test.ndjson.gz");
+        assert_eq!(Compression::Zstd.blob_name("test.json.zst"), "test.json.zst");
+        assert_eq!(Compression::None.blob_name("test.json"), "test.json");
+        assert_eq!(Compression::Gzip.file_name("data/test.ndjson.gz"), "data/test.ndjson");
+        assert_eq!(Compression::Zstd.file_name("test.json"), "test.json");
+    }
+}
@@ -0,0 +1,97 @@
+/*
+    This file contains the service principal (client credentials) authentication for Azure Blob Storage
+    The access token is requested from Azure AD and cached until shortly before it expires
+*/
+
+use async_trait::async_trait;
+use azure_core::auth::{AccessToken, TokenCredential, TokenResponse};
+use azure_core::error::ErrorKind;
+use log::info;
+use serde::Deserialize;
+use time::{Duration, OffsetDateTime};
+use tokio::sync::Mutex;
+
+// Tokens are renewed if they expire within this margin
+const EXPIRY_MARGIN: Duration = Duration::minutes(2);
+
+/// Token response of the Azure AD token endpoint
+#[derive(Deserialize)]
+struct TokenEndpointResponse {
+    access_token: String,
+    expires_in: i64,
+}
+
+/// Credential of an Azure AD app registration (tenant id, client id and client secret)
+/// - The app needs a data role on the storage account (e.g. Storage Blob Data Contributor)
+pub struct ClientSecretCredential {
+    tenant_id: String,
+    client_id: String,
+    client_secret: String,
+    client: reqwest::Client,
+    token: Mutex<Option<TokenResponse>>,
+}
+
+impl ClientSecretCredential {
+
+    pub fn new(tenant_id: &str, client_id: &str, client_secret: &str) -> Self {
+        ClientSecretCredential {
+            tenant_id: tenant_id.to_string(),
+            client_id: client_id.to_string(),
+            client_secret: client_secret.to_string(),
+            client: reqwest::Client::new(),
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Requests a new token for the resource (e.g. https://storage.azure.com/)
+    async fn request_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let url = format!("https://login.microsoftonline.com/{}/oauth2/v2.0/token", self.tenant_id);
+        let scope = format!("{}/.default", resource.trim_end_matches('/'));
+
+        let response = self.client
+            .post(&url)
+            .form(&[
+                ("grant_type", "client_credentials"),
+                ("client_id", self.client_id.as_str()),
+                ("client_secret", self.client_secret.as_str()),
+                ("scope", scope.as_str()),
+            ])
+            .send()
+            .await
+            .map_err(|e| azure_core::Error::new(ErrorKind::Credential, e))?;
+
+        if !response.status().is_success() {
+            let status = response.status();
+            let body = response.text().await.unwrap_or_default();
+            return Err(azure_core::Error::message(ErrorKind::Credential,
+                format!("Token request for client {} failed ({}): {}", self.client_id, status, body)));
+        }
+
+        let token = response.json::<TokenEndpointResponse>().await
+            .map_err(|e| azure_core::Error::new(ErrorKind::Credential, e))?;
+        info!("Retrieved access token for client {}", self.client_id);
+
+        Ok(TokenResponse::new(
+            AccessToken::new(token.access_token),
+            OffsetDateTime::now_utc() + Duration::seconds(token.expires_in),
+        ))
+    }
+}
+
+#[async_trait]
+impl TokenCredential for ClientSecretCredential {
+
+    async fn get_token(&self, resource: &str) -> azure_core::Result<TokenResponse> {
+        let mut cached = self.token.lock().await;
+
+        if let Some(token) = cached.as_ref() {
+            if token.expires_on - EXPIRY_MARGIN > OffsetDateTime::now_utc() {
+                return Ok(token.clone());
+            }
+        }
+
+        let token = self.request_token(resource).await?;
+        *cached = Some(token.clone());
+        Ok(token)
+    }
+}
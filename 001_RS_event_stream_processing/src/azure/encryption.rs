@@ -0,0 +1,296 @@
+/*
+    This file contains the client-side (envelope) encryption of blobs
+    Every blob is encrypted with its own data key (AES-256-GCM), the data key is wrapped by a
+    key-encryption key of encryption_config.json and stored with the key id and the nonce in the blob metadata
+*/
+
+use std::collections::BTreeMap;
+use std::fmt::{Debug, Formatter};
+use std::sync::OnceLock;
+
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::info;
+
+use crate::config::EncryptionConfig;
+use crate::errors::EncryptionError;
+use crate::get_encryption_details;
+
+// Metadata entries of an encrypted blob
+pub const ALGORITHM_METADATA: &str = "encryption_algorithm";
+pub const KEY_ID_METADATA: &str = "encryption_key_id";
+pub const NONCE_METADATA: &str = "encryption_nonce";
+pub const DATA_KEY_METADATA: &str = "encryption_data_key";
+
+// Value of the algorithm entry
+const ALGORITHM: &str = "AES-256-GCM";
+
+// Size of an AES-GCM nonce in bytes
+const NONCE_SIZE: usize = 12;
+
+/// Key-encryption keys of encryption_config.json
+/// - active_key: Id of the key that wraps the data keys of new blobs
+/// - The keys are never printed (Debug only shows the ids)
+#[derive(Clone)]
+pub struct KeyRing {
+    active_key: String,
+    keys: BTreeMap<String, Key<Aes256Gcm>>,
+}
+
+impl Debug for KeyRing {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("KeyRing")
+            .field("active_key", &self.active_key)
+            .field("keys", &self.keys.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+impl KeyRing {
+
+    /// Loads the keys from encryption_config.json
+    pub fn load() -> Result<Self, EncryptionError> {
+        KeyRing::from_config(&get_encryption_details()?)
+    }
+
+    /// Decodes the keys of the config
+    /// - Every key must be a base64 encoded 256-bit key
+    /// - The active key must be one of the keys
+    pub fn from_config(config: &EncryptionConfig) -> Result<Self, EncryptionError> {
+        let mut keys = BTreeMap::new();
+        for (key_id, key) in &config.keys {
+            let key = STANDARD.decode(key.trim())
+                .map_err(|e| EncryptionError::MalformedKey(key_id.clone(), e.to_string()))?;
+            if key.len() != 32 {
+                return Err(EncryptionError::MalformedKey(key_id.clone(), format!("expected 32 bytes, got {}", key.len())));
+            }
+            keys.insert(key_id.clone(), *Key::<Aes256Gcm>::from_slice(&key));
+        }
+
+        if !keys.contains_key(&config.active_key) {
+            return Err(EncryptionError::UnknownKey(config.active_key.clone()));
+        }
+
+        Ok(KeyRing { active_key: config.active_key.clone(), keys })
+    }
+
+    /// Returns the id of the key that wraps the data keys of new blobs
+    pub fn active_key(&self) -> &str {
+        &self.active_key
+    }
+
+    /// Returns the cipher of a key-encryption key
+    fn cipher(&self, key_id: &str) -> Result<Aes256Gcm, EncryptionError> {
+        self.keys.get(key_id)
+            .map(Aes256Gcm::new)
+            .ok_or_else(|| EncryptionError::UnknownKey(key_id.to_string()))
+    }
+
+    /// Wraps a data key with the active key, the key id is authenticated with the data key
+    /// - Returns the nonce followed by the wrapped key (base64)
+    fn wrap(&self, data_key: &Key<Aes256Gcm>) -> Result<String, EncryptionError> {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let wrapped = self.cipher(&self.active_key)?
+            .encrypt(&nonce, Payload { msg: data_key, aad: self.active_key.as_bytes() })
+            .map_err(|_| EncryptionError::EncryptionFailed("data key can not be wrapped".to_string()))?;
+
+        Ok(STANDARD.encode([nonce.as_slice(), &wrapped].concat()))
+    }
+
+    /// Unwraps the data key of a blob with the key it was wrapped with
+    fn unwrap(&self, blob_name: &str, key_id: &str, wrapped: &str) -> Result<Key<Aes256Gcm>, EncryptionError> {
+        let wrapped = STANDARD.decode(wrapped)
+            .map_err(|_| EncryptionError::MalformedMetadata(DATA_KEY_METADATA))?;
+        if wrapped.len() <= NONCE_SIZE {
+            return Err(EncryptionError::MalformedMetadata(DATA_KEY_METADATA));
+        }
+
+        let (nonce, wrapped) = wrapped.split_at(NONCE_SIZE);
+        let data_key = self.cipher(key_id)?
+            .decrypt(Nonce::from_slice(nonce), Payload { msg: wrapped, aad: key_id.as_bytes() })
+            .map_err(|_| EncryptionError::DecryptionFailed(blob_name.to_string()))?;
+
+        Ok(*Key::<Aes256Gcm>::from_slice(&data_key))
+    }
+}
+
+/// Keys to decrypt the blobs read by a command
+/// - The keys are loaded from encryption_config.json with the first encrypted blob and then reused,
+///   so that a command reading unencrypted blobs does not need the config
+/// - A failed load is retried with the next encrypted blob
+#[derive(Debug, Default)]
+pub struct DecryptionKeys {
+    keys: OnceLock<KeyRing>,
+}
+
+impl DecryptionKeys {
+
+    /// Creates the keys from an already loaded key ring
+    pub fn new(keys: KeyRing) -> Self {
+        DecryptionKeys { keys: OnceLock::from(keys) }
+    }
+
+    /// Returns the key ring, loads it on the first call
+    fn get(&self) -> Result<&KeyRing, EncryptionError> {
+        if let Some(keys) = self.keys.get() {
+            return Ok(keys);
+        }
+        let keys = KeyRing::load()?;
+        Ok(self.keys.get_or_init(|| keys))
+    }
+}
+
+/// Returns true if the metadata belongs to an encrypted blob
+pub fn is_encrypted(metadata: &BTreeMap<String, String>) -> bool {
+    metadata.contains_key(ALGORITHM_METADATA)
+}
+
+/// Encrypts the content with a new data key
+/// - Returns the encrypted content and the metadata needed to decrypt it (algorithm, key id, nonce, wrapped data key)
+pub fn encrypt(content: &[u8], keys: &KeyRing) -> Result<(Vec<u8>, BTreeMap<String, String>), EncryptionError> {
+    let data_key = Aes256Gcm::generate_key(&mut OsRng);
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+
+    let encrypted = Aes256Gcm::new(&data_key).encrypt(&nonce, content)
+        .map_err(|_| EncryptionError::EncryptionFailed(format!("{} bytes exceed the limit of AES-GCM", content.len())))?;
+
+    let metadata = BTreeMap::from([
+        (ALGORITHM_METADATA.to_string(), ALGORITHM.to_string()),
+        (KEY_ID_METADATA.to_string(), keys.active_key.clone()),
+        (NONCE_METADATA.to_string(), STANDARD.encode(nonce)),
+        (DATA_KEY_METADATA.to_string(), keys.wrap(&data_key)?),
+    ]);
+
+    info!("Encrypted {} bytes with a data key wrapped by key {}", content.len(), keys.active_key);
+    Ok((encrypted, metadata))
+}
+
+/// Decrypts the content of a blob with the data key stored in its metadata
+/// - Returns EncryptionError::DecryptionFailed if the key does not match or the content was modified
+pub fn decrypt(blob_name: &str, content: &[u8], metadata: &BTreeMap<String, String>, keys: &KeyRing) -> Result<Vec<u8>, EncryptionError> {
+    if metadata.get(ALGORITHM_METADATA).map(String::as_str) != Some(ALGORITHM) {
+        return Err(EncryptionError::MalformedMetadata(ALGORITHM_METADATA));
+    }
+
+    let key_id = entry(metadata, KEY_ID_METADATA)?;
+    let nonce = STANDARD.decode(entry(metadata, NONCE_METADATA)?)
+        .ok()
+        .filter(|nonce| nonce.len() == NONCE_SIZE)
+        .ok_or(EncryptionError::MalformedMetadata(NONCE_METADATA))?;
+    let data_key = keys.unwrap(blob_name, key_id, entry(metadata, DATA_KEY_METADATA)?)?;
+
+    let content = Aes256Gcm::new(&data_key).decrypt(Nonce::from_slice(&nonce), content)
+        .map_err(|_| EncryptionError::DecryptionFailed(blob_name.to_string()))?;
+
+    info!("Decrypted blob {} with key {}", blob_name, key_id);
+    Ok(content)
+}
+
+/// Decrypts the content of a blob if it is encrypted
+/// - Unencrypted blobs are returned unchanged
+/// - The keys are only loaded (once, see DecryptionKeys) for encrypted blobs
+pub fn decrypt_blob(blob_name: &str, content: Vec<u8>, metadata: &BTreeMap<String, String>, keys: &DecryptionKeys) -> Result<Vec<u8>, EncryptionError> {
+    if !is_encrypted(metadata) {
+        return Ok(content);
+    }
+    decrypt(blob_name, &content, metadata, keys.get()?)
+}
+
+/// Re-wraps the data key of an encrypted blob with the active key (key rotation)
+/// - The content stays untouched, only the key id and the wrapped data key change
+/// - Returns the new metadata, or None if the data key is already wrapped by the active key
+pub fn rewrap(blob_name: &str, metadata: &BTreeMap<String, String>, keys: &KeyRing) -> Result<Option<BTreeMap<String, String>>, EncryptionError> {
+    let key_id = entry(metadata, KEY_ID_METADATA)?;
+    if key_id == keys.active_key {
+        return Ok(None);
+    }
+
+    let data_key = keys.unwrap(blob_name, key_id, entry(metadata, DATA_KEY_METADATA)?)?;
+
+    let mut metadata = metadata.clone();
+    metadata.insert(KEY_ID_METADATA.to_string(), keys.active_key.clone());
+    metadata.insert(DATA_KEY_METADATA.to_string(), keys.wrap(&data_key)?);
+    Ok(Some(metadata))
+}
+
+/// Returns an entry of the encryption metadata
+fn entry<'a>(metadata: &'a BTreeMap<String, String>, name: &'static str) -> Result<&'a str, EncryptionError> {
+    metadata.get(name)
+        .map(String::as_str)
+        .ok_or(EncryptionError::MalformedMetadata(name))
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_ring(active_key: &str) -> KeyRing {
+        KeyRing::from_config(&EncryptionConfig {
+            active_key: active_key.to_string(),
+            keys: BTreeMap::from([
+                ("kek-1".to_string(), STANDARD.encode([1u8; 32])),
+                ("kek-2".to_string(), STANDARD.encode([2u8; 32])),
+            ]),
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let keys = key_ring("kek-1");
+        let (encrypted, metadata) = encrypt(b"{\"base\": \"EUR\"}", &keys).unwrap();
+
+        assert_ne!(encrypted, b"{\"base\": \"EUR\"}");
+        assert!(is_encrypted(&metadata));
+        assert_eq!(metadata[KEY_ID_METADATA], "kek-1");
+        assert_eq!(decrypt("rates.json", &encrypted, &metadata, &keys).unwrap(), b"{\"base\": \"EUR\"}");
+
+        // Modified content is rejected
+        let mut modified = encrypted.clone();
+        modified[0] ^= 1;
+        assert!(matches!(decrypt("rates.json", &modified, &metadata, &keys), Err(EncryptionError::DecryptionFailed(_))));
+    }
+
+    #[test]
+    fn test_decrypt_blob() {
+        let keys = DecryptionKeys::new(key_ring("kek-1"));
+        let (encrypted, metadata) = encrypt(b"{\"base\": \"EUR\"}", &key_ring("kek-1")).unwrap();
+
+        assert_eq!(decrypt_blob("rates.json", encrypted, &metadata, &keys).unwrap(), b"{\"base\": \"EUR\"}");
+        // Unencrypted blobs need no keys
+        assert_eq!(decrypt_blob("rates.json", b"{}".to_vec(), &BTreeMap::new(), &DecryptionKeys::default()).unwrap(), b"{}");
+    }
+
+    #[test]
+    fn test_rewrap() {
+        let (encrypted, metadata) = encrypt(b"{\"base\": \"EUR\"}", &key_ring("kek-1")).unwrap();
+
+        let keys = key_ring("kek-2");
+        let rotated = rewrap("rates.json", &metadata, &keys).unwrap().unwrap();
+        assert_eq!(rotated[KEY_ID_METADATA], "kek-2");
+        assert_eq!(rotated[NONCE_METADATA], metadata[NONCE_METADATA]);
+        assert_eq!(decrypt("rates.json", &encrypted, &rotated, &keys).unwrap(), b"{\"base\": \"EUR\"}");
+        assert!(rewrap("rates.json", &rotated, &keys).unwrap().is_none());
+
+        // A data key can not be moved to another key id without the key
+        let mut forged = rotated.clone();
+        forged.insert(KEY_ID_METADATA.to_string(), "kek-1".to_string());
+        assert!(decrypt("rates.json", &encrypted, &forged, &keys).is_err());
+    }
+
+    #[test]
+    fn test_malformed_keys() {
+        let config = |key: &str, active_key: &str| EncryptionConfig {
+            active_key: active_key.to_string(),
+            keys: BTreeMap::from([("kek-1".to_string(), key.to_string())]),
+        };
+
+        assert!(matches!(KeyRing::from_config(&config("c2hvcnQ=", "kek-1")), Err(EncryptionError::MalformedKey(..))));
+        assert!(matches!(KeyRing::from_config(&config(&STANDARD.encode([1u8; 32]), "kek-2")), Err(EncryptionError::UnknownKey(_))));
+        assert!(!format!("{:?}", key_ring("kek-1")).contains(&STANDARD.encode([1u8; 32])));
+    }
+}
@@ -2,41 +2,315 @@
     This file contains helper functions for Azure Blob Storage
 */
 
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+use crate::azure::credential::ClientSecretCredential;
+use crate::azure::integrity::{encode_md5, MD5_METADATA};
+use crate::config::{AzureAuth, AzureConfig};
+use crate::errors::CredentialError;
 use crate::get_azure_details;
-use azure_storage::StorageCredentials;
-use azure_storage_blobs::prelude::{ClientBuilder, PublicAccess};
+use azure_core::error::ErrorKind;
+use azure_core::headers::{HeaderName, Headers, IF_NONE_MATCH};
+use azure_core::request_options::Metadata;
+use azure_core::{ClientOptions, Context, CustomHeaders, Policy, PolicyResult, Request, StatusCode};
+use azure_storage::{CloudLocation, ConnectionString, StorageCredentials};
+use azure_storage_blobs::prelude::{ClientBuilder, PublicAccess, Tags};
+use base64::Engine;
 
 use log::{info, warn, error};
 use uuid::Uuid;
 
+// The service principal credential is shared while the config names the same principal, so that its token is cached;
+// a rotated client secret in azure_config.json gets a new credential
+static SERVICE_PRINCIPAL: Mutex<Option<(ServicePrincipal, Arc<ClientSecretCredential>)>> = Mutex::new(None);
+
+// Tenant, client id and client secret of a service principal
+type ServicePrincipal = (String, String, String);
+
 /// Get client for Azure Blob Storage connection
-/// - Establishes a connection to Azure Blob Storage via azure_key.json
-pub fn get_az_client() -> ClientBuilder {
+/// - Establishes a connection to Azure Blob Storage via azure_config.json
+/// - Targets the public cloud, a custom endpoint or the emulator (see get_cloud_location)
+/// - Returns a Credential error (see CredentialError) if the config can not be read or is invalid
+pub fn get_az_client() -> Result<ClientBuilder, CredentialError> {
     // Retrieve mandatory details from json file
-    let az_details = get_azure_details().unwrap();
-
-    // Assign details to variables
-    let account = az_details.storage_account_name;
-    let key = az_details.storage_account_key;
+    let az_details = get_azure_details()?;
 
     // Create a blob client
-    let storage_credentials = StorageCredentials::Key(account.clone(), key);
-    let client = ClientBuilder::new(account, storage_credentials);
+    let cloud_location = get_cloud_location(&az_details)?;
+    let policies: Vec<Arc<dyn Policy>> = vec![Arc::new(CommittedBlocksPolicy)];
+    Ok(ClientBuilder::with_location(cloud_location)
+        .client_options(ClientOptions::default().per_call_policies(policies)))
+}
+
+/// Wraps the credential error, so that it can be told apart from other Azure errors
+impl From<CredentialError> for azure_core::Error {
+    fn from(e: CredentialError) -> Self {
+        error!("{}", e);
+        azure_core::Error::new(ErrorKind::Credential, e)
+    }
+}
+
+// Response header with the number of committed blocks of an append blob (not parsed by azure_storage_blobs)
+const COMMITTED_BLOCK_COUNT: HeaderName = HeaderName::from_static("x-ms-blob-committed-block-count");
+
+/// Committed block count of an append blob, recorded from the response of a request
+/// - Added to the request context (see committed_blocks_context) and filled by CommittedBlocksPolicy
+#[derive(Debug, Default)]
+pub struct CommittedBlocks(Mutex<Option<u64>>);
+
+impl CommittedBlocks {
+
+    /// Returns the block count of the response, None if it had none (e.g. no append blob)
+    pub fn get(&self) -> Option<u64> {
+        *self.0.lock().unwrap()
+    }
+}
+
+/// Returns the request context that records the committed block count of the response
+pub fn committed_blocks_context(blocks: &Arc<CommittedBlocks>) -> Context {
+    let mut context = Context::new();
+    context.insert(blocks.clone());
+    context
+}
+
+/// Pipeline policy that records the committed block count of a response (see CommittedBlocks)
+#[derive(Debug)]
+struct CommittedBlocksPolicy;
+
+#[async_trait::async_trait]
+impl Policy for CommittedBlocksPolicy {
+    async fn send(&self, ctx: &Context, request: &mut Request, next: &[Arc<dyn Policy>]) -> PolicyResult {
+        let response = next[0].send(ctx, request, &next[1..]).await?;
+        if let Some(blocks) = ctx.get::<Arc<CommittedBlocks>>() {
+            *blocks.0.lock().unwrap() = response.headers().get_optional_str(&COMMITTED_BLOCK_COUNT)
+                .and_then(|count| count.parse().ok());
+        }
+        Ok(response)
+    }
+}
+
+/// Get the location (endpoint and credentials) of the storage account
+/// - The credentials are selected by the auth field (see AzureAuth)
+/// - connection_string: UseDevelopmentStorage=true targets the local emulator (Azurite),
+///   otherwise the BlobEndpoint (if any) and the key or SAS of the connection string are used
+/// - blob_endpoint: Custom endpoint used with the account credentials
+/// - Without both, the account in the public Azure cloud is used
+/// - Returns a Credential error (see CredentialError) if a credential is missing or malformed
+pub fn get_cloud_location(config: &AzureConfig) -> Result<CloudLocation, CredentialError> {
+
+    let auth = config.auth.unwrap_or(match config.connection_string {
+        Some(_) => AzureAuth::ConnectionString,
+        None => AzureAuth::Key,
+    });
+
+    let credentials = match auth {
+        AzureAuth::ConnectionString => return connection_string_location(config),
+        AzureAuth::Key => key_credentials(config),
+        AzureAuth::Sas => sas_credentials(config),
+        AzureAuth::ServicePrincipal => service_principal_credentials(config),
+    }?;
+
+    match &config.blob_endpoint {
+        Some(endpoint) => Ok(CloudLocation::Custom { uri: endpoint.trim_end_matches('/').to_string(), credentials }),
+        None => Ok(CloudLocation::Public { account: required(&config.storage_account_name, "storage_account_name")?.to_string(), credentials }),
+    }
+}
+
+/// Returns the value of a mandatory field, empty values count as missing
+fn required<'a>(value: &'a str, field: &'static str) -> Result<&'a str, CredentialError> {
+    match value.trim() {
+        "" => Err(CredentialError::MissingField(field)),
+        value => Ok(value),
+    }
+}
+
+/// Location of the storage account described by the connection string
+fn connection_string_location(config: &AzureConfig) -> Result<CloudLocation, CredentialError> {
+
+    let connection_string = required(config.connection_string.as_deref().unwrap_or_default(), "connection_string")?;
+    let connection_string = ConnectionString::new(connection_string)
+        .map_err(|e| CredentialError::MalformedField("connection_string", e.to_string()))?;
+
+    if connection_string.use_development_storage == Some(true) {
+        let (address, port) = emulator_address(connection_string.blob_endpoint.or(connection_string.development_storage_proxy_uri))?;
+        info!("Using the Azure Storage emulator at {}:{}", address, port);
+        return Ok(CloudLocation::Emulator { address, port });
+    }
+
+    let credentials = connection_string.storage_credentials()
+        .map_err(|_| CredentialError::MalformedField("connection_string", "contains neither AccountName/AccountKey nor SharedAccessSignature".to_string()))?;
+
+    match (connection_string.blob_endpoint, connection_string.account_name) {
+        (Some(endpoint), _) => Ok(CloudLocation::Custom { uri: endpoint.trim_end_matches('/').to_string(), credentials }),
+        (None, Some(account)) => Ok(CloudLocation::Public { account: account.to_string(), credentials }),
+        (None, None) => Err(CredentialError::MalformedField("connection_string", "contains neither BlobEndpoint nor AccountName".to_string())),
+    }
+}
+
+/// Host and port of the emulator, taken from the BlobEndpoint (or DevelopmentStorageProxyUri) of the connection string
+/// - Without an endpoint the default address of Azurite (127.0.0.1:10000) is used
+fn emulator_address(endpoint: Option<&str>) -> Result<(String, u16), CredentialError> {
+
+    let endpoint = match endpoint {
+        Some(endpoint) => endpoint,
+        None => return Ok(("127.0.0.1".to_string(), 10000)),
+    };
+    let url = azure_core::Url::parse(endpoint)
+        .map_err(|e| CredentialError::MalformedField("connection_string", format!("invalid emulator endpoint {} ({})", endpoint, e)))?;
+    match url.host_str() {
+        Some(host) => Ok((host.to_string(), url.port().unwrap_or(10000))),
+        None => Err(CredentialError::MalformedField("connection_string", format!("the emulator endpoint {} has no host", endpoint))),
+    }
+}
+
+/// Shared account key
+fn key_credentials(config: &AzureConfig) -> Result<StorageCredentials, CredentialError> {
+
+    let account = required(&config.storage_account_name, "storage_account_name")?;
+    let key = required(&config.storage_account_key, "storage_account_key")?;
+
+    // The key is used to sign every request, an invalid key would only fail on the first request
+    base64::engine::general_purpose::STANDARD.decode(key)
+        .map_err(|e| CredentialError::MalformedField("storage_account_key", format!("not valid base64 ({})", e)))?;
+
+    Ok(StorageCredentials::Key(account.to_string(), key.to_string()))
+}
+
+/// SAS token, scoped to the account or a single container
+fn sas_credentials(config: &AzureConfig) -> Result<StorageCredentials, CredentialError> {
+
+    let token = required(config.sas_token.as_deref().unwrap_or_default(), "sas_token")?;
+    if !token.contains("sig=") {
+        return Err(CredentialError::MalformedField("sas_token", "the signature (sig) is missing".to_string()));
+    }
+
+    StorageCredentials::sas_token(token)
+        .map_err(|e| CredentialError::MalformedField("sas_token", e.to_string()))
+}
+
+/// Client credentials of an Azure AD app (service principal)
+fn service_principal_credentials(config: &AzureConfig) -> Result<StorageCredentials, CredentialError> {
+
+    let tenant_id = required(config.tenant_id.as_deref().unwrap_or_default(), "tenant_id")?;
+    let client_id = required(config.client_id.as_deref().unwrap_or_default(), "client_id")?;
+    let client_secret = required(config.client_secret.as_deref().unwrap_or_default(), "client_secret")?;
+
+    Uuid::parse_str(client_id)
+        .map_err(|e| CredentialError::MalformedField("client_id", format!("not a GUID ({})", e)))?;
+
+    let principal = (tenant_id.to_string(), client_id.to_string(), client_secret.to_string());
+    let mut shared = SERVICE_PRINCIPAL.lock().unwrap();
+    let credential = match shared.as_ref() {
+        Some((current, credential)) if *current == principal => credential.clone(),
+        _ => {
+            let credential = Arc::new(ClientSecretCredential::new(tenant_id, client_id, client_secret));
+            *shared = Some((principal, credential.clone()));
+            credential
+        }
+    };
+    Ok(StorageCredentials::token_credential(credential))
+}
+
+/// Properties of a blob that is uploaded
+/// - content_type: MIME type of the (uncompressed) content
+/// - content_encoding: Compression of the content (e.g. gzip), if any
+/// - metadata: User defined name/value pairs stored with the blob
+/// - tags: Blob index tags (searchable across containers, e.g. by Data Factory)
+/// - if_not_exists: Only create the blob if it does not exist yet (If-None-Match: *)
+#[derive(Debug, Clone)]
+pub struct UploadProperties {
+    pub content_type: String,
+    pub content_encoding: Option<String>,
+    pub metadata: BTreeMap<String, String>,
+    pub tags: BTreeMap<String, String>,
+    pub if_not_exists: bool,
+}
+
+impl Default for UploadProperties {
+    fn default() -> Self {
+        UploadProperties {
+            content_type: "application/json".to_string(),
+            content_encoding: None,
+            metadata: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            if_not_exists: false,
+        }
+    }
+}
+
+impl UploadProperties {
+
+    /// Returns the metadata of the upload, including the MD5 hash of the content
+    pub fn metadata(&self, digest: &md5::Digest) -> Metadata {
+        let mut metadata = self.user_metadata();
+        metadata.insert(MD5_METADATA, encode_md5(digest));
+        metadata
+    }
+
+    /// Returns the metadata of the upload without an MD5 hash (e.g. for append blobs, whose content grows)
+    pub fn user_metadata(&self) -> Metadata {
+        let mut metadata = Metadata::new();
+        for (name, value) in &self.metadata {
+            metadata.insert(name.clone(), value.clone());
+        }
+        metadata
+    }
+
+    /// Returns the index tags of the upload (if any)
+    pub fn tags(&self) -> Option<Tags> {
+        if self.tags.is_empty() {
+            return None;
+        }
 
-    client
+        let mut tags = Tags::new();
+        tags.extend(self.tags.clone());
+        Some(tags)
+    }
+}
+
+/// Returns the request context of a conditional write
+/// - If-None-Match: * lets Azure reject the write if the blob exists, so concurrent writers never overwrite each other
+pub fn write_context(properties: &UploadProperties) -> Context {
+    let mut context = Context::new();
+    if properties.if_not_exists {
+        let mut headers = Headers::new();
+        headers.insert(IF_NONE_MATCH, "*");
+        context.insert(CustomHeaders::from(headers));
+    }
+    context
+}
+
+/// Returns true if a write was rejected because the blob exists (see write_context)
+pub fn is_conflict(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::HttpResponse { status: StatusCode::Conflict | StatusCode::PreconditionFailed, .. })
 }
 
 /// Create a container in Azure Blob
 /// - Establishes a connection to Azure Blob Storage via azure_key.json
-/// - Creates the container in Azure Blob Storage
-/// - Sets the container to public access
-/// - Returns the container name
-pub async fn create_azure_blob(container_name: &str, filename: &str, data: Vec<u8>) -> azure_core::Result<Uuid> {
+/// - Creates the blob with the given properties (content type, encoding, metadata and index tags)
+/// - Sends the MD5 hash of the data (verified by Azure) and records it in the metadata
+/// - Fails if the blob exists and if_not_exists is set (see is_conflict)
+/// - Returns the request id
+pub async fn create_azure_blob(container_name: &str, filename: &str, data: Vec<u8>, properties: &UploadProperties) -> azure_core::Result<Uuid> {
+
+    let blob_client = get_az_client()?.blob_client(container_name, filename);
 
-    let blob_client = get_az_client().blob_client(container_name, filename);
+    let digest = md5::compute(&data);
 
     // Create the blob
-    let blob = blob_client.put_block_blob(data).content_type("application/json").await;
+    let mut builder = blob_client.put_block_blob(data)
+        .content_type(properties.content_type.clone())
+        .hash(digest)
+        .metadata(properties.metadata(&digest))
+        .context(write_context(properties));
+    if let Some(content_encoding) = &properties.content_encoding {
+        builder = builder.content_encoding(content_encoding.clone());
+    }
+    if let Some(tags) = properties.tags() {
+        builder = builder.tags(tags);
+    }
+    let blob = builder.await;
     
     // Unwrap the result
     let blob = match blob {
@@ -44,6 +318,10 @@ pub async fn create_azure_blob(container_name: &str, filename: &str, data: Vec<u
             info!("Successfully created blob {}: {:?}", filename, blob.request_id);
             Ok(blob.request_id)
         }
+        Err(e) if properties.if_not_exists && is_conflict(&e) => {
+            warn!("Blob {} already exists: Not overwritten", filename);
+            Err(e)
+        }
         Err(e) => {
             error!("Error creating blob data: {}", e);
             Err(e)
@@ -53,12 +331,99 @@ pub async fn create_azure_blob(container_name: &str, filename: &str, data: Vec<u
     blob
 }
 
+/// Create an empty append blob in Azure Blob Storage
+/// - Establishes a connection to Azure Blob Storage via azure_key.json
+/// - Creates the blob with the given properties (content type, encoding, metadata and index tags)
+/// - An existing blob is kept (If-None-Match: *), so concurrent writers append to the same blob
+/// - Returns true if the blob was created
+pub async fn create_append_blob(container_name: &str, filename: &str, properties: &UploadProperties) -> azure_core::Result<bool> {
+
+    let blob_client = get_az_client()?.blob_client(container_name, filename);
+
+    let properties = UploadProperties { if_not_exists: true, ..properties.clone() };
+    let mut builder = blob_client.put_append_blob()
+        .content_type(properties.content_type.clone())
+        .metadata(properties.user_metadata())
+        .context(write_context(&properties));
+    if let Some(content_encoding) = &properties.content_encoding {
+        builder = builder.content_encoding(content_encoding.clone());
+    }
+    if let Some(tags) = properties.tags() {
+        builder = builder.tags(tags);
+    }
+
+    match builder.await {
+        Ok(_) => {
+            info!("Successfully created append blob {}", filename);
+            Ok(true)
+        }
+        Err(e) if is_conflict(&e) => Ok(false),
+        Err(e) => {
+            error!("Error creating append blob {}: {}", filename, e);
+            Err(e)
+        }
+    }
+}
+
+/// Append a block to an append blob in Azure Blob Storage
+/// - Establishes a connection to Azure Blob Storage via azure_key.json
+/// - Creates the append blob with the given properties if it does not exist and position is 0 (see create_append_blob)
+/// - Sends the MD5 hash of the block (verified by Azure)
+/// - position: Expected size of the blob, Azure rejects the block if the blob has another size (see is_append_position),
+///   so a block that is sent again by the retry policy is never appended twice
+/// - Fails with BlockCountExceedsLimit once the blob has 50,000 blocks (see is_block_limit), nothing is appended then
+pub async fn append_azure_blob(container_name: &str, filename: &str, block: Vec<u8>, properties: &UploadProperties, position: u64) -> azure_core::Result<()> {
+
+    let blob_client = get_az_client()?.blob_client(container_name, filename);
+
+    let digest = md5::compute(&block);
+    let size = block.len();
+
+    // Most appends go to an existing blob, so the blob is only created if the append fails
+    match blob_client.append_block(block.clone()).hash(digest).condition_append_position(position).await {
+        Ok(_) => {},
+        Err(e) if position == 0 && is_not_found(&e) => {
+            create_append_blob(container_name, filename, properties).await?;
+            blob_client.append_block(block).hash(digest).condition_append_position(position).await?;
+        }
+        Err(e) => return Err(e),
+    }
+
+    info!("Successfully appended {} bytes to blob {}", size, filename);
+    Ok(())
+}
+
+/// Returns true if an append was rejected because the append blob has reached its maximum number of blocks
+pub fn is_block_limit(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::HttpResponse { error_code: Some(code), .. } if code == "BlockCountExceedsLimit")
+}
+
+/// Returns true if an append was rejected because the blob does not have the expected size (see append_azure_blob)
+pub fn is_append_position(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::HttpResponse { error_code: Some(code), .. } if code == "AppendPositionConditionNotMet")
+}
+
+/// Returns true if a request failed because the blob (or its container) does not exist
+pub fn is_not_found(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::HttpResponse { status: StatusCode::NotFound, .. })
+}
+
+/// Returns true if a read pinned to an etag was rejected because the blob changed (see AzureBlobStore::get)
+pub fn is_changed(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::HttpResponse { status: StatusCode::PreconditionFailed, .. })
+}
+
+/// Returns true if an append was rejected because the blob is not an append blob
+pub fn is_invalid_blob_type(e: &azure_core::Error) -> bool {
+    matches!(e.kind(), ErrorKind::HttpResponse { error_code: Some(code), .. } if code == "InvalidBlobType")
+}
+
 /// Delete a file from Azure Blob Storage
 /// - Establishes a connection to Azure Blob Storage via azure_key.json
 /// - Deletes the file from Azure Blob Storage
 pub async fn delete_azure_blob(container_name: &str, filename: &str) -> azure_core::Result<()> {
 
-    let blob_client = get_az_client().blob_client(container_name, filename);
+    let blob_client = get_az_client()?.blob_client(container_name, filename);
 
     // Delete the blob
     let blob = blob_client.delete().await;
@@ -83,7 +448,7 @@ pub async fn delete_azure_blob(container_name: &str, filename: &str) -> azure_co
 /// - Creates the container in Azure Blob Storage
 pub async fn create_azure_container(container_name: &str) -> azure_core::Result<()> {
 
-    let blob_client = get_az_client().blob_client(container_name, "");
+    let blob_client = get_az_client()?.blob_client(container_name, "");
 
     // Create the container
     let container = blob_client.container_client().create().public_access(PublicAccess::None).await;
@@ -108,7 +473,7 @@ pub async fn create_azure_container(container_name: &str) -> azure_core::Result<
 /// - Deletes the container from Azure Blob Storage
 pub async fn delete_azure_container(container_name: &str) -> azure_core::Result<()> {
 
-    let blob_client = get_az_client().blob_client(container_name, "");
+    let blob_client = get_az_client()?.blob_client(container_name, "");
     // Delete the container
     let container = blob_client.container_client().delete().await;
     
@@ -134,6 +499,7 @@ pub async fn delete_azure_container(container_name: &str) -> azure_core::Result<
 #[cfg(test)]
 mod tests{
     use super::*;
+    use azure_storage::clients::ServiceType;
     const TEST_CONTAINER : &str = "test";
     const TEST_CONTAINER_TO_DELETE: &str = "testodelete";
     const TEST_FILE : &str = "test.json";
@@ -145,10 +511,93 @@ mod tests{
         // TODO: find a way to test this
     }
 
+    #[test]
+    fn test_cloud_location_emulator() {
+        let config = AzureConfig { connection_string: Some("UseDevelopmentStorage=true".to_string()), ..Default::default() };
+        let location = get_cloud_location(&config).unwrap();
+
+        assert!(matches!(location, CloudLocation::Emulator { port: 10000, .. }));
+        assert_eq!(location.url(ServiceType::Blob).unwrap().as_str(), "http://127.0.0.1:10000/devstoreaccount1");
+
+        let config = AzureConfig { connection_string: Some("UseDevelopmentStorage=true;BlobEndpoint=http://azurite:10010/devstoreaccount1".to_string()), ..Default::default() };
+        let location = get_cloud_location(&config).unwrap();
+        assert_eq!(location.url(ServiceType::Blob).unwrap().as_str(), "http://azurite:10010/devstoreaccount1");
+
+        let config = AzureConfig { connection_string: Some("UseDevelopmentStorage=true;BlobEndpoint=not a url".to_string()), ..Default::default() };
+        assert!(matches!(get_cloud_location(&config), Err(CredentialError::MalformedField("connection_string", _))));
+    }
+
+    #[test]
+    fn test_cloud_location_connection_string_endpoint() {
+        let connection_string = "DefaultEndpointsProtocol=http;AccountName=devstoreaccount1;AccountKey=a2V5;BlobEndpoint=http://azurite:10000/devstoreaccount1;";
+        let config = AzureConfig { connection_string: Some(connection_string.to_string()), ..Default::default() };
+        let location = get_cloud_location(&config).unwrap();
+
+        assert_eq!(location.url(ServiceType::Blob).unwrap().as_str(), "http://azurite:10000/devstoreaccount1");
+    }
+
+    #[test]
+    fn test_cloud_location_custom_endpoint() {
+        let config = AzureConfig {
+            storage_account_name: "account".to_string(),
+            storage_account_key: "a2V5".to_string(),
+            blob_endpoint: Some("https://blob.example.com/".to_string()),
+            ..Default::default()
+        };
+        let location = get_cloud_location(&config).unwrap();
+
+        assert_eq!(location.url(ServiceType::Blob).unwrap().as_str(), "https://blob.example.com/");
+    }
+
+    #[test]
+    fn test_cloud_location_sas() {
+        let config = AzureConfig {
+            auth: Some(AzureAuth::Sas),
+            storage_account_name: "account".to_string(),
+            sas_token: Some("?sv=2022-11-02&sr=c&sp=rcwl&sig=c2lnbmF0dXJl".to_string()),
+            ..Default::default()
+        };
+        let location = get_cloud_location(&config).unwrap();
+
+        assert!(matches!(location.credentials(), StorageCredentials::SASToken(_)));
+    }
+
+    #[test]
+    fn test_missing_credentials() {
+        let sas = AzureConfig { auth: Some(AzureAuth::Sas), storage_account_name: "account".to_string(), ..Default::default() };
+        let service_principal = AzureConfig { auth: Some(AzureAuth::ServicePrincipal), tenant_id: Some("tenant".to_string()), ..Default::default() };
+        let key = AzureConfig { storage_account_name: "account".to_string(), ..Default::default() };
+
+        assert!(get_cloud_location(&sas).unwrap_err().to_string().contains("sas_token"));
+        assert!(get_cloud_location(&service_principal).unwrap_err().to_string().contains("client_id"));
+        assert!(get_cloud_location(&key).unwrap_err().to_string().contains("storage_account_key"));
+    }
+
+    #[test]
+    fn test_malformed_credentials() {
+        let key = AzureConfig { storage_account_name: "account".to_string(), storage_account_key: "not base64!".to_string(), ..Default::default() };
+        let sas = AzureConfig { auth: Some(AzureAuth::Sas), storage_account_name: "account".to_string(), sas_token: Some("sv=2022-11-02".to_string()), ..Default::default() };
+        let connection_string = AzureConfig { connection_string: Some("AccountName=account".to_string()), ..Default::default() };
+
+        let error = get_cloud_location(&key).unwrap_err();
+        assert!(matches!(error, CredentialError::MalformedField("storage_account_key", _)));
+        assert_eq!(azure_core::Error::from(error).kind(), &azure_core::error::ErrorKind::Credential);
+        assert!(get_cloud_location(&sas).unwrap_err().to_string().contains("sig"));
+        assert!(get_cloud_location(&connection_string).unwrap_err().to_string().contains("connection_string"));
+    }
+
+    #[test]
+    fn test_cloud_location_public() {
+        let config = AzureConfig { storage_account_name: "account".to_string(), storage_account_key: "a2V5".to_string(), ..Default::default() };
+        let location = get_cloud_location(&config).unwrap();
+
+        assert_eq!(location.url(ServiceType::Blob).unwrap().as_str(), "https://account.blob.core.windows.net/");
+    }
+
     #[tokio::test]
     async fn test_create_azure_blob() {
         let _ = create_azure_container(TEST_CONTAINER).await;
-        let result = create_azure_blob(TEST_CONTAINER, TEST_FILE, TEST_DATA.as_bytes().to_vec()).await;
+        let result = create_azure_blob(TEST_CONTAINER, TEST_FILE, TEST_DATA.as_bytes().to_vec(), &UploadProperties::default()).await;
         assert!(result.is_ok());
     }
 
@@ -0,0 +1,87 @@
+/*
+    This file contains the content integrity checks of blobs
+    The MD5 hash of the stored bytes is sent with every upload and recorded in the blob metadata,
+    downloads are verified against it
+*/
+
+use azure_storage_blobs::blob::Blob;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::{info, error};
+
+use crate::errors::IntegrityError;
+
+// Metadata entry holding the MD5 hash (base64) of the stored bytes
+pub const MD5_METADATA: &str = "content_md5";
+
+/// Returns the MD5 hash of the content (base64, as used by the Content-MD5 header)
+pub fn content_md5(content: &[u8]) -> String {
+    encode_md5(&md5::compute(content))
+}
+
+/// Encodes an MD5 hash (base64, as used by the Content-MD5 header)
+pub fn encode_md5(digest: &md5::Digest) -> String {
+    STANDARD.encode(digest.0)
+}
+
+/// Returns the MD5 hash stored with an Azure blob
+/// - The metadata entry written by this application takes precedence
+/// - Blobs written by other tools are checked against their Content-MD5 property (if any)
+pub fn stored_md5(blob: &Blob) -> Option<String> {
+    blob.metadata.as_ref()
+        .and_then(|metadata| metadata.get(MD5_METADATA).cloned())
+        .or_else(|| blob.properties.content_md5.as_ref().map(|md5| STANDARD.encode(md5.as_slice())))
+}
+
+/// Verifies the downloaded (still compressed) bytes of a blob against the stored MD5 hash
+/// - Blobs without a stored hash can not be verified and are accepted
+/// - Returns IntegrityError::Mismatch if the hashes differ
+pub fn verify_md5(blob_name: &str, content: &[u8], expected: Option<&str>) -> Result<(), IntegrityError> {
+
+    let expected = match expected {
+        Some(expected) => expected,
+        None => {
+            info!("Blob {} has no stored MD5 hash: Skipping integrity check", blob_name);
+            return Ok(());
+        }
+    };
+
+    let actual = content_md5(content);
+    if actual != expected {
+        error!("MD5 mismatch for blob {}: expected {}, got {}", blob_name, expected, actual);
+        return Err(IntegrityError::Mismatch {
+            blob_name: blob_name.to_string(),
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+
+    info!("Verified MD5 of blob {}", blob_name);
+    Ok(())
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_content_md5() {
+        // MD5 of an empty input, as returned by Azure Blob Storage for empty blobs
+        assert_eq!(content_md5(b""), "1B2M2Y8AsgTpgAmY7PhCfg==");
+    }
+
+    #[test]
+    fn test_verify_md5() {
+        let expected = content_md5(b"{\"base\": \"EUR\"}");
+
+        assert!(verify_md5("rates.json", b"{\"base\": \"EUR\"}", Some(&expected)).is_ok());
+        assert!(verify_md5("rates.json", b"{\"base\": \"USD\"}", None).is_ok());
+        assert!(matches!(
+            verify_md5("rates.json", b"{\"base\": \"USD\"}", Some(&expected)),
+            Err(IntegrityError::Mismatch { .. })
+        ));
+    }
+}
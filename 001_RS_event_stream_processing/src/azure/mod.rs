@@ -1,3 +1,8 @@
+pub mod compression;
+pub mod credential;
+pub mod encryption;
 pub mod helper;
+pub mod integrity;
 pub mod reader;
+pub mod upload;
 pub mod writer;
\ No newline at end of file
@@ -1,49 +1,99 @@
 /*
     This file contains the function that pulls data from Azure Blob Storage
+    and the output (file or stdout) the pulled data is written to
 */
 
-use log::{info, warn, error};
 use std::io::Write;
+use std::path::PathBuf;
 
-use crate::azure::helper::get_az_client;
+use crate::azure::encryption::DecryptionKeys;
+use crate::storage::azure::AzureBlobStore;
+use crate::storage::read_blob;
 
 /// Pulls a file from Azure Blob Storage
 /// - Establishes a connection to Azure Blob Storage via azure_key.json
-/// - Pulls the file from Azure Blob Storage
-/// - Returns a Result with the status of the operation
-/// - Returns the file as a vector of bytes
-/// - Returns an error if the file is empty
-pub async fn pull_from_azure(container_name: &str, blob_name: &str) -> azure_core::Result<Vec<u8>> {
-
-    let blob_client = get_az_client().blob_client(container_name, blob_name);
-
-    // Get the blob
-    let blob = blob_client.get_content().await;
-    
-    // Unwrap the result
-    let blob = match blob {
-        Ok(content) => {
-
-            // If the blob is empty, return an error
-            if content.is_empty(){
-                let message = "Error retrieving blob data: Blob is empty!";
-                warn!("{}", message);
-                Err(azure_core::Error::message(azure_storage::ErrorKind::Other, message))
-            } else {
-                info!("Successfully retrieved blob: {:?}", blob_name);
-
-                // Create new file to store the retrieved blob content
-                let mut file = std::fs::File::create(blob_name).unwrap();
-                file.write(&content).unwrap();
-
-                Ok(content)
-            } 
+/// - Reads the file like every other sink (see storage::read_blob): MD5 check, decryption and decompression
+/// - Decrypts the file if it was encrypted by push_to_azure (keys loaded once per command, see DecryptionKeys)
+/// - Returns the file as a vector of bytes (an empty blob is a valid result)
+/// - The content is not written anywhere, see write_output
+pub async fn pull_from_azure(container_name: &str, blob_name: &str, keys: &DecryptionKeys) -> Result<Vec<u8>, anyhow::Error> {
+    read_blob(&AzureBlobStore::new(), container_name, blob_name, keys).await
+}
+
+/// Destination of the pulled data
+/// - Stdout: Selected by "-"
+/// - File: Any other path (relative to the current directory)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Stdout,
+    File(PathBuf),
+}
+
+impl Output {
+
+    /// Parses the output argument ("-" for stdout)
+    pub fn parse(output: &str) -> Self {
+        match output {
+            "-" => Output::Stdout,
+            path => Output::File(PathBuf::from(path)),
         }
-        Err(e) => {
-            error!("Error retrieving blob data: {}", e);
-            Err(e)
+    }
+}
+
+/// Writes the pulled data to the output
+/// - Creates the parent directories of the file
+/// - Refuses to overwrite an existing file unless force is set
+pub fn write_output(content: &[u8], output: &Output, force: bool) -> std::io::Result<()> {
+
+    match output {
+        Output::Stdout => {
+            let mut stdout = std::io::stdout().lock();
+            stdout.write_all(content)?;
+            stdout.flush()
+        },
+        Output::File(path) => {
+            if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            // Without force, the file is only created if it does not exist (checked atomically by the open)
+            let mut file = match std::fs::OpenOptions::new().write(true).create(force).truncate(force).create_new(!force).open(path) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    return Err(std::io::Error::new(std::io::ErrorKind::AlreadyExists,
+                        format!("{} already exists, use --force to overwrite it", path.display())));
+                },
+                Err(e) => return Err(e),
+            };
+            file.write_all(content)
         }
-    };
-    
-    blob
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_output() {
+        assert_eq!(Output::parse("-"), Output::Stdout);
+        assert_eq!(Output::parse("data/test.json"), Output::File(PathBuf::from("data/test.json")));
+    }
+
+    #[test]
+    fn test_write_output_creates_directories_and_refuses_overwrite() {
+        let root = std::env::temp_dir().join(format!("exchange-test-{}", uuid::Uuid::new_v4()));
+        let output = Output::File(root.join("rates/2023/empty.json"));
+
+        // Empty blobs are written as empty files
+        assert!(write_output(b"", &output, false).is_ok());
+        assert_eq!(write_output(b"{}", &output, false).unwrap_err().kind(), std::io::ErrorKind::AlreadyExists);
+        assert!(write_output(b"{}", &output, true).is_ok());
+        assert_eq!(std::fs::read(root.join("rates/2023/empty.json")).unwrap(), b"{}");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
 }
@@ -0,0 +1,315 @@
+/*
+    This file contains the staged (block) upload to Azure Blob Storage
+    Large blobs are uploaded as blocks (put_block) and committed at once (put_block_list)
+    Blocks that are already stored for the blob are skipped, so an interrupted upload can be resumed
+*/
+
+use std::collections::HashSet;
+use std::fmt::{Debug, Formatter};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use azure_core::error::ErrorKind;
+use azure_storage_blobs::prelude::{BlobBlockType, BlobClient, BlobContentMD5, BlockId, BlockList, BlockListType};
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use log::{info, warn, error};
+use tokio::io::AsyncReadExt;
+
+use crate::azure::helper::{create_azure_blob, get_az_client, is_conflict, write_context, UploadProperties};
+
+// Maximum number of blocks of a block blob
+const MAX_BLOCKS: u64 = 50_000;
+
+/// Progress of a staged upload, reported after every block
+/// - uploaded/total: Bytes of the blob uploaded so far (including skipped blocks) and in total
+/// - blocks: Number of blocks uploaded so far, skipped: Blocks that were already stored (resumed upload)
+#[derive(Debug, Clone, PartialEq)]
+pub struct UploadProgress {
+    pub blob_name: String,
+    pub uploaded: u64,
+    pub total: u64,
+    pub blocks: usize,
+    pub skipped: usize,
+}
+
+/// Callback that receives the progress of staged uploads (see BlockOptions)
+pub type ProgressCallback = Arc<dyn Fn(&UploadProgress) + Send + Sync>;
+
+/// Options for the staged upload
+/// - block_size: Size of a block in bytes, smaller blobs are uploaded with a single request
+/// - parallelism: Number of blocks uploaded at the same time
+/// - progress: Called after every block of a staged upload (e.g. print_progress)
+#[derive(Clone)]
+pub struct BlockOptions {
+    pub block_size: usize,
+    pub parallelism: usize,
+    pub progress: Option<ProgressCallback>,
+}
+
+impl Debug for BlockOptions {
+    fn fmt(&self, f: &mut Formatter) -> std::fmt::Result {
+        f.debug_struct("BlockOptions")
+            .field("block_size", &self.block_size)
+            .field("parallelism", &self.parallelism)
+            .field("progress", &self.progress.is_some())
+            .finish()
+    }
+}
+
+impl Default for BlockOptions {
+    fn default() -> Self {
+        BlockOptions {
+            block_size: 8 * 1024 * 1024,
+            parallelism: 4,
+            progress: None,
+        }
+    }
+}
+
+impl BlockOptions {
+
+    /// Returns true if content of the given size is uploaded as blocks
+    pub fn is_staged(&self, size: u64) -> bool {
+        size > self.block_size.max(1) as u64
+    }
+}
+
+/// Prints the progress of a staged upload to stderr (one line, updated in place)
+pub fn print_progress(progress: &UploadProgress) {
+    let mut stderr = std::io::stderr();
+    let _ = write!(stderr, "\rUploading {}: {} of {} bytes ({}%)", progress.blob_name, progress.uploaded, progress.total, progress.uploaded * 100 / progress.total.max(1));
+    if progress.uploaded >= progress.total {
+        let _ = writeln!(stderr);
+    }
+    let _ = stderr.flush();
+}
+
+/// Returns the id of a block
+/// - Consists of the position and the MD5 hash of the block, so that a resumed upload only skips identical blocks
+/// - All ids have the same length (required by Azure Blob Storage)
+pub fn block_id(index: usize, digest: &md5::Digest) -> BlockId {
+    BlockId::new(format!("{:06}-{:x}", index, digest).into_bytes())
+}
+
+/// Uploads the content to Azure Blob Storage
+/// - Content up to the block size is uploaded with a single request (see create_azure_blob)
+/// - Larger content is uploaded as blocks (see stage_blocks)
+pub async fn upload_blob(container_name: &str, blob_name: &str, data: Vec<u8>, properties: &UploadProperties, options: &BlockOptions) -> azure_core::Result<()> {
+
+    if !options.is_staged(data.len() as u64) {
+        create_azure_blob(container_name, blob_name, data, properties).await?;
+        return Ok(());
+    }
+
+    let total = data.len() as u64;
+    let blocks = stream::iter(data.chunks(options.block_size.max(1)).map(owned_block));
+    stage_blocks(container_name, blob_name, blocks, total, properties, options).await
+}
+
+/// Uploads a file to Azure Blob Storage
+/// - The file is read block by block, at most parallelism blocks are held in memory
+/// - Files up to the block size are uploaded with a single request (see create_azure_blob)
+pub async fn upload_file(container_name: &str, blob_name: &str, path: &Path, properties: &UploadProperties, options: &BlockOptions) -> azure_core::Result<()> {
+
+    let file = tokio::fs::File::open(path).await?;
+    let total = file.metadata().await?.len();
+
+    if !options.is_staged(total) {
+        let data = tokio::fs::read(path).await?;
+        create_azure_blob(container_name, blob_name, data, properties).await?;
+        return Ok(());
+    }
+
+    stage_blocks(container_name, blob_name, file_blocks(file, options.block_size.max(1)), total, properties, options).await
+}
+
+/// Copies a block of the content, the copy is dropped once the block is uploaded
+fn owned_block(block: &[u8]) -> std::io::Result<Vec<u8>> {
+    Ok(block.to_vec())
+}
+
+/// Splits a file into blocks of the given size (the last block may be smaller)
+fn file_blocks(file: tokio::fs::File, block_size: usize) -> impl Stream<Item = std::io::Result<Vec<u8>>> {
+    stream::try_unfold(file, move |mut file| async move {
+        let mut block = Vec::with_capacity(block_size);
+        (&mut file).take(block_size as u64).read_to_end(&mut block).await?;
+
+        if block.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some((block, file)))
+        }
+    })
+}
+
+/// Returns the ids of the blocks already stored for the blob (committed and uncommitted)
+/// - A blob that does not exist has no blocks, every other error fails the upload (e.g. missing permission)
+async fn stored_blocks(blob_client: &BlobClient) -> azure_core::Result<HashSet<Vec<u8>>> {
+    match blob_client.get_block_list().block_list_type(BlockListType::All).await {
+        Ok(response) => Ok(response.block_with_size_list.blocks
+            .into_iter()
+            .map(|block| match block.block_list_type {
+                BlobBlockType::Committed(id) | BlobBlockType::Uncommitted(id) | BlobBlockType::Latest(id) => id.as_ref().to_vec(),
+            })
+            .collect()),
+        Err(e) if matches!(e.kind(), ErrorKind::HttpResponse { error_code: Some(code), .. } if code == "BlobNotFound") => Ok(HashSet::new()),
+        Err(e) => {
+            error!("Error reading the stored blocks of blob {}: {}", blob_client.blob_name(), e);
+            Err(e)
+        }
+    }
+}
+
+/// Uploads the blocks and commits them as the content of the blob
+/// - Uploads up to parallelism blocks at the same time, the block list keeps their order
+/// - Skips blocks already stored for the blob (resume of an interrupted upload)
+/// - Logs and reports the progress after every block (see BlockOptions::progress)
+/// - Fails before the upload if the content needs more than 50,000 blocks
+/// - Sends the MD5 hash of every block and records the MD5 hash of the blob in its metadata
+/// - The blob is only replaced once every block is uploaded (or not at all if it exists and if_not_exists is set)
+async fn stage_blocks<S>(container_name: &str, blob_name: &str, blocks: S, total: u64, properties: &UploadProperties, options: &BlockOptions) -> azure_core::Result<()>
+where
+    S: Stream<Item = std::io::Result<Vec<u8>>>,
+{
+    let blocks_needed = total.div_ceil(options.block_size.max(1) as u64);
+    if blocks_needed > MAX_BLOCKS {
+        error!("Blob {} needs {} blocks (maximum: {}): Increase the block size", blob_name, blocks_needed, MAX_BLOCKS);
+        return Err(azure_core::Error::message(azure_core::error::ErrorKind::Other,
+            format!("{} bytes exceed {} blocks of {} bytes", total, MAX_BLOCKS, options.block_size)));
+    }
+
+    let blob_client = get_az_client()?.blob_client(container_name, blob_name);
+
+    let stored = stored_blocks(&blob_client).await?;
+    if !stored.is_empty() {
+        info!("Blob {} has {} stored block(s): Resuming upload", blob_name, stored.len());
+    }
+
+    // The blocks are read in order, so the hash of the whole blob is computed on the way
+    let mut content_md5 = md5::Context::new();
+    let mut uploads = Box::pin(blocks
+        .map_err(azure_core::Error::from)
+        .inspect_ok(|block| content_md5.consume(block))
+        .enumerate()
+        .map(|(index, block)| {
+            let blob_client = blob_client.clone();
+            let stored = &stored;
+            async move {
+                let block = block?;
+                let digest = md5::compute(&block);
+                let id = block_id(index, &digest);
+                let size = block.len() as u64;
+
+                // Azure verifies every block against its MD5 hash
+                let skipped = stored.contains(id.as_ref());
+                if !skipped {
+                    blob_client.put_block(id.clone(), block).hash(digest).await?;
+                }
+                Ok::<_, azure_core::Error>((id, size, skipped))
+            }
+        })
+        .buffered(options.parallelism.max(1)));
+
+    // Collect the ids in the order of the blocks and report the progress
+    let mut block_list = BlockList::default();
+    let mut uploaded = 0;
+    let mut skipped = 0;
+    while let Some(result) = uploads.next().await {
+        let (id, size, was_stored) = match result {
+            Ok(block) => block,
+            Err(e) => {
+                error!("Error uploading block {} of blob {}: {}", block_list.blocks.len() + 1, blob_name, e);
+                return Err(e);
+            }
+        };
+
+        uploaded += size;
+        if was_stored {
+            skipped += 1;
+        }
+        block_list.blocks.push(BlobBlockType::new_latest(id));
+        info!("Upload of {}: {} of {} bytes ({}%, {} block(s))", blob_name, uploaded, total, uploaded * 100 / total.max(1), block_list.blocks.len());
+        if let Some(progress) = &options.progress {
+            progress(&UploadProgress {
+                blob_name: blob_name.to_string(),
+                uploaded,
+                total,
+                blocks: block_list.blocks.len(),
+                skipped,
+            });
+        }
+    }
+
+    drop(uploads);
+    let digest = content_md5.compute();
+
+    // Commit the blocks with the properties and the MD5 hash of the blob
+    let mut builder = blob_client.put_block_list(block_list)
+        .content_type(properties.content_type.clone())
+        .metadata(properties.metadata(&digest))
+        .content_md5(BlobContentMD5::from(digest))
+        .context(write_context(properties));
+    if let Some(content_encoding) = &properties.content_encoding {
+        builder = builder.content_encoding(content_encoding.clone());
+    }
+    if let Some(tags) = properties.tags() {
+        builder = builder.tags(tags);
+    }
+
+    match builder.await {
+        Ok(_) => {
+            info!("Successfully created blob {} from staged blocks ({} skipped as already uploaded)", blob_name, skipped);
+            Ok(())
+        }
+        Err(e) if properties.if_not_exists && is_conflict(&e) => {
+            warn!("Blob {} already exists: Blocks not committed", blob_name);
+            Err(e)
+        }
+        Err(e) => {
+            error!("Error committing blocks of blob {}: {}", blob_name, e);
+            Err(e)
+        }
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_block_id() {
+        let eur = md5::compute(b"{\"base\": \"EUR\"}");
+        let first = block_id(0, &eur);
+        let second = block_id(1, &eur);
+
+        assert_eq!(first.as_ref().len(), second.as_ref().len());
+        assert_ne!(first, second);
+        assert_eq!(first, block_id(0, &eur));
+        assert_ne!(first, block_id(0, &md5::compute(b"{\"base\": \"USD\"}")));
+        assert_eq!(block_id(49_999, &md5::compute(b"")).as_ref().len(), first.as_ref().len());
+    }
+
+    #[test]
+    fn test_is_staged() {
+        let options = BlockOptions { block_size: 4, ..Default::default() };
+        assert!(!options.is_staged(0));
+        assert!(!options.is_staged(4));
+        assert!(options.is_staged(5));
+    }
+
+    #[tokio::test]
+    async fn test_file_blocks() {
+        let path = std::env::temp_dir().join(format!("exchange-blocks-{}.json", uuid::Uuid::new_v4()));
+        std::fs::write(&path, b"0123456789").unwrap();
+
+        let file = tokio::fs::File::open(&path).await.unwrap();
+        let blocks: Vec<Vec<u8>> = file_blocks(file, 4).try_collect().await.unwrap();
+        assert_eq!(blocks, vec![b"0123".to_vec(), b"4567".to_vec(), b"89".to_vec()]);
+
+        let _ = std::fs::remove_file(path);
+    }
+}
@@ -2,31 +2,136 @@
     This file contains the function that pushes data to Azure Blob Storage
 */
 
-use azure_storage_blobs::prelude::PublicAccess;
-use log::{info, warn, error};
+use std::collections::BTreeMap;
 
-use crate::azure::helper::{get_az_client, create_azure_container};
-use super::helper::create_azure_blob;
+use crate::azure::compression::Compression;
+use crate::azure::encryption::KeyRing;
+use crate::azure::upload::BlockOptions;
+use crate::storage::azure::AzureBlobStore;
+use crate::storage::write_blob;
 
+// Maximum number of suffixes tried by ConflictPolicy::AppendSuffix
+const MAX_SUFFIX: usize = 1000;
+
+/// Handling of blobs that already exist
+/// - Overwrite: The existing blob is replaced
+/// - IfNotExists: The write fails, the existing blob is kept
+/// - AppendSuffix: The blob is written with the first free suffix (e.g. 0-42-1.ndjson)
+/// - Except for Overwrite, the sink checks the existence atomically (If-None-Match), so concurrent writers never overwrite each other
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ConflictPolicy {
+    #[default]
+    Overwrite,
+    IfNotExists,
+    AppendSuffix,
+}
+
+impl ConflictPolicy {
+
+    /// Returns true if existing blobs must not be overwritten
+    pub fn if_not_exists(&self) -> bool {
+        *self != ConflictPolicy::Overwrite
+    }
+
+    /// Returns the blob names that are tried in order (only AppendSuffix tries more than one)
+    pub fn candidates<'a>(&self, blob_name: &'a str) -> impl Iterator<Item = String> + 'a {
+        let attempts = match self {
+            ConflictPolicy::AppendSuffix => MAX_SUFFIX + 1,
+            _ => 1,
+        };
+        (0..attempts).map(move |suffix| suffixed_name(blob_name, suffix))
+    }
+}
+
+/// Appends a numeric suffix to the file name of a blob, in front of its extensions
+/// - rates/0-42.ndjson.gz with suffix 1 becomes rates/0-42-1.ndjson.gz
+/// - Suffix 0 returns the blob name unchanged
+pub fn suffixed_name(blob_name: &str, suffix: usize) -> String {
+    if suffix == 0 {
+        return blob_name.to_string();
+    }
+
+    let stem = name_stem(blob_name);
+    format!("{}-{}{}", stem, suffix, &blob_name[stem.len()..])
+}
+
+/// Returns the blob name without its extensions, the names of all suffixes start with it (see suffixed_name)
+/// - rates/0-42.ndjson.gz becomes rates/0-42
+pub fn name_stem(blob_name: &str) -> &str {
+    let file_start = blob_name.rfind('/').map(|index| index + 1).unwrap_or(0);
+    // A leading dot (hidden file) is part of the name, not an extension
+    let extension_start = blob_name[file_start..].char_indices()
+        .skip(1)
+        .find(|(_, character)| *character == '.')
+        .map(|(index, _)| file_start + index)
+        .unwrap_or(blob_name.len());
+
+    &blob_name[..extension_start]
+}
+
+/// Options for pushing a blob
+/// - content_type: MIME type of the (uncompressed) content
+/// - compression: Compression applied before the upload
+/// - conflict: Handling of a blob that already exists
+/// - metadata: Name/value pairs stored with the blob (see export::metadata)
+/// - tags: Blob index tags
+/// - encryption: Keys of the client-side encryption, applied after the compression (see azure::encryption)
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    pub content_type: String,
+    pub compression: Compression,
+    pub conflict: ConflictPolicy,
+    pub metadata: BTreeMap<String, String>,
+    pub tags: BTreeMap<String, String>,
+    pub encryption: Option<KeyRing>,
+}
+
+impl Default for WriteOptions {
+    fn default() -> Self {
+        WriteOptions {
+            content_type: "application/json".to_string(),
+            compression: Compression::None,
+            conflict: ConflictPolicy::Overwrite,
+            metadata: BTreeMap::new(),
+            tags: BTreeMap::new(),
+            encryption: None,
+        }
+    }
+}
 
 /// Pushes a file to Azure Blob Storage
 /// - Establishes a connection to Azure Blob Storage via azure_key.json
-/// - Reads the file to be pushed from the file system
-/// - Pushes the file to Azure Blob Storage
-/// - Returns a Result with the status of the operation
-pub async fn push_to_azure(container_name: &str, blob_name: &str, content: &str) -> azure_core::Result<()> {
-
-    let blob_client = get_az_client().blob_client(container_name, blob_name);
-    // Check if container exists
-    // - If not, create it
-    let container_exists = blob_client.container_client().exists().await?;
-    if !container_exists {
-        warn!("Container {} does not exist. Creating...", container_name);
-        let _container = create_azure_container(container_name).await?;
+/// - Writes the content like every other sink (see storage::write_blob): compression, encryption,
+///   MD5 hash and conflict policy
+/// - Pushes the file as staged blocks if it is larger than the block size (blocks, e.g. from UploadArgs)
+/// - Returns the name of the blob written
+pub async fn push_to_azure(container_name: &str, blob_name: &str, content: impl AsRef<[u8]>, options: &WriteOptions, blocks: &BlockOptions) -> Result<String, anyhow::Error> {
+    write_blob(&AzureBlobStore::with_blocks(blocks.clone()), container_name, blob_name, content.as_ref(), options).await
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suffixed_name() {
+        assert_eq!(suffixed_name("rates/0-42.ndjson.gz", 0), "rates/0-42.ndjson.gz");
+        assert_eq!(suffixed_name("rates/0-42.ndjson.gz", 1), "rates/0-42-1.ndjson.gz");
+        assert_eq!(suffixed_name("rates.v2/export", 3), "rates.v2/export-3");
+        assert_eq!(suffixed_name(".hidden", 1), ".hidden-1");
+        assert_eq!(name_stem("rates/0-42.ndjson.gz"), "rates/0-42");
     }
 
-    // Create a Blob and push file
-    let _blob = create_azure_blob(container_name, blob_name, content.as_bytes().to_vec()).await?;
+    #[test]
+    fn test_candidates() {
+        assert_eq!(ConflictPolicy::IfNotExists.candidates("test.json").collect::<Vec<_>>(), vec!["test.json"]);
 
-    Ok(())
+        let mut candidates = ConflictPolicy::AppendSuffix.candidates("test.json");
+        assert_eq!(candidates.next().as_deref(), Some("test.json"));
+        assert_eq!(candidates.next().as_deref(), Some("test-1.json"));
+        assert_eq!(candidates.count(), MAX_SUFFIX - 1);
+    }
 }
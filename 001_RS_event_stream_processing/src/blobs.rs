@@ -0,0 +1,233 @@
+/*
+    This file contains the blob commands (list, info, download, rotate-keys)
+    They are used to discover and fetch what forward and write have stored in a container
+*/
+
+use std::path::{Path, PathBuf};
+
+use log::{info, error};
+use time::format_description::well_known::Rfc3339;
+
+use crate::azure::compression::Compression;
+use crate::azure::encryption::{is_encrypted, rewrap, DecryptionKeys, KeyRing};
+use crate::storage::local::checked_path;
+use crate::storage::{read_blob, BlobInfo, BlobStore};
+
+/// Result of a bulk download
+/// - files: Local paths of the downloaded blobs
+/// - bytes: Number of (decompressed) bytes written
+/// - failures: Name and error of every blob that could not be downloaded
+#[derive(Debug, Clone, Default)]
+pub struct DownloadReport {
+    pub files: Vec<PathBuf>,
+    pub bytes: u64,
+    pub failures: Vec<(String, String)>,
+}
+
+/// Result of a key rotation
+/// - rotated: Names of the blobs whose data key was re-wrapped with the active key
+/// - current: Number of encrypted blobs already using the active key
+/// - unencrypted: Number of blobs without encryption (skipped)
+/// - failures: Name and error of every blob that could not be rotated
+#[derive(Debug, Clone, Default)]
+pub struct RotationReport {
+    pub rotated: Vec<String>,
+    pub current: u64,
+    pub unencrypted: u64,
+    pub failures: Vec<(String, String)>,
+}
+
+/// Formats a blob as one line of the blob list (name, size, last modified, content type)
+pub fn format_blob(blob: &BlobInfo) -> String {
+    format!("{}\t{}\t{}\t{}",
+        blob.name,
+        blob.size,
+        blob.last_modified.format(&Rfc3339).unwrap_or_default(),
+        blob.properties.content_type)
+}
+
+/// Formats the properties, metadata and tags of a blob (one "name: value" pair per line)
+pub fn format_blob_info(blob: &BlobInfo) -> String {
+    let mut lines = vec![
+        format!("name: {}", blob.name),
+        format!("size: {}", blob.size),
+        format!("last_modified: {}", blob.last_modified.format(&Rfc3339).unwrap_or_default()),
+        format!("content_type: {}", blob.properties.content_type),
+        format!("content_encoding: {}", blob.properties.content_encoding.as_deref().unwrap_or("-")),
+    ];
+    lines.extend(blob.properties.metadata.iter().map(|(name, value)| format!("metadata.{}: {}", name, value)));
+    lines.extend(blob.properties.tags.iter().map(|(name, value)| format!("tag.{}: {}", name, value)));
+    lines.join("\n")
+}
+
+/// Downloads every blob of the container (matching the prefix) into a directory
+/// - The blob names are kept as relative paths, the directories are created as needed
+/// - Compressed blobs are decompressed and stored without the extension of the compression
+/// - Encrypted blobs are decrypted, the keys are loaded once for all blobs (see DecryptionKeys)
+/// - A failed blob is reported and skipped, the download continues with the next one
+pub async fn download_blobs(store: &dyn BlobStore, container_name: &str, prefix: Option<&str>, out_dir: &Path) -> Result<DownloadReport, anyhow::Error> {
+
+    let blobs = store.list(container_name, prefix).await?;
+    info!("Blob(s) to download from {}: {}", container_name, blobs.len());
+
+    let keys = DecryptionKeys::default();
+    let mut report = DownloadReport::default();
+    for blob in blobs {
+        match download_blob(store, container_name, &blob, out_dir, &keys).await {
+            Ok((path, bytes)) => {
+                report.files.push(path);
+                report.bytes += bytes;
+            },
+            Err(e) => {
+                error!("Error while downloading blob {}: {}", &blob.name, e);
+                report.failures.push((blob.name, e.to_string()));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+/// Downloads a single blob, returns the local path and the number of bytes written
+async fn download_blob(store: &dyn BlobStore, container_name: &str, blob: &BlobInfo, out_dir: &Path, keys: &DecryptionKeys) -> Result<(PathBuf, u64), anyhow::Error> {
+
+    let compression = Compression::from_properties(blob.properties.content_encoding.as_deref(), &blob.properties.metadata);
+    let path = out_dir.join(checked_path(&compression.file_name(&blob.name))?);
+
+    let content = read_blob(store, container_name, &blob.name, keys).await?;
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(&path, &content).await?;
+
+    info!("Downloaded blob {} to {}", &blob.name, path.display());
+    Ok((path, content.len() as u64))
+}
+
+/// Re-wraps the data keys of every encrypted blob of the container (matching the prefix) with the active key
+/// - Only the metadata is updated, the content is neither downloaded nor rewritten
+/// - A blob that was changed since it was listed is not updated (see BlobStore::set_metadata)
+/// - A failed blob is reported and skipped, the rotation continues with the next one
+pub async fn rotate_keys(store: &dyn BlobStore, container_name: &str, prefix: Option<&str>, keys: &KeyRing) -> Result<RotationReport, anyhow::Error> {
+
+    let blobs = store.list(container_name, prefix).await?;
+    info!("Blob(s) to check for key rotation in {}: {} (active key: {})", container_name, blobs.len(), keys.active_key());
+
+    let mut report = RotationReport::default();
+    for blob in blobs {
+        if !is_encrypted(&blob.properties.metadata) {
+            report.unencrypted += 1;
+            continue;
+        }
+
+        let metadata = match rewrap(&blob.name, &blob.properties.metadata, keys) {
+            Ok(Some(metadata)) => metadata,
+            Ok(None) => {
+                report.current += 1;
+                continue;
+            },
+            Err(e) => {
+                error!("Error while rotating the key of blob {}: {}", &blob.name, e);
+                report.failures.push((blob.name, e.to_string()));
+                continue;
+            }
+        };
+
+        match store.set_metadata(container_name, &blob.name, &metadata, blob.etag.as_deref()).await {
+            Ok(()) => {
+                info!("Rotated the key of blob {} to {}", &blob.name, keys.active_key());
+                report.rotated.push(blob.name);
+            },
+            Err(e) => {
+                error!("Error while rotating the key of blob {}: {}", &blob.name, e);
+                report.failures.push((blob.name, e.to_string()));
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+
+    use super::*;
+    use crate::azure::compression::COMPRESSION_METADATA;
+    use crate::azure::encryption::{decrypt, KEY_ID_METADATA};
+    use crate::config::EncryptionConfig;
+    use crate::azure::writer::WriteOptions;
+    use crate::storage::local::LocalBlobStore;
+    use crate::storage::{read_blob, write_blob};
+
+    #[tokio::test]
+    async fn test_download_blobs() {
+        let root = std::env::temp_dir().join(format!("exchange-test-{}", uuid::Uuid::new_v4()));
+        let store = LocalBlobStore::new(root.join("store"));
+        let compressed = WriteOptions { compression: Compression::Gzip, ..Default::default() };
+
+        write_blob(&store, "test", "rates/2023/0-1.ndjson", b"{}\n", &compressed).await.unwrap();
+        write_blob(&store, "test", "rates/2023/0-2.ndjson", b"{}\n{}\n", &WriteOptions::default()).await.unwrap();
+        write_blob(&store, "test", "other.json", b"{}", &WriteOptions::default()).await.unwrap();
+
+        let report = download_blobs(&store, "test", Some("rates/"), &root.join("out")).await.unwrap();
+
+        assert!(report.failures.is_empty());
+        assert_eq!(report.files.len(), 2);
+        assert_eq!(report.bytes, 9);
+        assert_eq!(std::fs::read(root.join("out/rates/2023/0-1.ndjson")).unwrap(), b"{}\n");
+        assert!(!root.join("out/other.json").exists());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_rotate_keys() {
+        let key_ring = |active_key: &str| KeyRing::from_config(&EncryptionConfig {
+            active_key: active_key.to_string(),
+            keys: BTreeMap::from([
+                ("kek-1".to_string(), STANDARD.encode([1u8; 32])),
+                ("kek-2".to_string(), STANDARD.encode([2u8; 32])),
+            ]),
+        }).unwrap();
+
+        let root = std::env::temp_dir().join(format!("exchange-test-{}", uuid::Uuid::new_v4()));
+        let store = LocalBlobStore::new(&root);
+        let encrypted = WriteOptions { compression: Compression::Gzip, encryption: Some(key_ring("kek-1")), ..Default::default() };
+
+        let blob_name = write_blob(&store, "test", "rates/0-1.ndjson", b"{}\n", &encrypted).await.unwrap();
+        write_blob(&store, "test", "rates/0-2.ndjson", b"{}\n", &WriteOptions::default()).await.unwrap();
+
+        let keys = key_ring("kek-2");
+        let report = rotate_keys(&store, "test", Some("rates/"), &keys).await.unwrap();
+        assert_eq!(report.rotated, vec![blob_name.clone()]);
+        assert_eq!(report.unencrypted, 1);
+        assert!(report.failures.is_empty());
+
+        // The content is unchanged (MD5), only the data key is wrapped by the active key
+        let blob = store.get("test", &blob_name).await.unwrap();
+        assert_eq!(blob.properties.metadata[KEY_ID_METADATA], "kek-2");
+        let content = decrypt(&blob_name, &blob.content, &blob.properties.metadata, &keys).unwrap();
+        assert_eq!(Compression::Gzip.decompress(&content).unwrap(), b"{}\n");
+
+        // The compression of an encrypted blob is kept in the metadata, not as Content-Encoding
+        assert_eq!(blob.properties.content_encoding, None);
+        assert_eq!(blob.properties.metadata[COMPRESSION_METADATA], "gzip");
+        assert_eq!(read_blob(&store, "test", &blob_name, &DecryptionKeys::new(keys.clone())).await.unwrap(), b"{}\n");
+
+        let report = rotate_keys(&store, "test", Some("rates/"), &keys).await.unwrap();
+        assert!(report.rotated.is_empty());
+        assert_eq!(report.current, 1);
+
+        // Unencrypted blobs are still read without encryption_config.json
+        assert_eq!(read_blob(&store, "test", "rates/0-2.ndjson", &DecryptionKeys::default()).await.unwrap(), b"{}\n");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
@@ -2,8 +2,24 @@
     This file contains the CLI configuration
 */
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use clap::Args;
 
+use crate::azure::compression::Compression;
+use crate::azure::upload::{print_progress, BlockOptions, ProgressCallback};
+use crate::azure::writer::ConflictPolicy;
+use crate::export::csv::parse_field;
+use crate::export::format::ExportFormat;
+use crate::export::metadata::parse_tag;
+use crate::export::rolling::{parse_template, RollPolicy};
+use crate::kafka::consumer::{ConsumerOptions, StartPosition};
+use crate::retention::parse_age;
+use crate::storage::{open_blob_store, BlobStore, SinkKind};
+
+use log::warn;
+
 /// Command line arguments
 /// - subcommand: Action that should be performed
 /// - args: Arguments that are processed for the subcommand
@@ -21,8 +37,12 @@ pub struct Cli {
 /// - Request: Request data from external API
 /// - Ingest: Run the applications pipeline (Request -> Produce)
 /// - Forward: Forward data from one broker through an intermediate appicaltion to azure  (Consume -> Write)  
+/// - Replay: Produce exported blobs back into a topic (Read -> Produce)
+/// - Blobs: List, inspect and download blobs (see BlobsCommand)
 /// - Config: Configure the application (API for Ingest)
 /// - Version: Get version information
+// The command is parsed once, so the size of the Forward variant does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(clap::Subcommand)]
 pub enum Command {
 
@@ -30,7 +50,7 @@ pub enum Command {
     Produce {
         #[clap(short, long, help = "Topic name")]
         topic: String,
-        #[clap(short, long, help = "Message")]
+        #[clap(short, long, help = "File with a JSON document or newline-delimited JSON (\"-\" for stdin)")]
         file: String,
     },
 
@@ -38,16 +58,26 @@ pub enum Command {
     Consume {
         #[clap(short, long, help = "Topic name")]
         topic: String,
-        #[clap(long, help="Time to live (in seconds)", default_value = "30")]
-        ttl: u8,
+        #[clap(long, help="Time to live (in seconds) to wait for a message before the poll counts as idle", default_value = "30")]
+        ttl: u64,
+        #[clap(long, help = "Number of subsequent idle polls before the consumer stops", default_value = "5")]
+        max_retries: u32,
+        #[clap(flatten)]
+        consumer: ConsumerArgs,
     },
 
     #[clap(about = "Read data from Azure Blob Storage")]
     Read {
         #[clap(short, long, help = "Container name")]
         container_name: String,
-        #[clap(short, long, help = "File path")]
+        #[clap(short, long, help = "Blob name")]
         file: String,
+        #[clap(short, long, help = "Output path or \"-\" for stdout (default: the blob name)")]
+        output: Option<String>,
+        #[clap(long, help = "Overwrite the output file if it exists")]
+        force: bool,
+        #[clap(flatten)]
+        sink: SinkArgs,
     },
 
     #[clap(about = "Write data to Azure Blob Storage")]
@@ -56,6 +86,18 @@ pub enum Command {
         container_name: String,
         #[clap(short, long, help = "File path")]
         file: String,
+        #[clap(long, help = "Compression of the blob (the extension is appended to the blob name)", value_enum, default_value = "none")]
+        compression: Compression,
+        #[clap(flatten)]
+        upload: UploadArgs,
+        #[clap(flatten)]
+        conflict: ConflictArgs,
+        #[clap(long = "tag", help = "Blob index tag (key=value, repeatable, at most 10)", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        #[clap(long, help = "Encrypt the blob with the active key of encryption_config.json (AES-256-GCM)")]
+        encrypt: bool,
+        #[clap(flatten)]
+        sink: SinkArgs,
     },
 
     #[clap(about = "Request most recent data from external API")]
@@ -78,8 +120,62 @@ pub enum Command {
         topic: String,
         #[clap(short, long, help = "Container name for Azure Blob Storage")]
         container_name: String,
-        #[clap(short, long, help = "File (or path) for Azure Blob Storage")]
+        #[clap(short, long, help = "File (or path) for Azure Blob Storage, may contain placeholders (e.g. {topic}/{yyyy}/{MM}/{dd}/{HH}/{partition}-{first_offset}.{ext})", value_parser = parse_template)]
         filename: String,
+        #[clap(long, help = "Time (in seconds) to wait for a message before the poll counts as idle", default_value = "2")]
+        idle_timeout: u64,
+        #[clap(long, help = "Number of subsequent idle polls before the consumer stops", default_value = "5")]
+        max_retries: u32,
+        #[clap(long, help = "Blob format", value_enum, default_value = "ndjson")]
+        format: ExportFormat,
+        #[clap(long, help = "JSON Schema file with the columns of parquet blobs (inferred from the payloads by default)")]
+        schema: Option<String>,
+        #[clap(long, help = "Comma-separated dotted paths of the csv columns, e.g. base,end_date,rates.USD (all fields by default)", value_delimiter = ',', value_parser = parse_field)]
+        fields: Vec<String>,
+        #[clap(long, help = "Compression of the blobs (the extension is appended to the blob name)", value_enum, default_value = "none")]
+        compression: Compression,
+        #[clap(long, help = "Number of times a failed upload is retried before the batch is left uncommitted", default_value = "0")]
+        upload_retries: u32,
+        #[clap(flatten)]
+        consumer: ConsumerArgs,
+        #[clap(flatten)]
+        roll: RollArgs,
+        #[clap(flatten)]
+        upload: UploadArgs,
+        #[clap(flatten)]
+        conflict: ConflictArgs,
+        #[clap(long = "tag", help = "Blob index tag (key=value, repeatable, at most 10)", value_parser = parse_tag)]
+        tags: Vec<(String, String)>,
+        #[clap(long, help = "Encrypt the blob with the active key of encryption_config.json (AES-256-GCM)")]
+        encrypt: bool,
+        #[clap(long, help = "Append every batch to an append blob (e.g. a daily blob: {topic}/{yyyy}-{MM}-{dd}.{ext}), created if missing", conflicts_with_all = ["encrypt", "overwrite", "if_not_exists", "append_suffix"])]
+        append: bool,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+
+    #[clap(about = "Produce exported blobs back into a Kafka topic")]
+    Replay {
+        #[clap(short, long, help = "Container name")]
+        container_name: String,
+        #[clap(short, long, help = "Only replay blobs whose name starts with the prefix")]
+        prefix: Option<String>,
+        #[clap(short, long, help = "Topic name")]
+        topic: String,
+        #[clap(long, help = "Maximum number of records per second")]
+        rate: Option<u64>,
+        #[clap(long, help = "Number of records produced at once", default_value = "500")]
+        batch_size: usize,
+        #[clap(long, help = "Checkpoint file, a replay with the same file resumes where it stopped")]
+        checkpoint: Option<String>,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+
+    #[clap(about = "List, inspect and download blobs")]
+    Blobs {
+        #[clap(subcommand)]
+        command: BlobsCommand,
     },
 
     #[clap(about = "Configure the application")]
@@ -97,6 +193,243 @@ pub enum Command {
 
 }
 
+/// Subcommands of Blobs
+/// - List: List the blobs of a container (name, size, last modified, content type)
+/// - Info: Show properties and metadata of a blob
+/// - Download: Download every blob matching a prefix into a directory
+/// - Prune: Delete expired blobs by age and/or count (see retention)
+/// - RotateKeys: Re-wrap the data keys of encrypted blobs with the active key of encryption_config.json
+#[derive(clap::Subcommand)]
+pub enum BlobsCommand {
+
+    #[clap(about = "List the blobs of a container")]
+    List {
+        #[clap(short, long, help = "Container name")]
+        container_name: String,
+        #[clap(short, long, help = "Only list blobs whose name starts with the prefix")]
+        prefix: Option<String>,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+
+    #[clap(about = "Show properties and metadata of a blob")]
+    Info {
+        #[clap(short, long, help = "Container name")]
+        container_name: String,
+        #[clap(short, long, help = "Blob name")]
+        blob_name: String,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+
+    #[clap(about = "Download blobs into a directory")]
+    Download {
+        #[clap(short, long, help = "Container name")]
+        container_name: String,
+        #[clap(short, long, help = "Only download blobs whose name starts with the prefix")]
+        prefix: Option<String>,
+        #[clap(short, long, help = "Directory the blobs are downloaded to", default_value = ".")]
+        out: String,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+
+    #[clap(about = "Delete expired blobs by age and/or count")]
+    Prune {
+        #[clap(short, long, help = "Container name")]
+        container_name: String,
+        #[clap(short, long, help = "Only prune blobs whose name starts with the prefix")]
+        prefix: Option<String>,
+        #[clap(long, help = "Delete blobs older than the age (e.g. 30d, units: s, m, h, d, w), overrides retention_config.json", value_parser = parse_age)]
+        older_than: Option<Duration>,
+        #[clap(long, help = "Always keep the newest blobs, overrides retention_config.json")]
+        keep_last: Option<usize>,
+        #[clap(long, help = "Only show the blobs that would be deleted")]
+        dry_run: bool,
+        #[clap(long, help = "Number of blobs deleted at the same time", default_value = "8", value_parser = clap::value_parser!(u64).range(1..))]
+        parallelism: u64,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+
+    #[clap(about = "Re-wrap the data keys of encrypted blobs with the active key")]
+    RotateKeys {
+        #[clap(short, long, help = "Container name")]
+        container_name: String,
+        #[clap(short, long, help = "Only rotate blobs whose name starts with the prefix")]
+        prefix: Option<String>,
+        #[clap(flatten)]
+        sink: SinkArgs,
+    },
+}
+
+/// Consumer arguments shared by Consume and Forward
+/// - group_id: Consumer group (overrides kafka_config.json)
+/// - from_beginning/offset/since: Start position (default: committed offsets of the group)
+/// - max_messages/max_bytes/until_end: Termination bounds (default: stop when idle)
+#[derive(Debug, Args)]
+pub struct ConsumerArgs {
+    #[clap(long, help = "Consumer group id (overrides kafka_config.json)")]
+    pub group_id: Option<String>,
+    #[clap(long, help = "Read the topic from the beginning", conflicts_with_all = ["offset", "since"])]
+    pub from_beginning: bool,
+    #[clap(long, help = "Read a single partition from an offset (partition:offset)", value_parser = StartPosition::parse_offset, conflicts_with = "since")]
+    pub offset: Option<StartPosition>,
+    #[clap(long, help = "Read everything since a RFC3339 timestamp (e.g. 2023-03-01T00:00:00Z)", value_parser = StartPosition::parse_since)]
+    pub since: Option<StartPosition>,
+    #[clap(long, help = "Stop after the given number of messages")]
+    pub max_messages: Option<u64>,
+    #[clap(long, help = "Stop once the given number of payload bytes is read")]
+    pub max_bytes: Option<u64>,
+    #[clap(long, help = "Stop once every partition reached its end (as of the start)")]
+    pub until_end: bool,
+}
+
+impl ConsumerArgs {
+
+    /// Builds the consumer options from the arguments
+    pub fn options(&self, idle_timeout: Duration, max_retries: u32) -> ConsumerOptions {
+        ConsumerOptions {
+            idle_timeout,
+            max_retries,
+            group_id: self.group_id.clone(),
+            start: self.start_position(),
+            max_messages: self.max_messages,
+            max_bytes: self.max_bytes,
+            until_end: self.until_end,
+            ..Default::default()
+        }
+    }
+
+    /// Returns the start position selected by the arguments
+    pub fn start_position(&self) -> StartPosition {
+        if self.from_beginning {
+            StartPosition::Beginning
+        } else {
+            self.offset.clone()
+                .or_else(|| self.since.clone())
+                .unwrap_or_default()
+        }
+    }
+}
+
+// Roll age of a continuous forward without a roll bound
+const DEFAULT_ROLL_MINUTES: u64 = 60;
+
+/// Rolling arguments of Forward
+/// - continuous: Keep forwarding until Ctrl-C (instead of stopping when idle)
+/// - roll_records/roll_size_mb/roll_minutes: Roll a new blob after N records, megabytes or minutes
+/// - Without a roll bound, continuous mode rolls every DEFAULT_ROLL_MINUTES (otherwise nothing is written until Ctrl-C)
+#[derive(Debug, Args)]
+pub struct RollArgs {
+    #[clap(long, help = "Keep forwarding until Ctrl-C is pressed instead of stopping when idle")]
+    pub continuous: bool,
+    #[clap(long, help = "Roll a new blob after the given number of records")]
+    pub roll_records: Option<u64>,
+    #[clap(long, help = "Roll a new blob after the given number of megabytes")]
+    pub roll_size_mb: Option<u64>,
+    #[clap(long, help = "Roll a new blob after the given number of minutes")]
+    pub roll_minutes: Option<u64>,
+}
+
+impl RollArgs {
+
+    /// Builds the roll policy from the arguments
+    pub fn policy(&self) -> RollPolicy {
+        let unbounded = self.roll_records.is_none() && self.roll_size_mb.is_none() && self.roll_minutes.is_none();
+        let roll_minutes = if self.continuous && unbounded {
+            warn!("No roll bound set for --continuous: Rolling a new blob every {} minutes", DEFAULT_ROLL_MINUTES);
+            Some(DEFAULT_ROLL_MINUTES)
+        } else {
+            self.roll_minutes
+        };
+
+        RollPolicy {
+            max_records: self.roll_records,
+            max_bytes: self.roll_size_mb.map(|megabytes| megabytes * 1024 * 1024),
+            max_age: roll_minutes.map(|minutes| Duration::from_secs(minutes * 60)),
+        }
+    }
+}
+
+/// Upload arguments shared by Write and Forward
+/// - block_size_mb: Blobs larger than the block size are uploaded as staged blocks (Azure only)
+/// - parallelism: Number of blocks uploaded at the same time
+/// - progress: Print the progress of staged uploads to stderr
+#[derive(Debug, Args)]
+pub struct UploadArgs {
+    #[clap(long, help = "Size of a block in megabytes, larger blobs are uploaded in blocks (resumable)", default_value = "8", value_parser = clap::value_parser!(u64).range(1..=4000))]
+    pub block_size_mb: u64,
+    #[clap(long, help = "Number of blocks uploaded at the same time", default_value = "4", value_parser = clap::value_parser!(u64).range(1..))]
+    pub parallelism: u64,
+    #[clap(long, help = "Print the progress of staged uploads")]
+    pub progress: bool,
+}
+
+impl UploadArgs {
+
+    /// Builds the block options from the arguments
+    pub fn blocks(&self) -> BlockOptions {
+        BlockOptions {
+            block_size: (self.block_size_mb * 1024 * 1024) as usize,
+            parallelism: self.parallelism as usize,
+            progress: self.progress.then(|| Arc::new(print_progress) as ProgressCallback),
+        }
+    }
+}
+
+/// Conflict arguments shared by Write and Forward
+/// - overwrite: Replace existing blobs (default)
+/// - if_not_exists: Keep existing blobs, the write fails
+/// - append_suffix: Write to the first free name (e.g. 0-42-1.ndjson)
+#[derive(Debug, Args)]
+pub struct ConflictArgs {
+    #[clap(long, help = "Overwrite existing blobs (default)", conflicts_with_all = ["if_not_exists", "append_suffix"])]
+    pub overwrite: bool,
+    #[clap(long, help = "Fail instead of overwriting an existing blob", conflicts_with = "append_suffix")]
+    pub if_not_exists: bool,
+    #[clap(long, help = "Append a numeric suffix to the blob name instead of overwriting an existing blob")]
+    pub append_suffix: bool,
+}
+
+impl ConflictArgs {
+
+    /// Returns the conflict policy selected by the arguments
+    pub fn policy(&self) -> ConflictPolicy {
+        if self.if_not_exists {
+            ConflictPolicy::IfNotExists
+        } else if self.append_suffix {
+            ConflictPolicy::AppendSuffix
+        } else {
+            ConflictPolicy::Overwrite
+        }
+    }
+}
+
+/// Sink arguments shared by Write, Read, Forward, Replay and Blobs
+/// - sink: Where the blobs are stored (overrides sink_config.json, default: azure)
+/// - sink_path: Root directory of the local sink
+#[derive(Debug, Args)]
+pub struct SinkArgs {
+    #[clap(long, help = "Sink for the blobs (overrides sink_config.json)", value_enum)]
+    pub sink: Option<SinkKind>,
+    #[clap(long, help = "Root directory of the local sink (overrides sink_config.json)")]
+    pub sink_path: Option<String>,
+}
+
+impl SinkArgs {
+
+    /// Opens the selected sink (fails if sink_config.json can not be read)
+    pub fn store(&self) -> Result<Box<dyn BlobStore>, anyhow::Error> {
+        open_blob_store(self.sink, self.sink_path.as_deref(), BlockOptions::default())
+    }
+
+    /// Opens the selected sink for uploads with the given block size and parallelism
+    pub fn upload_store(&self, upload: &UploadArgs) -> Result<Box<dyn BlobStore>, anyhow::Error> {
+        open_blob_store(self.sink, self.sink_path.as_deref(), upload.blocks())
+    }
+}
+
 // Arguments for the subcommands
 // IMPORTANT: !!! Not in use !!!
 // - Produce: topic, message
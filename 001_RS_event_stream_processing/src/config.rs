@@ -1,22 +1,121 @@
 // Contains structs used to parse configurations
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
+use crate::storage::SinkKind;
+
 // struct for the kafka_key.json file
+// - message_timeout_ms: Delivery timeout of the producer, also bounds the metadata requests of the consumer
+// - session_timeout_ms: Session timeout of the consumer group (librdkafka default if not set)
 #[derive(Serialize, Deserialize, Debug)]
 pub struct KafkaConfig {
     pub bootstrap_servers: String,
     pub group_id: String,
     pub message_timeout_ms: u32,
     pub connection_max_idle_ms: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub session_timeout_ms: Option<u32>,
+}
+
+// Authentication methods for Azure Blob Storage
+// - key: Shared account key (storage_account_key)
+// - sas: SAS token, account or container scoped (sas_token)
+// - connection_string: Full connection string (connection_string)
+// - service_principal: Client credentials of an Azure AD app (tenant_id, client_id, client_secret)
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AzureAuth {
+    Key,
+    Sas,
+    ConnectionString,
+    ServicePrincipal,
 }
 
 // struct for the azure_key.json file
-#[derive(Serialize, Deserialize, Debug)]
+// - auth: Authentication method, defaults to connection_string if one is set and to key otherwise
+// - blob_endpoint: Custom endpoint (e.g. http://127.0.0.1:10000/devstoreaccount1), used instead of the public cloud
+// - connection_string: Replaces account name, credentials and endpoint (e.g. UseDevelopmentStorage=true for Azurite)
+#[derive(Serialize, Deserialize, Debug, Default)]
 pub struct AzureConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auth: Option<AzureAuth>,
+    #[serde(default)]
     pub storage_account_name: String,
+    #[serde(default)]
     pub storage_account_key: String,
+    #[serde(default)]
     pub storage_container: String,
+    #[serde(default)]
     pub storage_blob_name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blob_endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connection_string: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sas_token: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_secret: Option<String>,
+}
+
+// struct for the (optional) sink_config.json file
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SinkConfig {
+    #[serde(default)]
+    pub sink: SinkKind,
+    #[serde(default = "default_local_path")]
+    pub local_path: String,
+}
+
+impl Default for SinkConfig {
+    fn default() -> Self {
+        SinkConfig {
+            sink: SinkKind::default(),
+            local_path: default_local_path(),
+        }
+    }
+}
+
+fn default_local_path() -> String {
+    "exchange-data".to_string()
+}
+
+// struct for the (optional) encryption_config.json file
+// - active_key: Id of the key-encryption key used for new blobs and key rotations
+// - keys: Key-encryption keys by id (base64 encoded 256-bit keys), retired keys are kept to read older blobs
+#[derive(Serialize, Deserialize, Debug)]
+pub struct EncryptionConfig {
+    pub active_key: String,
+    pub keys: BTreeMap<String, String>,
+}
+
+// struct for the (optional) retention_config.json file
+// - rules: Retention rules of exchange blobs prune, the rule with the longest matching prefix applies to a blob
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RetentionConfig {
+    #[serde(default)]
+    pub rules: Vec<RetentionRuleConfig>,
+}
+
+// A retention rule of retention_config.json
+// - container_name: Container the rule applies to (all containers if not set)
+// - prefix: Blobs the rule applies to (all blobs if empty)
+// - older_than: Blobs older than this age are deleted (e.g. 30d, see retention::parse_age)
+// - keep_last: Number of newest blobs that are always kept
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct RetentionRuleConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub older_than: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_last: Option<usize>,
 }
 
 // structs for the api_key.json file
@@ -34,4 +34,174 @@ impl KeyError {
            Self::NotFound => "Not Found, please check if entry exists",
        }
    }
-}
\ No newline at end of file
+}
+
+// Custom error types for the Azure credentials in azure_config.json
+// - MissingConfig: azure_config.json can not be read or parsed
+// - MissingField: The selected auth method requires a field that is not set
+// - MalformedField: A field is set, but can not be used (e.g. invalid base64)
+pub enum CredentialError {
+    MissingConfig(String),
+    MissingField(&'static str),
+    MalformedField(&'static str, String),
+}
+
+impl Display for CredentialError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl Debug for CredentialError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl std::error::Error for CredentialError {}
+
+impl CredentialError {
+   fn message(&self) -> String {
+       match self {
+           Self::MissingConfig(reason) => format!("Missing Azure config, please check azure_config.json: {}", reason),
+           Self::MissingField(field) => format!("Missing Credential, please set {} in azure_config.json", field),
+           Self::MalformedField(field, reason) => format!("Malformed Credential, please check {} in azure_config.json: {}", field, reason),
+       }
+   }
+}
+
+// Custom error types for the content integrity of blobs
+// - Mismatch: The MD5 hash of the transferred bytes differs from the hash stored with the blob
+pub enum IntegrityError {
+    Mismatch { blob_name: String, expected: String, actual: String },
+}
+
+impl Display for IntegrityError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl Debug for IntegrityError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl std::error::Error for IntegrityError {}
+
+impl IntegrityError {
+   fn message(&self) -> String {
+       match self {
+           Self::Mismatch { blob_name, expected, actual } => format!("Integrity check failed for blob {}: expected MD5 {}, got {}", blob_name, expected, actual),
+       }
+   }
+}
+
+// Custom error types for conditional writes of blobs
+// - BlobExists: The blob was not written because it already exists (see ConflictPolicy)
+pub enum ConflictError {
+    BlobExists(String),
+}
+
+impl Display for ConflictError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl Debug for ConflictError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl std::error::Error for ConflictError {}
+
+impl ConflictError {
+   fn message(&self) -> String {
+       match self {
+           Self::BlobExists(blob_name) => format!("Blob {} already exists, use --overwrite or --append-suffix to write it anyway", blob_name),
+       }
+   }
+}
+
+// Custom error types for the client-side encryption of blobs (see encryption_config.json)
+// - MissingConfig: Encryption was requested, but encryption_config.json can not be read
+// - UnknownKey: The key-encryption key is not configured (e.g. removed after a rotation)
+// - MalformedKey: A configured key is not a base64 encoded 256-bit key
+// - MalformedMetadata: The encryption metadata of a blob is missing or invalid
+// - EncryptionFailed: The content can not be encrypted (e.g. too large for AES-GCM)
+// - DecryptionFailed: The blob or its data key does not match the key (wrong key or modified content)
+pub enum EncryptionError {
+    MissingConfig(String),
+    UnknownKey(String),
+    MalformedKey(String, String),
+    MalformedMetadata(&'static str),
+    EncryptionFailed(String),
+    DecryptionFailed(String),
+}
+
+impl Display for EncryptionError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl Debug for EncryptionError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl std::error::Error for EncryptionError {}
+
+impl EncryptionError {
+   fn message(&self) -> String {
+       match self {
+           Self::MissingConfig(reason) => format!("Missing encryption config, please check encryption_config.json: {}", reason),
+           Self::UnknownKey(key_id) => format!("Unknown key {}, please add it to encryption_config.json", key_id),
+           Self::MalformedKey(key_id, reason) => format!("Malformed key {} in encryption_config.json: {}", key_id, reason),
+           Self::MalformedMetadata(name) => format!("Missing or malformed encryption metadata: {}", name),
+           Self::EncryptionFailed(reason) => format!("Encryption failed: {}", reason),
+           Self::DecryptionFailed(blob_name) => format!("Decryption of blob {} failed: wrong key or modified content", blob_name),
+       }
+   }
+}
+
+// Custom error types for appending to blobs (see BlobStore::append)
+// - BlockLimit: The append blob has reached its maximum number of blocks (50,000), nothing was appended
+// - NotAppendBlob: The blob exists, but is not an append blob (e.g. written without --append)
+// - PositionMismatch: The blob does not have the expected size (e.g. another writer appended), nothing was appended
+// - LineTooLong: A line does not fit into a block (blob name, line length, block size), nothing was appended
+pub enum AppendError {
+    BlockLimit(String),
+    NotAppendBlob(String),
+    PositionMismatch(String),
+    LineTooLong(String, usize, usize),
+}
+
+impl Display for AppendError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl Debug for AppendError {
+   fn fmt(&self, f: &mut Formatter) -> Result {
+       write!(f, "{}", self.message())
+   }
+}
+
+impl std::error::Error for AppendError {}
+
+impl AppendError {
+   fn message(&self) -> String {
+       match self {
+           Self::BlockLimit(blob_name) => format!("Blob {} has reached the maximum number of blocks", blob_name),
+           Self::NotAppendBlob(blob_name) => format!("Blob {} is not an append blob, please choose another blob name", blob_name),
+           Self::PositionMismatch(blob_name) => format!("Blob {} was changed since its size was read", blob_name),
+           Self::LineTooLong(blob_name, length, block_size) => format!("Line of {} bytes can not be appended to blob {}: Lines are limited to {} bytes, please forward without --append", length, blob_name, block_size),
+       }
+   }
+}
@@ -0,0 +1,249 @@
+/*
+    This file contains the CSV format of exported batches
+    Every JSON payload becomes a row, the columns are selected by dotted paths (e.g. rates.USD)
+*/
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use log::warn;
+use serde_json::Value;
+
+use crate::export::parquet::RAW_COLUMN;
+use crate::kafka::report::ConsumedMessage;
+
+/// Encoder of CSV blobs
+/// - fields: Dotted paths of the columns (e.g. base,end_date,rates.USD), a number selects an array element (e.g. items.0)
+/// - A dot or backslash that is part of a key is escaped with a backslash (e.g. rates.2023\.03 for the key "2023.03")
+/// - Without configured fields, the columns are all paths to values of the payloads. They are kept between batches,
+///   so that the blobs of a forward run share their columns (new paths are appended in the order of their names)
+/// - No payload is lost: Payloads that are no JSON object are kept as text in the column RAW_COLUMN
+///   (appended when it is needed first)
+#[derive(Debug, Clone, Default)]
+pub struct CsvEncoder {
+    fields: Vec<String>,
+    fixed: bool,
+    raw: bool,
+}
+
+impl CsvEncoder {
+
+    /// Creates an encoder with configured fields (see parse_field) or one that infers the fields (empty)
+    pub fn new(fields: Vec<String>) -> Self {
+        CsvEncoder {
+            fixed: !fields.is_empty(),
+            fields,
+            raw: false,
+        }
+    }
+
+    /// Returns the columns of the last blob
+    pub fn columns(&self) -> Vec<&str> {
+        let raw = self.raw.then_some(RAW_COLUMN);
+        self.fields.iter().map(String::as_str).chain(raw).collect()
+    }
+
+    /// Encodes the payloads of the messages as CSV with a header row (one row per message)
+    /// - Missing fields and null are empty cells, objects and arrays are written as compact JSON
+    pub fn encode(&mut self, messages: &[ConsumedMessage]) -> Vec<u8> {
+
+        let payloads: Vec<Option<Value>> = messages.iter()
+            .map(|message| match serde_json::from_slice(&message.payload) {
+                Ok(payload @ Value::Object(_)) => Some(payload),
+                _ => None,
+            })
+            .collect();
+
+        if !self.fixed {
+            let mut paths = BTreeSet::new();
+            for payload in payloads.iter().flatten() {
+                collect_paths(payload, "", &mut paths);
+            }
+            for path in paths {
+                if !self.fields.contains(&path) {
+                    self.fields.push(path);
+                }
+            }
+        }
+
+        let raw_rows = payloads.iter().filter(|payload| payload.is_none()).count();
+        if raw_rows > 0 {
+            warn!("Payload(s) that are no JSON object: {} (kept as text in column {})", raw_rows, RAW_COLUMN);
+            self.raw = true;
+        }
+
+        let paths: Vec<Vec<String>> = self.fields.iter().map(|field| segments(field)).collect();
+        let mut content = Vec::new();
+        write_row(&mut content, self.columns().into_iter().map(Cow::Borrowed));
+        for (message, payload) in messages.iter().zip(&payloads) {
+            let cells = paths.iter().map(|path| cell(payload.as_ref().and_then(|payload| lookup(payload, path))));
+            let raw = self.raw.then(|| match payload {
+                Some(_) => Cow::Borrowed(""),
+                None => String::from_utf8_lossy(&message.payload),
+            });
+            write_row(&mut content, cells.chain(raw));
+        }
+        content
+    }
+}
+
+/// Parses the fields of the command line (e.g. base,end_date,rates.USD)
+/// - An odd number of trailing backslashes escapes nothing, an even number are escaped backslashes (e.g. a\\)
+pub fn parse_field(field: &str) -> Result<String, String> {
+    let field = field.trim();
+    let trailing_backslashes = field.chars().rev().take_while(|character| *character == '\\').count();
+    if field.is_empty() || segments(field).iter().any(String::is_empty) || trailing_backslashes % 2 == 1 {
+        return Err(format!("Invalid field {:?}: Expected a dotted path (e.g. rates.USD)", field));
+    }
+    Ok(field.to_string())
+}
+
+/// Splits a dotted path into its keys (\. and \\ are a dot and a backslash of the key)
+fn segments(path: &str) -> Vec<String> {
+    let mut segments = vec![String::new()];
+    let mut characters = path.chars();
+    while let Some(character) = characters.next() {
+        match character {
+            '\\' => segments.last_mut().unwrap().extend(characters.next()),
+            '.' => segments.push(String::new()),
+            character => segments.last_mut().unwrap().push(character),
+        }
+    }
+    segments
+}
+
+/// Escapes the dots and backslashes of a key for a dotted path
+fn escape_key(key: &str) -> Cow<'_, str> {
+    if key.contains(['.', '\\']) {
+        Cow::Owned(key.replace('\\', "\\\\").replace('.', "\\."))
+    } else {
+        Cow::Borrowed(key)
+    }
+}
+
+/// Returns the value at a path (see segments)
+/// - A key selects the entry of an object or, if it is a number, the element of an array
+fn lookup<'a>(value: &'a Value, path: &[String]) -> Option<&'a Value> {
+    path.iter().try_fold(value, |value, segment| match value {
+        Value::Object(map) => map.get(segment),
+        Value::Array(elements) => segment.parse::<usize>().ok().and_then(|index| elements.get(index)),
+        _ => None,
+    })
+}
+
+/// Collects the paths of all values below objects (arrays are values)
+fn collect_paths(value: &Value, prefix: &str, paths: &mut BTreeSet<String>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                let key = escape_key(key);
+                let path = if prefix.is_empty() { key.to_string() } else { format!("{}.{}", prefix, key) };
+                collect_paths(value, &path, paths);
+            }
+        },
+        _ if !prefix.is_empty() => {
+            paths.insert(prefix.to_string());
+        },
+        _ => {},
+    }
+}
+
+/// Returns the text of a cell
+fn cell(value: Option<&Value>) -> Cow<'_, str> {
+    match value {
+        None | Some(Value::Null) => Cow::Borrowed(""),
+        Some(Value::String(text)) => Cow::Borrowed(text),
+        Some(value) => Cow::Owned(value.to_string()),
+    }
+}
+
+/// Writes a row, cells with a comma, a quote or a line break are quoted (RFC 4180)
+fn write_row<'a>(content: &mut Vec<u8>, cells: impl Iterator<Item = Cow<'a, str>>) {
+    for (index, cell) in cells.enumerate() {
+        if index > 0 {
+            content.push(b',');
+        }
+        if cell.contains([',', '"', '\n', '\r']) {
+            content.push(b'"');
+            content.extend_from_slice(cell.replace('"', "\"\"").as_bytes());
+            content.push(b'"');
+        } else {
+            content.extend_from_slice(cell.as_bytes());
+        }
+    }
+    content.push(b'\n');
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_helper::messages;
+
+    fn encode(payloads: &[&str], fields: &str) -> String {
+        let fields = if fields.is_empty() { Vec::new() } else { fields.split(',').map(|field| parse_field(field).unwrap()).collect() };
+        String::from_utf8(CsvEncoder::new(fields).encode(&messages(payloads))).unwrap()
+    }
+
+    #[test]
+    fn test_encode_fields() {
+        let content = encode(&[
+            "{\"base\":\"EUR\",\"end_date\":\"2023-03-03\",\"rates\":{\"USD\":1.06}}",
+            "{\"base\":\"USD\",\"rates\":{\"EUR\":0.94}}",
+        ], "base,end_date,rates.USD");
+
+        assert_eq!(content, "base,end_date,rates.USD\nEUR,2023-03-03,1.06\nUSD,,\n");
+    }
+
+    #[test]
+    fn test_encode_nested_values() {
+        let content = encode(&[
+            "{\"symbols\":[\"USD\",\"GBP\"],\"note\":\"a, \\\"b\\\"\",\"rates\":{\"2023-03-01\":{\"USD\":1.06}},\"success\":true}",
+        ], "symbols,symbols.1,symbols.2,note,rates.2023-03-01,success");
+
+        assert_eq!(content,
+            "symbols,symbols.1,symbols.2,note,rates.2023-03-01,success\n\"[\"\"USD\"\",\"\"GBP\"\"]\",GBP,,\"a, \"\"b\"\"\",\"{\"\"USD\"\":1.06}\",true\n");
+    }
+
+    #[test]
+    fn test_encode_inferred_fields() {
+        let mut encoder = CsvEncoder::default();
+        let content = encoder.encode(&messages(&[
+            "{\"base\":\"EUR\",\"rates\":{\"USD\":1.06}}",
+            "{\"rates\":{\"GBP\":0.88},\"empty\":{}}",
+        ]));
+        assert_eq!(String::from_utf8(content).unwrap(), "base,empty,rates.GBP,rates.USD\nEUR,,,1.06\n,{},0.88,\n");
+
+        // The columns are kept for the next batch, new columns are appended
+        let content = encoder.encode(&messages(&["{\"rates\":{\"CHF\":0.99,\"USD\":1.07}}"]));
+        assert_eq!(String::from_utf8(content).unwrap(), "base,empty,rates.GBP,rates.USD,rates.CHF\n,,,1.07,0.99\n");
+    }
+
+    #[test]
+    fn test_raw_payloads() {
+        let mut encoder = CsvEncoder::new(vec!["base".to_string()]);
+        let content = encoder.encode(&messages(&["{\"base\":\"EUR\"}", "not, json", "42"]));
+        assert_eq!(String::from_utf8(content).unwrap(), format!("base,{}\nEUR,\n,\"not, json\"\n,42\n", RAW_COLUMN));
+
+        // The raw column is kept for the next batch
+        let content = encoder.encode(&messages(&["{\"base\":\"USD\"}"]));
+        assert_eq!(String::from_utf8(content).unwrap(), format!("base,{}\nUSD,\n", RAW_COLUMN));
+    }
+
+    #[test]
+    fn test_dotted_keys() {
+        let payload = "{\"rates\":{\"2023.03\":1.06,\"2023\":{\"03\":1.07}},\"a\\\\b\":1}";
+        assert_eq!(encode(&[payload], "rates.2023\\.03,rates.2023.03,a\\\\b"), "rates.2023\\.03,rates.2023.03,a\\\\b\n1.06,1.07,1\n");
+        assert_eq!(encode(&[payload], ""), "a\\\\b,rates.2023.03,rates.2023\\.03\n1,1.07,1.06\n");
+
+        assert!(parse_field("rates..USD").is_err());
+        assert!(parse_field("rates.USD\\").is_err());
+        assert!(parse_field("a\\\\\\").is_err());
+
+        // An escaped trailing backslash is the last character of the key
+        assert_eq!(parse_field("a\\\\"), Ok("a\\\\".to_string()));
+        assert_eq!(encode(&["{\"a\\\\\":1}"], "a\\\\"), "a\\\\\n1\n");
+    }
+}
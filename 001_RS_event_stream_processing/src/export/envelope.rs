@@ -0,0 +1,186 @@
+/*
+    This file contains the envelope that wraps a Kafka message for the export
+    It keeps the Kafka metadata next to the payload for downstream jobs (e.g. Azure Data Factory)
+*/
+
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use crate::kafka::producer::OutgoingRecord;
+use crate::kafka::report::ConsumedMessage;
+
+/// A single exported record
+/// - topic, partition, offset, key, timestamp: Kafka metadata of the message
+/// - headers: Kafka headers as [name, value] pairs in their original order, so that repeated names are kept
+///   (value is null if the header has no value, envelopes with a headers object are still read)
+/// - payload: The payload as embedded JSON if it parses, otherwise as string
+/// - payload_kind: Whether the payload was JSON or text, so that a JSON string ("EUR") and the text EUR
+///   are told apart on replay (envelopes without it are read as text)
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Envelope {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<String>,
+    pub timestamp: Option<i64>,
+    #[serde(deserialize_with = "deserialize_headers")]
+    pub headers: Vec<(String, Option<String>)>,
+    pub payload: Value,
+    #[serde(default)]
+    pub payload_kind: PayloadKind,
+}
+
+/// Kind of an embedded payload
+/// - Json: The payload is the JSON value
+/// - Text: The payload is the (lossy) utf-8 string, it was no valid JSON
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadKind {
+    Json,
+    #[default]
+    Text,
+}
+
+impl Envelope {
+
+    /// Wraps the message in an envelope
+    pub fn from_message(message: &ConsumedMessage) -> Self {
+        let (payload, payload_kind) = embed_payload(&message.payload);
+
+        Envelope {
+            topic: message.metadata.topic.clone(),
+            partition: message.metadata.partition,
+            offset: message.metadata.offset,
+            key: message.metadata.key.clone(),
+            timestamp: message.metadata.timestamp,
+            headers: message.headers.clone(),
+            payload,
+            payload_kind,
+        }
+    }
+
+    /// Unwraps the envelope into a record that can be produced again (e.g. replay)
+    /// - Key and headers are restored, a text payload is restored as it was
+    /// - A JSON payload is restored as compact JSON (the original whitespace is lost), a JSON string keeps its quotes
+    pub fn into_record(self) -> OutgoingRecord {
+        let payload = match (self.payload, self.payload_kind) {
+            (Value::String(payload), PayloadKind::Text) => payload.into_bytes(),
+            (payload, _) => payload.to_string().into_bytes(),
+        };
+
+        OutgoingRecord {
+            key: self.key,
+            headers: self.headers,
+            payload,
+        }
+    }
+}
+
+/// Reads the headers of an envelope
+/// - A list of [name, value] pairs (see Envelope)
+/// - An object of name/value, as written by earlier versions (non-string values are kept as JSON)
+fn deserialize_headers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<(String, Option<String>)>, D::Error> {
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Headers {
+        Pairs(Vec<(String, Option<String>)>),
+        Object(serde_json::Map<String, Value>),
+    }
+
+    Ok(match Headers::deserialize(deserializer)? {
+        Headers::Pairs(headers) => headers,
+        Headers::Object(headers) => headers.into_iter()
+            .map(|(name, value)| match value {
+                Value::Null => (name, None),
+                Value::String(value) => (name, Some(value)),
+                value => (name, Some(value.to_string())),
+            })
+            .collect(),
+    })
+}
+
+/// Embeds the payload as JSON value
+/// - Valid JSON is embedded as is (no escaped string)
+/// - Anything else is embedded as (lossy) utf-8 string
+fn embed_payload(payload: &[u8]) -> (Value, PayloadKind) {
+    match serde_json::from_slice(payload) {
+        Ok(value) => (value, PayloadKind::Json),
+        Err(_) => (Value::String(String::from_utf8_lossy(payload).to_string()), PayloadKind::Text),
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_helper;
+
+    // Message of partition 1 at offset 42 with a key and headers
+    fn message(payload: &str) -> ConsumedMessage {
+        let mut message = test_helper::message(1, 42, payload);
+        message.metadata.key = Some("key".to_string());
+        message.headers = vec![("source".to_string(), Some("api".to_string())), ("empty".to_string(), None)];
+        message
+    }
+
+    #[test]
+    fn test_envelope_embeds_json_payload() {
+        let envelope = Envelope::from_message(&message("{\"base\": \"EUR\"}"));
+
+        assert_eq!(envelope.payload, serde_json::json!({ "base": "EUR" }));
+        assert_eq!(envelope.partition, 1);
+        assert_eq!(envelope.offset, 42);
+        assert_eq!(envelope.headers, vec![("source".to_string(), Some("api".to_string())), ("empty".to_string(), None)]);
+    }
+
+    #[test]
+    fn test_envelope_keeps_repeated_headers() {
+        let mut message = message("{}");
+        message.headers = vec![("trace".to_string(), Some("a".to_string())), ("trace".to_string(), Some("b".to_string()))];
+
+        let json = serde_json::to_string(&Envelope::from_message(&message)).unwrap();
+        assert!(json.contains("\"headers\":[[\"trace\",\"a\"],[\"trace\",\"b\"]]"));
+
+        let envelope: Envelope = serde_json::from_str(&json).unwrap();
+        assert_eq!(envelope.into_record().headers, message.headers);
+    }
+
+    #[test]
+    fn test_envelope_reads_header_object() {
+        let json = "{\"topic\":\"rates\",\"partition\":0,\"offset\":7,\"key\":null,\"timestamp\":null,\"headers\":{\"source\":\"api\",\"empty\":null},\"payload\":{}}";
+        let envelope: Envelope = serde_json::from_str(json).unwrap();
+        assert_eq!(envelope.headers, vec![("empty".to_string(), None), ("source".to_string(), Some("api".to_string()))]);
+    }
+
+    #[test]
+    fn test_envelope_into_record() {
+        let record = Envelope::from_message(&message("{\"base\": \"EUR\"}")).into_record();
+        assert_eq!(record.key.as_deref(), Some("key"));
+        assert_eq!(record.payload, b"{\"base\":\"EUR\"}");
+        assert!(record.headers.contains(&("source".to_string(), Some("api".to_string()))));
+        assert!(record.headers.contains(&("empty".to_string(), None)));
+
+        let record = Envelope::from_message(&message("not json")).into_record();
+        assert_eq!(record.payload, b"not json");
+    }
+
+    #[test]
+    fn test_envelope_keeps_text_payload() {
+        let envelope = Envelope::from_message(&message("not json"));
+        assert_eq!(envelope.payload, Value::String("not json".to_string()));
+        assert_eq!(envelope.payload_kind, PayloadKind::Text);
+    }
+
+    #[test]
+    fn test_envelope_keeps_json_string_payload() {
+        let envelope = Envelope::from_message(&message("\"EUR\""));
+        assert_eq!(envelope.payload_kind, PayloadKind::Json);
+
+        // The kind is kept in the export, so the JSON string is replayed with its quotes
+        let envelope: Envelope = serde_json::from_str(&serde_json::to_string(&envelope).unwrap()).unwrap();
+        assert_eq!(envelope.into_record().payload, b"\"EUR\"");
+    }
+}
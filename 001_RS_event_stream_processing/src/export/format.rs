@@ -0,0 +1,158 @@
+/*
+    This file contains the formats used to export a batch of Kafka messages
+*/
+
+use arrow_schema::SchemaRef;
+
+use crate::export::csv::CsvEncoder;
+use crate::export::envelope::Envelope;
+use crate::export::parquet::ParquetEncoder;
+use crate::kafka::report::ConsumedMessage;
+
+/// Blob format of an exported batch
+/// - Ndjson: One envelope (see Envelope) per line
+/// - JsonArray: All envelopes as one JSON array
+/// - Raw: The payloads as they are, one per line
+/// - Parquet: The JSON payloads as rows of a Parquet file (see ParquetEncoder)
+/// - Csv: The JSON payloads as rows with a header row (see CsvEncoder)
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum ExportFormat {
+    #[default]
+    Ndjson,
+    JsonArray,
+    Raw,
+    Parquet,
+    Csv,
+}
+
+impl ExportFormat {
+
+    /// Encodes the messages in the format
+    /// - A Parquet file gets the schema inferred from the messages (see Encoder for a schema that spans batches)
+    /// - A CSV file gets all fields of the messages (see Encoder for columns that span batches)
+    pub fn encode(&self, messages: &[ConsumedMessage]) -> Result<Vec<u8>, anyhow::Error> {
+        Ok(match self {
+            ExportFormat::Ndjson => {
+                let mut content = Vec::new();
+                for message in messages {
+                    // Serializing an envelope cannot fail (string keys only)
+                    serde_json::to_writer(&mut content, &Envelope::from_message(message)).unwrap();
+                    content.push(b'\n');
+                }
+                content
+            },
+            ExportFormat::JsonArray => {
+                let envelopes: Vec<Envelope> = messages.iter().map(Envelope::from_message).collect();
+                serde_json::to_vec(&envelopes).unwrap()
+            },
+            ExportFormat::Raw => {
+                let mut content = Vec::new();
+                for message in messages {
+                    content.extend_from_slice(&message.payload);
+                    content.push(b'\n');
+                }
+                content
+            },
+            ExportFormat::Parquet => ParquetEncoder::default().encode(messages)?,
+            ExportFormat::Csv => CsvEncoder::default().encode(messages),
+        })
+    }
+
+    /// Returns true if the format has one record per line, so that batches can be appended to a blob
+    pub fn is_line_based(&self) -> bool {
+        matches!(self, ExportFormat::Ndjson | ExportFormat::Raw)
+    }
+
+    /// Returns the file extension of the format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::JsonArray => "json",
+            ExportFormat::Raw => "txt",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Csv => "csv",
+        }
+    }
+
+    /// Returns the content type of the format
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::JsonArray => "application/json",
+            ExportFormat::Raw => "text/plain",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+            ExportFormat::Csv => "text/csv",
+        }
+    }
+}
+
+/// Encoder of the batches of a forward run
+/// - Keeps the state of formats that span batches (the schema of Parquet blobs, the columns of CSV blobs)
+#[derive(Debug, Clone)]
+pub struct Encoder {
+    format: ExportFormat,
+    parquet: ParquetEncoder,
+    csv: CsvEncoder,
+}
+
+impl Encoder {
+
+    /// Creates an encoder for the format
+    /// - schema: Schema of Parquet blobs (inferred from the payloads if None)
+    /// - fields: Dotted paths of the CSV columns (all fields of the batch if empty)
+    pub fn new(format: ExportFormat, schema: Option<SchemaRef>, fields: Vec<String>) -> Self {
+        Encoder {
+            format,
+            parquet: ParquetEncoder::new(schema),
+            csv: CsvEncoder::new(fields),
+        }
+    }
+
+    /// Encodes the messages of a batch (see ExportFormat::encode)
+    pub fn encode(&mut self, messages: &[ConsumedMessage]) -> Result<Vec<u8>, anyhow::Error> {
+        match self.format {
+            ExportFormat::Parquet => self.parquet.encode(messages),
+            ExportFormat::Csv => Ok(self.csv.encode(messages)),
+            format => format.encode(messages),
+        }
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_helper;
+
+    fn messages() -> Vec<ConsumedMessage> {
+        test_helper::messages(&["{\"id\":1}", "{\"id\":2}"])
+    }
+
+    #[test]
+    fn test_encode_ndjson() {
+        let content = String::from_utf8(ExportFormat::Ndjson.encode(&messages()).unwrap()).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+
+        assert_eq!(lines.len(), 2);
+        let envelope: Envelope = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(envelope.offset, 1);
+        assert_eq!(envelope.payload, serde_json::json!({ "id": 2 }));
+    }
+
+    #[test]
+    fn test_encode_json_array() {
+        let content = ExportFormat::JsonArray.encode(&messages()).unwrap();
+        let envelopes: Vec<Envelope> = serde_json::from_slice(&content).unwrap();
+
+        assert_eq!(envelopes.len(), 2);
+        assert_eq!(envelopes[0].payload, serde_json::json!({ "id": 1 }));
+    }
+
+    #[test]
+    fn test_encode_raw() {
+        let content = ExportFormat::Raw.encode(&messages()).unwrap();
+        assert_eq!(content, b"{\"id\":1}\n{\"id\":2}\n");
+    }
+}
@@ -0,0 +1,195 @@
+/*
+    This file contains the metadata and index tags of exported blobs
+    The metadata describes where the content of a blob comes from, so that it can be filtered without opening it
+*/
+
+use std::collections::BTreeMap;
+
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::kafka::report::ConsumeReport;
+
+// Header of a produced message holding the name of the API it was requested from (see Ingest)
+pub const API_NAME_HEADER: &str = "api_name";
+
+// Metadata holding the time a blob was written (see base_metadata), kept when only the metadata changes
+pub const INGESTED_AT_METADATA: &str = "ingested_at";
+
+// Maximum number of index tags of a blob
+const MAX_TAGS: usize = 10;
+
+/// Returns the metadata every written blob carries
+/// - exchange_version: Version of the application that wrote the blob
+/// - ingested_at: Time of the write (RFC3339, UTC)
+pub fn base_metadata() -> BTreeMap<String, String> {
+    BTreeMap::from([
+        ("exchange_version".to_string(), env!("CARGO_PKG_VERSION").to_string()),
+        (INGESTED_AT_METADATA.to_string(), OffsetDateTime::now_utc().format(&Rfc3339).unwrap_or_default()),
+    ])
+}
+
+/// Returns the metadata of a forwarded batch (in addition to base_metadata)
+/// - source_topic: Topic the messages were read from
+/// - partitions: Offset range of every partition (e.g. 0:5-42,1:0-9)
+/// - message_count: Number of messages in the blob
+/// - api_name: APIs the messages were requested from (api_name header of Ingest), if any
+///   Metadata is sent as HTTP headers, so the names are percent-encoded (see metadata_value), blank names are skipped
+pub fn batch_metadata(topic: &str, batch: &ConsumeReport) -> BTreeMap<String, String> {
+    let mut metadata = base_metadata();
+
+    let partitions = batch.partitions.iter()
+        .map(|(partition, summary)| format!("{}:{}-{}", partition, summary.first_offset, summary.last_offset))
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let mut api_names: Vec<String> = batch.messages.iter()
+        .flat_map(|message| message.headers.iter())
+        .filter(|(name, _)| name == API_NAME_HEADER)
+        .filter_map(|(_, value)| value.as_deref())
+        .filter_map(metadata_value)
+        .collect();
+    api_names.sort();
+    api_names.dedup();
+
+    metadata.insert("source_topic".to_string(), topic.to_string());
+    metadata.insert("partitions".to_string(), partitions);
+    metadata.insert("message_count".to_string(), batch.total_messages.to_string());
+    if !api_names.is_empty() {
+        metadata.insert("api_name".to_string(), api_names.join(","));
+    }
+
+    metadata
+}
+
+/// Returns the value as it can be stored in the metadata, None if it is blank
+/// - Metadata values must be printable ASCII, other bytes are percent-encoded (UTF-8), as are % and the separator ,
+fn metadata_value(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        match byte {
+            b'%' | b',' => encoded.push_str(&format!("%{:02X}", byte)),
+            0x20..=0x7E => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    Some(encoded)
+}
+
+/// Returns the metadata of a written file (in addition to base_metadata)
+/// - source_file: Path of the file, percent-encoded like api_name (see metadata_value)
+pub fn file_metadata(path: &str) -> BTreeMap<String, String> {
+    let mut metadata = base_metadata();
+    if let Some(source_file) = metadata_value(path) {
+        metadata.insert("source_file".to_string(), source_file);
+    }
+    metadata
+}
+
+/// Returns the metadata of an append blob (in addition to base_metadata)
+/// - source_topic: Topic the messages are read from
+/// - The metadata is set when the blob is created, so it holds nothing that changes with later appends
+pub fn append_metadata(topic: &str) -> BTreeMap<String, String> {
+    let mut metadata = base_metadata();
+    metadata.insert("source_topic".to_string(), topic.to_string());
+    metadata
+}
+
+/// Parses a blob index tag (key=value)
+/// - Keys have 1 to 128, values up to 256 characters
+/// - Allowed characters: letters, digits, space and + - . / : = _
+pub fn parse_tag(tag: &str) -> Result<(String, String), String> {
+    let (key, value) = tag.split_once('=')
+        .ok_or_else(|| format!("Invalid tag {}: Expected key=value", tag))?;
+
+    let allowed = |text: &str| text.chars().all(|character| character.is_ascii_alphanumeric() || " +-./:=_".contains(character));
+
+    if key.is_empty() || key.len() > 128 || !allowed(key) {
+        return Err(format!("Invalid tag key {:?}: 1-128 letters, digits or ' +-./:=_'", key));
+    }
+    if value.len() > 256 || !allowed(value) {
+        return Err(format!("Invalid tag value {:?}: up to 256 letters, digits or ' +-./:=_'", value));
+    }
+
+    Ok((key.to_string(), value.to_string()))
+}
+
+/// Collects the parsed tags, a blob can have at most 10 tags
+pub fn collect_tags(tags: &[(String, String)]) -> Result<BTreeMap<String, String>, String> {
+    let tags: BTreeMap<String, String> = tags.iter().cloned().collect();
+    if tags.len() > MAX_TAGS {
+        return Err(format!("Too many tags: {} (maximum: {})", tags.len(), MAX_TAGS));
+    }
+    Ok(tags)
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_helper;
+    use crate::kafka::report::ConsumedMessage;
+
+    fn message(partition: i32, offset: i64, api_name: Option<&str>) -> ConsumedMessage {
+        let mut message = test_helper::message(partition, offset, "{}");
+        message.headers = api_name.map(|name| vec![(API_NAME_HEADER.to_string(), Some(name.to_string()))]).unwrap_or_default();
+        message
+    }
+
+    #[test]
+    fn test_batch_metadata() {
+        let mut batch = ConsumeReport::default();
+        batch.add(message(0, 5, Some("exchangerates")));
+        batch.add(message(0, 42, Some("exchangerates")));
+        batch.add(message(1, 0, None));
+
+        let metadata = batch_metadata("rates", &batch);
+        assert_eq!(metadata["source_topic"], "rates");
+        assert_eq!(metadata["partitions"], "0:5-42,1:0-0");
+        assert_eq!(metadata["message_count"], "3");
+        assert_eq!(metadata["api_name"], "exchangerates");
+        assert_eq!(metadata["exchange_version"], env!("CARGO_PKG_VERSION"));
+        assert!(metadata.contains_key("ingested_at"));
+    }
+
+    #[test]
+    fn test_batch_metadata_encoding() {
+        let mut batch = ConsumeReport::default();
+        batch.add(message(0, 0, Some("wechselkurse-ä")));
+        batch.add(message(0, 1, Some("rates,eu")));
+        batch.add(message(0, 2, Some(" ")));
+        batch.add(message(0, 3, Some("line\nbreak")));
+
+        let metadata = batch_metadata("rates", &batch);
+        assert_eq!(metadata["api_name"], "line%0Abreak,rates%2Ceu,wechselkurse-%C3%A4");
+    }
+
+    #[test]
+    fn test_file_metadata() {
+        assert_eq!(file_metadata("data/rates.json")["source_file"], "data/rates.json");
+        assert_eq!(file_metadata("data/wechselkurse-ä.json")["source_file"], "data/wechselkurse-%C3%A4.json");
+    }
+
+    #[test]
+    fn test_parse_tag() {
+        assert_eq!(parse_tag("project=fx-rates").unwrap(), ("project".to_string(), "fx-rates".to_string()));
+        assert_eq!(parse_tag("query=base=EUR").unwrap(), ("query".to_string(), "base=EUR".to_string()));
+        assert!(parse_tag("project").is_err());
+        assert!(parse_tag("=value").is_err());
+        assert!(parse_tag("owner=a&b").is_err());
+    }
+
+    #[test]
+    fn test_collect_tags() {
+        let tags: Vec<(String, String)> = (0..11).map(|index| (format!("key{}", index), "value".to_string())).collect();
+        assert!(collect_tags(&tags[..10]).is_ok());
+        assert!(collect_tags(&tags).is_err());
+    }
+}
@@ -0,0 +1,9 @@
+pub mod csv;
+pub mod envelope;
+pub mod format;
+pub mod metadata;
+pub mod parquet;
+pub mod rolling;
+
+#[cfg(test)]
+mod test_helper;
@@ -0,0 +1,482 @@
+/*
+    This file contains the Parquet format of exported batches
+    Every JSON payload becomes a row, the columns are inferred from the payloads or taken from a JSON Schema
+*/
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use arrow_json::reader::ReaderBuilder;
+use arrow_schema::{DataType, Field, FieldRef, Fields, Schema, SchemaRef};
+use log::{info, warn};
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::file::properties::WriterProperties;
+use serde_json::{Map, Value};
+
+use crate::kafka::report::ConsumedMessage;
+
+// Maximum number of rows of a row group
+const ROW_GROUP_SIZE: usize = 64 * 1024;
+
+// Column with the payload (as text) of rows that do not fit the other columns
+pub const RAW_COLUMN: &str = "_raw_payload";
+
+/// Encoder of Parquet blobs
+/// - The schema is kept between batches, so that the blobs of a forward run share their columns
+/// - An inferred schema drifts with the payloads: New fields are added as nullable columns,
+///   columns that are missing in a batch stay (null) and changed types are widened (see widen)
+/// - A configured schema is fixed: Fields that are not in the schema are dropped
+/// - No payload is lost: Payloads that are no JSON object or do not match a configured schema
+///   are kept as text in the column RAW_COLUMN (added to the schema when it is needed first)
+#[derive(Debug, Clone, Default)]
+pub struct ParquetEncoder {
+    schema: Option<SchemaRef>,
+    fixed: bool,
+}
+
+impl ParquetEncoder {
+
+    /// Creates an encoder with a configured schema (see load_schema) or one that infers the schema (None)
+    pub fn new(schema: Option<SchemaRef>) -> Self {
+        ParquetEncoder {
+            fixed: schema.is_some(),
+            schema,
+        }
+    }
+
+    /// Returns the schema of the last blob (None before the first inferred blob)
+    pub fn schema(&self) -> Option<&SchemaRef> {
+        self.schema.as_ref()
+    }
+
+    /// Encodes the payloads of the messages as one Parquet file (one row per message)
+    /// - Values of string columns that are no string (e.g. an object in a widened column) are written as JSON text
+    /// - The rows are written in row groups of up to ROW_GROUP_SIZE rows (Snappy compressed)
+    pub fn encode(&mut self, messages: &[ConsumedMessage]) -> Result<Vec<u8>, anyhow::Error> {
+
+        let payloads: Vec<Option<Map<String, Value>>> = messages.iter()
+            .map(|message| match serde_json::from_slice(&message.payload) {
+                Ok(Value::Object(payload)) => Some(payload),
+                _ => None,
+            })
+            .collect();
+        if !self.fixed {
+            self.infer_schema(payloads.iter().flatten());
+        }
+        let schema = self.schema.clone().unwrap_or_else(|| Arc::new(Schema::empty()));
+
+        let mut raw_rows = 0;
+        let rows: Vec<Map<String, Value>> = messages.iter().zip(payloads)
+            .map(|(message, payload)| {
+                let mut mismatch = payload.is_none();
+                let mut row = payload
+                    .map(|payload| conform_fields(payload, schema.fields(), &mut mismatch))
+                    .unwrap_or_default();
+                if mismatch {
+                    raw_rows += 1;
+                    row.insert(RAW_COLUMN.to_string(), Value::String(String::from_utf8_lossy(&message.payload).to_string()));
+                }
+                row
+            })
+            .collect();
+
+        let schema = if raw_rows > 0 {
+            warn!("Payload(s) that do not fit the Parquet columns: {} (kept as text in column {})", raw_rows, RAW_COLUMN);
+            self.add_raw_column()
+        } else {
+            schema
+        };
+
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(ROW_GROUP_SIZE)
+            .build();
+        let mut content = Vec::new();
+        let mut writer = ArrowWriter::try_new(&mut content, schema.clone(), Some(properties))?;
+        let mut decoder = ReaderBuilder::new(schema)
+            .with_batch_size(ROW_GROUP_SIZE)
+            .build_decoder()?;
+
+        for chunk in rows.chunks(ROW_GROUP_SIZE) {
+            decoder.serialize(chunk)
+                .map_err(|e| anyhow!("Payloads do not match the Parquet schema: {}", e))?;
+            if let Some(batch) = decoder.flush()? {
+                writer.write(&batch)?;
+            }
+        }
+        writer.close()?;
+
+        Ok(content)
+    }
+
+    /// Merges the types of the payloads into the schema of the previous blobs
+    fn infer_schema<'a>(&mut self, payloads: impl Iterator<Item = &'a Map<String, Value>>) {
+        let previous = self.schema.clone();
+        let mut fields = previous.as_ref().map(|schema| schema.fields().clone()).unwrap_or_default();
+        for payload in payloads {
+            fields = merge_fields(Some(&fields), &object_fields(payload));
+        }
+        let schema = Arc::new(Schema::new(fields));
+
+        if let Some(previous) = &previous {
+            if previous != &schema {
+                info!("Parquet schema changed: {} column(s), was {}", schema.fields().len(), previous.fields().len());
+            }
+        }
+        self.schema = Some(schema);
+    }
+
+    /// Adds RAW_COLUMN to the schema (if it is missing) and returns the schema
+    fn add_raw_column(&mut self) -> SchemaRef {
+        let mut fields: Vec<FieldRef> = self.schema.as_ref().map(|schema| schema.fields().iter().cloned().collect()).unwrap_or_default();
+        if !fields.iter().any(|field| field.name() == RAW_COLUMN) {
+            fields.push(nullable(RAW_COLUMN, DataType::Utf8));
+        }
+        let schema = Arc::new(Schema::new(fields));
+        self.schema = Some(schema.clone());
+        schema
+    }
+}
+
+/// Returns the inferred type of a JSON value
+/// - Numbers are Int64 if they fit, otherwise Float64
+/// - The items of arrays and the fields of objects are inferred (and widened) as well
+fn infer_type(value: &Value) -> DataType {
+    match value {
+        Value::Null => DataType::Null,
+        Value::Bool(_) => DataType::Boolean,
+        Value::Number(number) if number.is_i64() => DataType::Int64,
+        Value::Number(_) => DataType::Float64,
+        Value::String(_) => DataType::Utf8,
+        Value::Array(items) => {
+            let item = items.iter().fold(DataType::Null, |item, value| widen(&item, &infer_type(value)));
+            DataType::List(nullable("item", item))
+        },
+        Value::Object(object) => DataType::Struct(object_fields(object)),
+    }
+}
+
+/// Returns the inferred fields of a JSON object, in the order of their names
+fn object_fields(object: &Map<String, Value>) -> Fields {
+    let fields: Fields = object.iter()
+        .map(|(name, value)| nullable(name, infer_type(value)))
+        .collect();
+    merge_fields(None, &fields)
+}
+
+/// Converts the fields of a JSON object to the types of the columns (see conform)
+/// - Fields without a column are dropped
+fn conform_fields(mut object: Map<String, Value>, fields: &Fields, mismatch: &mut bool) -> Map<String, Value> {
+    fields.iter()
+        .filter_map(|field| object.remove(field.name()).map(|value| (field.name().clone(), conform(value, field.data_type(), mismatch))))
+        .collect()
+}
+
+/// Converts a JSON value to the type of its column
+/// - Values of string columns that are no string are written as JSON text (e.g. an object in a widened column)
+/// - A value in a list column that is no array becomes a list with one item
+/// - A value that does not fit (only with a configured schema) is null and sets mismatch
+fn conform(value: Value, data_type: &DataType, mismatch: &mut bool) -> Value {
+    match (data_type, value) {
+        (_, Value::Null) => Value::Null,
+        (DataType::Utf8, Value::String(text)) => Value::String(text),
+        (DataType::Utf8, value) => Value::String(value.to_string()),
+        (DataType::Int64, Value::Number(number)) if number.is_i64() => Value::Number(number),
+        (DataType::Float64, Value::Number(number)) => Value::Number(number),
+        (DataType::Boolean, Value::Bool(flag)) => Value::Bool(flag),
+        (DataType::Struct(fields), Value::Object(object)) => Value::Object(conform_fields(object, fields, mismatch)),
+        (DataType::List(item), Value::Array(items)) => Value::Array(items.into_iter().map(|value| conform(value, item.data_type(), mismatch)).collect()),
+        (DataType::List(item), value) => Value::Array(vec![conform(value, item.data_type(), mismatch)]),
+        (DataType::Map(entries, _), Value::Object(object)) => {
+            let values = match entries.data_type() {
+                DataType::Struct(fields) if fields.len() == 2 => fields[1].data_type().clone(),
+                _ => DataType::Utf8,
+            };
+            Value::Object(object.into_iter().map(|(key, value)| (key, conform(value, &values, mismatch))).collect())
+        },
+        _ => {
+            *mismatch = true;
+            Value::Null
+        }
+    }
+}
+
+/// Merges inferred fields into the fields of the previous blobs
+/// - Previous fields keep their position, new fields are appended in the order of their names
+/// - Fields of both are widened (see widen), structs without fields are dropped (Parquet can not store them)
+fn merge_fields(previous: Option<&Fields>, inferred: &Fields) -> Fields {
+    let mut fields: Vec<FieldRef> = previous.map(|fields| fields.iter().cloned().collect()).unwrap_or_default();
+
+    let mut new_fields: Vec<&FieldRef> = inferred.iter().collect();
+    new_fields.sort_by(|a, b| a.name().cmp(b.name()));
+
+    for new_field in new_fields {
+        match fields.iter_mut().find(|field| field.name() == new_field.name()) {
+            Some(field) => *field = nullable(field.name(), widen(field.data_type(), new_field.data_type())),
+            None => fields.push(nullable(new_field.name(), sorted(new_field.data_type()))),
+        }
+    }
+
+    fields.into_iter()
+        .filter(|field| !is_empty_struct(field.data_type()))
+        .collect()
+}
+
+/// Returns a nullable field (every column of an inferred schema is nullable)
+fn nullable(name: &str, data_type: DataType) -> FieldRef {
+    Arc::new(Field::new(name, data_type, true))
+}
+
+/// Sorts the fields of structs by their names
+fn sorted(data_type: &DataType) -> DataType {
+    match data_type {
+        DataType::Struct(fields) => DataType::Struct(merge_fields(None, fields)),
+        DataType::List(item) => DataType::List(nullable(item.name(), sorted(item.data_type()))),
+        data_type => data_type.clone(),
+    }
+}
+
+/// Returns true for structs without fields (e.g. inferred from {})
+fn is_empty_struct(data_type: &DataType) -> bool {
+    match data_type {
+        DataType::Struct(fields) => fields.is_empty(),
+        DataType::List(item) => is_empty_struct(item.data_type()),
+        _ => false,
+    }
+}
+
+/// Widens the type of a column to a type that holds the values of both
+/// - Null (a column that was null so far) takes the other type
+/// - Int64 and Float64 are widened to Float64
+/// - Structs are merged (see merge_fields), lists widen their items, a list and a scalar become a list
+/// - Every other combination is widened to Utf8
+pub fn widen(current: &DataType, other: &DataType) -> DataType {
+    match (current, other) {
+        (current, other) if current == other => current.clone(),
+        (DataType::Null, other) => sorted(other),
+        (current, DataType::Null) => current.clone(),
+        (DataType::Int64, DataType::Float64) | (DataType::Float64, DataType::Int64) => DataType::Float64,
+        (DataType::Struct(current), DataType::Struct(other)) => DataType::Struct(merge_fields(Some(current), other)),
+        (DataType::List(current), DataType::List(other)) => DataType::List(nullable(current.name(), widen(current.data_type(), other.data_type()))),
+        (DataType::List(item), scalar) | (scalar, DataType::List(item)) if !matches!(scalar, DataType::Struct(_)) => {
+            DataType::List(nullable(item.name(), widen(item.data_type(), scalar)))
+        },
+        _ => DataType::Utf8,
+    }
+}
+
+/// Loads the Arrow schema of Parquet blobs from a JSON Schema file
+/// - See from_json_schema for the supported keywords
+pub fn load_schema(path: &str) -> Result<Schema, anyhow::Error> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|e| anyhow!("Error reading schema {}: {}", path, e))?;
+    let json_schema: Value = serde_json::from_str(&content)
+        .map_err(|e| anyhow!("Error parsing schema {}: {}", path, e))?;
+
+    let schema = from_json_schema(&json_schema)
+        .map_err(|e| anyhow!("Unsupported schema {}: {}", path, e))?;
+    info!("Parquet schema loaded from {}: {} column(s)", path, schema.fields().len());
+    Ok(schema)
+}
+
+/// Converts a JSON Schema (an object with properties) into an Arrow schema
+/// - string, integer, number and boolean become Utf8, Int64, Float64 and Boolean
+/// - object becomes a struct of its properties, or a map if it only has additionalProperties (e.g. rates)
+/// - array becomes a list of its items
+/// - A type list (e.g. ["number", "null"]) is widened like an inferred schema, every column is nullable
+pub fn from_json_schema(json_schema: &Value) -> Result<Schema, anyhow::Error> {
+    match json_data_type(json_schema)? {
+        DataType::Struct(fields) if !fields.is_empty() => Ok(Schema::new(fields)),
+        _ => Err(anyhow!("The schema must be an object with properties")),
+    }
+}
+
+/// Returns the Arrow type of a JSON Schema
+fn json_data_type(json_schema: &Value) -> Result<DataType, anyhow::Error> {
+    let types: Vec<&str> = match json_schema.get("type") {
+        Some(Value::String(json_type)) => vec![json_type.as_str()],
+        Some(Value::Array(json_types)) => json_types.iter().filter_map(Value::as_str).collect(),
+        _ => return Err(anyhow!("Missing type in {}", json_schema)),
+    };
+
+    let mut data_type = DataType::Null;
+    for json_type in types {
+        let next = match json_type {
+            "null" => DataType::Null,
+            "string" => DataType::Utf8,
+            "integer" => DataType::Int64,
+            "number" => DataType::Float64,
+            "boolean" => DataType::Boolean,
+            "array" => {
+                let items = json_schema.get("items")
+                    .ok_or_else(|| anyhow!("Missing items in {}", json_schema))?;
+                DataType::List(nullable("item", json_data_type(items)?))
+            },
+            "object" => json_object_type(json_schema)?,
+            json_type => return Err(anyhow!("Unknown type {}", json_type)),
+        };
+        data_type = widen(&data_type, &next);
+    }
+
+    match data_type {
+        DataType::Null => Err(anyhow!("Missing type in {}", json_schema)),
+        data_type => Ok(data_type),
+    }
+}
+
+/// Returns the Arrow type of a JSON Schema object
+fn json_object_type(json_schema: &Value) -> Result<DataType, anyhow::Error> {
+    if let Some(properties) = json_schema.get("properties").and_then(Value::as_object) {
+        let fields = properties.iter()
+            .map(|(name, property)| Ok(nullable(name, json_data_type(property)?)))
+            .collect::<Result<Vec<_>, anyhow::Error>>()?;
+        return Ok(DataType::Struct(fields.into()));
+    }
+
+    match json_schema.get("additionalProperties") {
+        Some(values) if values.is_object() => {
+            let entries = Fields::from(vec![
+                Field::new("keys", DataType::Utf8, false),
+                Field::new("values", json_data_type(values)?, true),
+            ]);
+            Ok(DataType::Map(Arc::new(Field::new("entries", DataType::Struct(entries), false)), false))
+        },
+        _ => Err(anyhow!("Object without properties or additionalProperties in {}", json_schema)),
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_json::LineDelimitedWriter;
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::export::test_helper::messages;
+
+    // Returns the schema and the rows (as JSON, without null columns) of a Parquet file
+    fn read(content: Vec<u8>) -> (SchemaRef, Vec<Value>) {
+        let path = std::env::temp_dir().join(format!("exchange-test-{}.parquet", uuid::Uuid::new_v4()));
+        std::fs::write(&path, content).unwrap();
+
+        let builder = ParquetRecordBatchReaderBuilder::try_new(std::fs::File::open(&path).unwrap()).unwrap();
+        let schema = builder.schema().clone();
+        let mut writer = LineDelimitedWriter::new(Vec::new());
+        for batch in builder.build().unwrap() {
+            writer.write(&batch.unwrap()).unwrap();
+        }
+        writer.finish().unwrap();
+        let rows = serde_json::Deserializer::from_slice(&writer.into_inner())
+            .into_iter::<Value>()
+            .map(Result::unwrap)
+            .collect();
+        let _ = std::fs::remove_file(path);
+        (schema, rows)
+    }
+
+    fn data_type<'a>(schema: &'a Schema, name: &str) -> &'a DataType {
+        schema.field_with_name(name).unwrap().data_type()
+    }
+
+    fn names(schema: &Schema) -> Vec<&str> {
+        schema.fields().iter().map(|field| field.name().as_str()).collect()
+    }
+
+    #[test]
+    fn test_encode_inferred() {
+        let mut encoder = ParquetEncoder::default();
+        let content = encoder.encode(&messages(&[
+            "{\"base\":\"EUR\",\"rates\":{\"USD\":1.08}}",
+            "{\"base\":\"USD\",\"rates\":{\"EUR\":0.92,\"USD\":1},\"symbols\":[]}",
+        ])).unwrap();
+
+        let (schema, rows) = read(content);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(names(&schema), vec!["base", "rates", "symbols"]);
+        assert!(matches!(data_type(&schema, "rates"), DataType::Struct(fields) if fields.len() == 2));
+        assert_eq!(rows[1]["rates"]["USD"], serde_json::json!(1.0));
+    }
+
+    #[test]
+    fn test_raw_payloads() {
+        // Payloads that are no JSON object are kept as text
+        let mut encoder = ParquetEncoder::default();
+        let (schema, rows) = read(encoder.encode(&messages(&["{\"base\":\"EUR\"}", "not json", "[1,2]"])).unwrap());
+        assert_eq!(names(&schema), vec!["base", RAW_COLUMN]);
+        assert_eq!(rows, vec![
+            serde_json::json!({ "base": "EUR" }),
+            serde_json::json!({ RAW_COLUMN: "not json" }),
+            serde_json::json!({ RAW_COLUMN: "[1,2]" }),
+        ]);
+
+        // A batch without JSON objects has only the raw column
+        let (schema, rows) = read(ParquetEncoder::default().encode(&messages(&["not json"])).unwrap());
+        assert_eq!(names(&schema), vec![RAW_COLUMN]);
+        assert_eq!(rows.len(), 1);
+    }
+
+    #[test]
+    fn test_conflicting_types() {
+        // A struct and a scalar in one batch are widened to text, the struct is kept as JSON text
+        let mut encoder = ParquetEncoder::default();
+        let (schema, rows) = read(encoder.encode(&messages(&["{\"rate\":{\"USD\":1}}", "{\"rate\":1.5}"])).unwrap());
+        assert_eq!(data_type(&schema, "rate"), &DataType::Utf8);
+        assert_eq!(rows[0]["rate"], "{\"USD\":1}");
+        assert_eq!(rows[1]["rate"], "1.5");
+
+        // An object in a column that was widened to text in a previous batch
+        let (schema, rows) = read(encoder.encode(&messages(&["{\"rate\":{\"GBP\":[0.86]}}"])).unwrap());
+        assert_eq!(data_type(&schema, "rate"), &DataType::Utf8);
+        assert_eq!(rows[0]["rate"], "{\"GBP\":[0.86]}");
+        assert!(schema.field_with_name(RAW_COLUMN).is_err());
+    }
+
+    #[test]
+    fn test_schema_drift() {
+        let mut encoder = ParquetEncoder::default();
+        encoder.encode(&messages(&["{\"base\":\"EUR\",\"amount\":1,\"flag\":true}"])).unwrap();
+        let content = encoder.encode(&messages(&["{\"date\":\"2024-01-02\",\"amount\":1.5,\"flag\":\"yes\"}"])).unwrap();
+
+        // Columns keep their position, new columns are appended, types are widened
+        let (schema, rows) = read(content);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(names(&schema), vec!["amount", "base", "flag", "date"]);
+        assert_eq!(data_type(&schema, "amount"), &DataType::Float64);
+        assert_eq!(data_type(&schema, "flag"), &DataType::Utf8);
+        assert!(schema.fields().iter().all(|field| field.is_nullable()));
+
+        // Widened columns stay widened
+        let (_, rows) = read(encoder.encode(&messages(&["{\"amount\":2,\"flag\":false}"])).unwrap());
+        assert_eq!(data_type(encoder.schema().unwrap(), "amount"), &DataType::Float64);
+        assert_eq!(data_type(encoder.schema().unwrap(), "flag"), &DataType::Utf8);
+        assert_eq!(rows[0]["flag"], "false");
+    }
+
+    #[test]
+    fn test_configured_schema() {
+        let json_schema = serde_json::json!({
+            "type": "object",
+            "properties": {
+                "base": { "type": "string" },
+                "timestamp": { "type": ["integer", "null"] },
+                "rates": { "type": "object", "additionalProperties": { "type": "number" } }
+            }
+        });
+        let schema = from_json_schema(&json_schema).unwrap();
+        assert_eq!(data_type(&schema, "timestamp"), &DataType::Int64);
+        assert!(matches!(data_type(&schema, "rates"), DataType::Map(..)));
+
+        let mut encoder = ParquetEncoder::new(Some(Arc::new(schema.clone())));
+        let (read_schema, rows) = read(encoder.encode(&messages(&["{\"base\":\"EUR\",\"rates\":{\"USD\":1.08,\"GBP\":0.86},\"extra\":1}"])).unwrap());
+        assert_eq!(rows.len(), 1);
+        assert_eq!(read_schema.fields().len(), 3);
+
+        // A configured schema does not drift, a payload that does not match is kept as text
+        let (read_schema, rows) = read(encoder.encode(&messages(&["{\"base\":\"EUR\",\"timestamp\":\"today\"}"])).unwrap());
+        assert_eq!(names(&read_schema), vec!["base", "rates", "timestamp", RAW_COLUMN]);
+        assert_eq!(rows[0], serde_json::json!({ "base": "EUR", RAW_COLUMN: "{\"base\":\"EUR\",\"timestamp\":\"today\"}" }));
+        assert!(from_json_schema(&serde_json::json!({ "type": "string" })).is_err());
+    }
+}
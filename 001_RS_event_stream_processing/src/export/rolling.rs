@@ -0,0 +1,237 @@
+/*
+    This file contains the rolling of exported batches into multiple blobs
+    A batch is rolled after a number of records, bytes or minutes and named from a template
+*/
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use time::OffsetDateTime;
+
+use crate::kafka::report::{ConsumeReport, ConsumedMessage};
+
+/// Determines when a batch is rolled into a new blob
+/// - max_records: Roll after the given number of records
+/// - max_bytes: Roll once the payloads reach the given number of bytes
+/// - max_age: Roll once the first record of the batch is older than the given duration
+/// - Without any limit, the batch is only rolled when the forward run ends
+#[derive(Debug, Clone, Default)]
+pub struct RollPolicy {
+    pub max_records: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub max_age: Option<Duration>,
+}
+
+/// Template used to name the rolled blobs
+/// - Placeholders: {topic}, {yyyy}, {MM}, {dd}, {HH}, {mm}, {partition}, {first_offset}, {last_offset}, {ext}
+/// - The date placeholders are taken from the timestamp of the first record (UTC)
+/// - Example: {topic}/{yyyy}/{MM}/{dd}/{HH}/{partition}-{first_offset}.{ext}
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlobTemplate {
+    template: String,
+}
+
+impl BlobTemplate {
+
+    pub fn new(template: &str) -> Self {
+        BlobTemplate { template: template.to_string() }
+    }
+
+    /// Returns true if every partition needs its own blob
+    pub fn per_partition(&self) -> bool {
+        self.template.contains("{partition}")
+    }
+
+    /// Returns true if two batches can end up with the same name
+    pub fn is_static(&self) -> bool {
+        !self.template.contains("{first_offset}") && !self.template.contains("{last_offset}")
+    }
+
+    /// Renders the blob name for a batch
+    /// - extension: File extension of the export format (for {ext})
+    pub fn render(&self, batch: &ConsumeReport, extension: &str) -> String {
+        let first = batch.messages.first().map(|message| &message.metadata);
+
+        let timestamp = first
+            .and_then(|metadata| metadata.timestamp)
+            .and_then(|timestamp| OffsetDateTime::from_unix_timestamp_nanos(timestamp as i128 * 1_000_000).ok())
+            .unwrap_or_else(OffsetDateTime::now_utc);
+
+        let partition = first.map(|metadata| metadata.partition).unwrap_or_default();
+        let summary = batch.partitions.get(&partition).cloned().unwrap_or_default();
+
+        self.template
+            .replace("{topic}", first.map(|metadata| metadata.topic.as_str()).unwrap_or_default())
+            .replace("{yyyy}", &format!("{:04}", timestamp.year()))
+            .replace("{MM}", &format!("{:02}", timestamp.month() as u8))
+            .replace("{dd}", &format!("{:02}", timestamp.day()))
+            .replace("{HH}", &format!("{:02}", timestamp.hour()))
+            .replace("{mm}", &format!("{:02}", timestamp.minute()))
+            .replace("{partition}", &partition.to_string())
+            .replace("{first_offset}", &summary.first_offset.to_string())
+            .replace("{last_offset}", &summary.last_offset.to_string())
+            .replace("{ext}", extension)
+    }
+}
+
+/// Parses a blob name template (see BlobTemplate)
+/// - The offsets of different partitions overlap (e.g. 0-42 in partition 0 and 1), so offset placeholders need {partition}
+pub fn parse_template(template: &str) -> Result<String, String> {
+    let parsed = BlobTemplate::new(template);
+    if !parsed.is_static() && !parsed.per_partition() {
+        return Err(format!("Invalid template {}: {{first_offset}} and {{last_offset}} need {{partition}}, otherwise blobs of different partitions get the same name", template));
+    }
+    Ok(template.to_string())
+}
+
+/// Batch that is currently filled
+struct OpenBatch {
+    batch: ConsumeReport,
+    opened: Instant,
+}
+
+/// Collects consumed messages into batches and rolls them according to the RollPolicy
+/// - Keeps one batch per partition if the template contains {partition}, otherwise one batch overall
+pub struct Roller {
+    policy: RollPolicy,
+    per_partition: bool,
+    batches: BTreeMap<Option<i32>, OpenBatch>,
+}
+
+impl Roller {
+
+    pub fn new(policy: RollPolicy, template: &BlobTemplate) -> Self {
+        Roller {
+            policy,
+            per_partition: template.per_partition(),
+            batches: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the message to its batch
+    /// - Returns the batch if it is full (records or bytes)
+    pub fn push(&mut self, message: ConsumedMessage) -> Option<ConsumeReport> {
+        let key = self.per_partition.then_some(message.metadata.partition);
+
+        let open = self.batches.entry(key).or_insert_with(|| OpenBatch {
+            batch: ConsumeReport::default(),
+            opened: Instant::now(),
+        });
+        open.batch.add(message);
+
+        let full = self.policy.max_records.is_some_and(|max| open.batch.total_messages >= max)
+            || self.policy.max_bytes.is_some_and(|max| open.batch.total_bytes >= max);
+
+        if full {
+            self.batches.remove(&key).map(|open| open.batch)
+        } else {
+            None
+        }
+    }
+
+    /// Removes and returns all batches that are older than max_age
+    pub fn expired(&mut self, now: Instant) -> Vec<ConsumeReport> {
+        let Some(max_age) = self.policy.max_age else {
+            return Vec::new();
+        };
+
+        let expired: Vec<Option<i32>> = self.batches.iter()
+            .filter(|(_, open)| now.duration_since(open.opened) >= max_age)
+            .map(|(key, _)| *key)
+            .collect();
+
+        expired.into_iter()
+            .filter_map(|key| self.batches.remove(&key))
+            .map(|open| open.batch)
+            .collect()
+    }
+
+    /// Removes and returns all remaining batches (e.g. on shutdown)
+    pub fn drain(&mut self) -> Vec<ConsumeReport> {
+        std::mem::take(&mut self.batches)
+            .into_values()
+            .map(|open| open.batch)
+            .collect()
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::test_helper;
+
+    // Message of the topic rates with 100 bytes, written at 2023-03-01T13:45:00Z
+    fn message(partition: i32, offset: i64) -> ConsumedMessage {
+        let mut message = test_helper::message(partition, offset, &"x".repeat(100));
+        message.metadata.topic = "rates".to_string();
+        message.metadata.timestamp = Some(1_677_678_300_000);
+        message
+    }
+
+    #[test]
+    fn test_render_template() {
+        let template = BlobTemplate::new("{topic}/{yyyy}/{MM}/{dd}/{HH}/{partition}-{first_offset}.{ext}");
+        let mut batch = ConsumeReport::default();
+        batch.add(message(3, 42));
+        batch.add(message(3, 43));
+
+        assert_eq!(template.render(&batch, "ndjson"), "rates/2023/03/01/13/3-42.ndjson");
+        assert!(template.per_partition());
+        assert!(!template.is_static());
+    }
+
+    #[test]
+    fn test_render_static_name() {
+        let template = BlobTemplate::new("export.json");
+        let mut batch = ConsumeReport::default();
+        batch.add(message(0, 0));
+
+        assert_eq!(template.render(&batch, "ndjson"), "export.json");
+        assert!(template.is_static());
+    }
+
+    #[test]
+    fn test_parse_template() {
+        assert!(parse_template("{topic}/{partition}-{first_offset}.{ext}").is_ok());
+        assert!(parse_template("{topic}/{yyyy}-{MM}-{dd}.{ext}").is_ok());
+        assert!(parse_template("{topic}/{first_offset}-{last_offset}.{ext}").is_err());
+    }
+
+    #[test]
+    fn test_roll_by_records_per_partition() {
+        let policy = RollPolicy { max_records: Some(2), ..Default::default() };
+        let mut roller = Roller::new(policy, &BlobTemplate::new("{partition}-{first_offset}"));
+
+        assert!(roller.push(message(0, 0)).is_none());
+        assert!(roller.push(message(1, 0)).is_none());
+        let rolled = roller.push(message(0, 1)).expect("partition 0 should roll");
+
+        assert_eq!(rolled.total_messages, 2);
+        assert_eq!(rolled.partitions.keys().collect::<Vec<_>>(), vec![&0]);
+        assert_eq!(roller.drain().len(), 1);
+    }
+
+    #[test]
+    fn test_roll_by_bytes() {
+        let policy = RollPolicy { max_bytes: Some(250), ..Default::default() };
+        let mut roller = Roller::new(policy, &BlobTemplate::new("{first_offset}"));
+
+        assert!(roller.push(message(0, 0)).is_none());
+        assert!(roller.push(message(1, 0)).is_none());
+        assert_eq!(roller.push(message(0, 1)).map(|batch| batch.total_bytes), Some(300));
+    }
+
+    #[test]
+    fn test_roll_by_age() {
+        let policy = RollPolicy { max_age: Some(Duration::from_secs(60)), ..Default::default() };
+        let mut roller = Roller::new(policy, &BlobTemplate::new("{first_offset}"));
+        roller.push(message(0, 0));
+
+        assert!(roller.expired(Instant::now()).is_empty());
+        assert_eq!(roller.expired(Instant::now() + Duration::from_secs(61)).len(), 1);
+        assert!(roller.drain().is_empty());
+    }
+}
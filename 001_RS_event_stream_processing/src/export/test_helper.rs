@@ -0,0 +1,23 @@
+/*
+    This file contains the fixtures shared by the unit tests of the export
+*/
+
+use crate::kafka::report::ConsumedMessage;
+use crate::kafka::test_helper::metadata;
+
+/// Returns a message of the topic test without key, timestamp and headers
+pub fn message(partition: i32, offset: i64, payload: &str) -> ConsumedMessage {
+    ConsumedMessage {
+        metadata: metadata(partition, offset, payload.len() as u64),
+        headers: Vec::new(),
+        payload: payload.as_bytes().to_vec(),
+    }
+}
+
+/// Returns a message per payload (partition 0, offsets from 0)
+pub fn messages(payloads: &[&str]) -> Vec<ConsumedMessage> {
+    payloads.iter()
+        .enumerate()
+        .map(|(offset, payload)| message(0, offset as i64, payload))
+        .collect()
+}
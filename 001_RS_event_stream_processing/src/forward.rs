@@ -0,0 +1,249 @@
+/*
+    This file contains the forward pipeline (Consume -> Write)
+    The blobs are written to a BlobStore (Azure Blob Storage or a local directory)
+    The offsets are committed only after the blob upload succeeded (at-least-once delivery)
+*/
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use arrow_schema::SchemaRef;
+use futures::StreamExt;
+use log::{info, warn, error};
+
+use crate::azure::compression::Compression;
+use crate::azure::encryption::KeyRing;
+use crate::azure::writer::{ConflictPolicy, WriteOptions};
+use crate::export::format::{Encoder, ExportFormat};
+use crate::export::metadata::{append_metadata, batch_metadata};
+use crate::export::rolling::{BlobTemplate, RollPolicy, Roller};
+use crate::kafka::consumer::{new_kafka_source, CommitPolicy, ConsumerOptions, KafkaSource};
+use crate::kafka::report::ConsumeReport;
+use crate::storage::azure::AzureBlobStore;
+use crate::errors::AppendError;
+use crate::storage::{append_blob, is_blob_exists, write_blob, AppendProgress, BlobStore};
+
+// Interval in which batches are checked for their age while the topic is idle
+const ROLL_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Options for the forward pipeline
+/// - consumer: Options for the consumer (the commit policy is always manual)
+/// - format: Blob format of the batch
+/// - compression: Compression of the blobs (the extension is appended to the blob name)
+/// - roll: Determines when a batch is rolled into a new blob (continuous forward)
+/// - upload_retries: Number of times a failed upload of the batch is retried
+/// - retry_delay: Delay before the first retry (doubled on every further retry)
+/// - conflict: Handling of blobs that already exist (see ConflictPolicy)
+/// - tags: Blob index tags of every blob (the metadata is derived from the batch)
+/// - encryption: Keys of the client-side encryption of the blobs (see azure::encryption)
+/// - append: Append every batch to an append blob instead of writing a blob per batch (see append_blob)
+/// - schema: Schema of Parquet blobs, inferred from the payloads if None (see ParquetEncoder)
+/// - fields: Dotted paths of the CSV columns, all fields of the payloads if empty (see CsvEncoder)
+#[derive(Debug, Clone)]
+pub struct ForwardOptions {
+    pub consumer: ConsumerOptions,
+    pub format: ExportFormat,
+    pub compression: Compression,
+    pub roll: RollPolicy,
+    pub upload_retries: u32,
+    pub retry_delay: Duration,
+    pub conflict: ConflictPolicy,
+    pub tags: BTreeMap<String, String>,
+    pub encryption: Option<KeyRing>,
+    pub append: bool,
+    pub schema: Option<SchemaRef>,
+    pub fields: Vec<String>,
+}
+
+impl Default for ForwardOptions {
+    fn default() -> Self {
+        ForwardOptions {
+            consumer: ConsumerOptions::default(),
+            format: ExportFormat::Ndjson,
+            compression: Compression::None,
+            roll: RollPolicy::default(),
+            upload_retries: 0,
+            retry_delay: Duration::from_secs(2),
+            conflict: ConflictPolicy::Overwrite,
+            tags: BTreeMap::new(),
+            encryption: None,
+            append: false,
+            schema: None,
+            fields: Vec::new(),
+        }
+    }
+}
+
+/// Result of the forward pipeline
+/// - messages/bytes: Number of messages and payload bytes forwarded
+/// - blobs: Names of the blobs written
+#[derive(Debug, Clone, Default)]
+pub struct ForwardReport {
+    pub messages: u64,
+    pub bytes: u64,
+    pub blobs: Vec<String>,
+}
+
+/// Forwards the messages of a topic to Azure Blob Storage
+/// - See forward_to_store
+pub async fn forward_to_azure(topic: &str, container_name: &str, blob_name: &str, options: &ForwardOptions) -> Result<ForwardReport, anyhow::Error> {
+    forward_to_store(&AzureBlobStore::new(), topic, container_name, blob_name, options).await
+}
+
+/// Forwards the messages of a topic to a BlobStore
+/// - Buffers the messages of the topic in batches (see ConsumerOptions for the bounds)
+/// - Rolls a batch into its own blob according to the RollPolicy, the rest is written when the run ends
+/// - Names the blobs from the template (see BlobTemplate), a plain blob name is used as is
+/// - Uploads each batch with its metadata (see batch_metadata), failed uploads are retried as configured
+/// - With append, every batch is appended to the blob of its name (e.g. a daily blob), which is created if it does not exist
+/// - Commits the exact offsets of a batch only after its upload succeeded
+/// - Returns an error (without committing) if an upload failed, the batch is read again on the next run
+pub async fn forward_to_store(store: &dyn BlobStore, topic: &str, container_name: &str, blob_name: &str, options: &ForwardOptions) -> Result<ForwardReport, anyhow::Error> {
+
+    if options.append && !options.format.is_line_based() {
+        return Err(anyhow!("Format {:?} can not be appended to a blob: Use ndjson or raw", options.format));
+    }
+    if options.append && options.encryption.is_some() {
+        return Err(anyhow!("Encrypted blobs can not be appended to"));
+    }
+    if options.schema.is_some() && options.format != ExportFormat::Parquet {
+        return Err(anyhow!("A schema is only used by the parquet format, not by {:?}", options.format));
+    }
+    if !options.fields.is_empty() && options.format != ExportFormat::Csv {
+        return Err(anyhow!("Fields are only used by the csv format, not by {:?}", options.format));
+    }
+
+    let consumer_options = ConsumerOptions {
+        commit: CommitPolicy::Manual,
+        ..options.consumer.clone()
+    };
+
+    let template = BlobTemplate::new(blob_name);
+    if !options.append && template.is_static() && (options.roll.max_records.is_some() || options.roll.max_bytes.is_some() || options.roll.max_age.is_some()) {
+        warn!("Blob name {} contains no offset placeholder: Rolled blobs overwrite each other", blob_name);
+    }
+
+    let source = new_kafka_source(topic, consumer_options)?;
+    let ctrl_c = source.shutdown_on_ctrl_c();
+
+    let mut roller = Roller::new(options.roll.clone(), &template);
+    let mut encoder = Encoder::new(options.format, options.schema.clone(), options.fields.clone());
+    let mut report = ForwardReport::default();
+
+    let mut messages = Box::pin(source.messages());
+    loop {
+        // The timeout allows rolling by age while the topic is idle
+        match tokio::time::timeout(ROLL_CHECK_INTERVAL, messages.next()).await {
+            Ok(Some(Ok(message))) => {
+                if let Some(batch) = roller.push(message) {
+                    write_batch(store, &source, container_name, &template, &mut encoder, batch, options, &mut report).await?;
+                }
+            },
+            // Errors are already logged by the stream
+            Ok(Some(Err(_))) => {},
+            Ok(None) => break,
+            Err(_) => {
+                for batch in roller.expired(Instant::now()) {
+                    write_batch(store, &source, container_name, &template, &mut encoder, batch, options, &mut report).await?;
+                }
+            },
+        }
+    }
+    drop(messages);
+    ctrl_c.abort();
+
+    // Write the remaining (not yet rolled) batches
+    for batch in roller.drain() {
+        write_batch(store, &source, container_name, &template, &mut encoder, batch, options, &mut report).await?;
+    }
+
+    // Check if message count is 0
+    if report.messages == 0 {
+        // The content should not be pushed to the sink since it results in an overwrite by default
+        warn!("No data received from Kafka: Skipping push to container {}", container_name);
+    }
+
+    Ok(report)
+}
+
+/// Uploads a single batch and commits its offsets afterwards
+#[allow(clippy::too_many_arguments)]
+async fn write_batch(store: &dyn BlobStore, source: &KafkaSource, container_name: &str, template: &BlobTemplate, encoder: &mut Encoder, batch: ConsumeReport, options: &ForwardOptions, report: &mut ForwardReport) -> Result<(), anyhow::Error> {
+
+    if batch.is_empty() {
+        return Ok(());
+    }
+
+    let blob_name = template.render(&batch, options.format.extension());
+    info!("Message(s) read from Kafka: {} (blob: {})", batch.total_messages, &blob_name);
+
+    let content = encoder.encode(&batch.messages)?;
+    let topic = &batch.messages[0].metadata.topic;
+    let metadata = if options.append { append_metadata(topic) } else { batch_metadata(topic, &batch) };
+    let blob_names = upload_with_retries(store, container_name, &blob_name, &content, metadata, options).await?;
+
+    // The batch is safe in the sink, the offsets can be committed
+    source.commit(&batch.partitions)?;
+
+    report.messages += batch.total_messages;
+    report.bytes += batch.total_bytes;
+    for blob_name in blob_names {
+        if !report.blobs.contains(&blob_name) {
+            report.blobs.push(blob_name);
+        }
+    }
+    Ok(())
+}
+
+/// Uploads the content, retrying failed uploads with an exponential backoff
+/// - metadata: Metadata of the batch (see batch_metadata), the tags are taken from the options
+/// - With append, the content is appended (see append_blob), a retry continues behind the blocks that were appended
+/// - Returns the names of the written blobs (more than one if an append blob was full)
+async fn upload_with_retries(store: &dyn BlobStore, container_name: &str, blob_name: &str, content: &[u8], metadata: BTreeMap<String, String>, options: &ForwardOptions) -> Result<Vec<String>, anyhow::Error> {
+
+    let write_options = WriteOptions {
+        content_type: options.format.content_type().to_string(),
+        compression: options.compression,
+        conflict: options.conflict,
+        metadata,
+        tags: options.tags.clone(),
+        encryption: options.encryption.clone(),
+    };
+    let mut delay = options.retry_delay;
+    let mut progress = AppendProgress::default();
+
+    for attempt in 0..=options.upload_retries {
+        let result = if options.append {
+            append_blob(store, container_name, blob_name, content, &write_options, &mut progress).await
+                .map(|_| progress.blob_names.clone())
+        } else {
+            write_blob(store, container_name, blob_name, content, &write_options).await.map(|blob_name| vec![blob_name])
+        };
+
+        match result {
+            Ok(blob_names) => {
+                info!("Data pushed to container {} successfully", container_name);
+                return Ok(blob_names);
+            },
+            // A blob that exists (or is no append blob) stays in place on every retry
+            Err(e) if is_blob_exists(&e) || e.downcast_ref::<AppendError>().is_some() => {
+                error!("Error while pushing data to container {}: {}", container_name, e);
+                error!("Offsets are not committed: The batch is read again on the next run");
+                return Err(e);
+            },
+            Err(e) if attempt < options.upload_retries => {
+                warn!("Error while pushing data to container {}: {} (retry {} of {} in {:?})", container_name, e, attempt + 1, options.upload_retries, delay);
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            },
+            Err(e) => {
+                error!("Error while pushing data to container {}: {}", container_name, e);
+                error!("Offsets are not committed: The batch is read again on the next run");
+                return Err(anyhow!("Upload of {} to {} failed: {}", blob_name, container_name, e));
+            }
+        }
+    }
+
+    unreachable!("The last attempt always returns")
+}
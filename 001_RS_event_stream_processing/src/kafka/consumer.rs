@@ -3,174 +3,629 @@
     It mimics the consumer in the project setup
 */
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{BTreeMap, HashMap};
 use std::time::Duration;
 
-use bytecount::num_chars;
+use futures::{Stream, StreamExt};
 use log::{warn, info};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
+use crate::config::KafkaConfig;
 use crate::get_kafka_details;
+use crate::kafka::report::{ConsumeReport, ConsumedMessage, MessageMetadata, PartitionSummary};
 
-use rdkafka::error::{KafkaError, KafkaResult};
-use rdkafka::{Message};
+use rdkafka::error::{KafkaError, KafkaResult, RDKafkaErrorCode};
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{Consumer,BaseConsumer, CommitMode};
+use rdkafka::consumer::{Consumer, StreamConsumer, CommitMode};
+use rdkafka::topic_partition_list::{Offset, TopicPartitionList};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+
+/// Position the consumer starts reading from
+/// - Committed: Committed offsets of the consumer group (default)
+/// - Beginning: First available offset of every partition
+/// - Offset: Given offset of a single partition
+/// - Timestamp: First offset of every partition at or after the timestamp (milliseconds since epoch)
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum StartPosition {
+    #[default]
+    Committed,
+    Beginning,
+    Offset { partition: i32, offset: i64 },
+    Timestamp(i64),
+}
 
+impl StartPosition {
 
-/// Reads from the Kafka topic. (Kafka Consumer)
-/// - Establishes a connection to the Kafka broker as defined in the kafka_key.json file
-/// - Reads the messages from the topic
-/// 
-/// - Returns a tuple (total number of messages read, byte size of the individual messages, total byte size transmitted)
-pub async fn read_from_kafka(topic: &str, time_to_live: u8) -> Result<(u8, HashMap<u8, String>,Vec<u8>, u32), KafkaError>{
+    /// Parses a "partition:offset" argument (e.g. "0:42")
+    pub fn parse_offset(value: &str) -> Result<StartPosition, String> {
+        let (partition, offset) = value
+            .split_once(':')
+            .ok_or_else(|| format!("Expected partition:offset, got {}", value))?;
 
-    // TODO: Why does calling new_kafka_consumer() here not work?
-    // - It works in the producer.rs file
-    // - It works in the client.rs file
-    // - It does not work here
-    // results in an stack overflow error
+        Ok(StartPosition::Offset {
+            partition: partition.trim().parse().map_err(|e| format!("Invalid partition {}: {}", partition, e))?,
+            offset: offset.trim().parse().map_err(|e| format!("Invalid offset {}: {}", offset, e))?,
+        })
+    }
 
-    // let consumer = new_kafka_consumer().await;
+    /// Parses a RFC3339 timestamp argument (e.g. "2023-03-01T00:00:00+01:00")
+    pub fn parse_since(value: &str) -> Result<StartPosition, String> {
+        let timestamp = OffsetDateTime::parse(value, &Rfc3339)
+            .map_err(|e| format!("Invalid RFC3339 timestamp {}: {}", value, e))?;
 
-    // TODO: remove this and use the function above instead
-    // - This is a temporary solution to avoid the stack overflow error
-    // - The function is not working here, yet
+        Ok(StartPosition::Timestamp((timestamp.unix_timestamp_nanos() / 1_000_000) as i64))
+    }
+}
 
+/// Determines when the offsets of the consumed messages are committed
+/// - OnRead: Every yielded message is committed (automatically and on close)
+/// - Manual: Nothing is committed until commit() is called (e.g. after the batch was processed)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CommitPolicy {
+    #[default]
+    OnRead,
+    Manual,
+}
 
-    // Read the kafka details from a file and store them in a vector
-    let kafka_details = get_kafka_details().unwrap();
+/// Options for the consumer
+/// - idle_timeout: Time to wait for a message before the poll counts as idle
+/// - max_retries: Number of subsequent idle polls before the consumer stops
+/// - group_id: Consumer group (overrides the group_id of kafka_config.json)
+/// - start: Position the consumer starts reading from
+/// - max_messages: Stop after the given number of messages
+/// - max_bytes: Stop once the given number of payload bytes is reached (the last message is included)
+/// - until_end: Stop once every partition reached its high watermark at start time, or when a poll of the assigned partitions finds nothing left to read
+/// - commit: Determines when the offsets are committed
+#[derive(Debug, Clone)]
+pub struct ConsumerOptions {
+    pub idle_timeout: Duration,
+    pub max_retries: u32,
+    pub group_id: Option<String>,
+    pub start: StartPosition,
+    pub max_messages: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub until_end: bool,
+    pub commit: CommitPolicy,
+}
 
-    // Assign details to variables (for readability)
-    let bootstrap_servers = kafka_details.bootstrap_servers;
-    let group_id = kafka_details.group_id;
-    let message_timeout_ms = kafka_details.message_timeout_ms;
-    let connection_max_idle_ms = kafka_details.connection_max_idle_ms;
+impl Default for ConsumerOptions {
+    fn default() -> Self {
+        ConsumerOptions {
+            idle_timeout: Duration::from_secs(2),
+            max_retries: 5,
+            group_id: None,
+            start: StartPosition::Committed,
+            max_messages: None,
+            max_bytes: None,
+            until_end: false,
+            commit: CommitPolicy::OnRead,
+        }
+    }
+}
 
-    // Create a new Kafka consumer (if not already existing)
-    let consumer: &BaseConsumer = &ClientConfig::new()
-        .set("bootstrap.servers", &bootstrap_servers)
-        .set("group.id", &group_id)
-        .set("message.timeout.ms", &message_timeout_ms.to_string())
-        .set("connections.max.idle.ms", &connection_max_idle_ms.to_string())
-        .create()
-        .expect("Error: Failed to create Kafka consumer");
+/// Keeps track of the termination bounds of a message stream
+/// - end_offsets: High watermark of every partition with messages left (only if until_end is used)
+#[derive(Debug, Clone, Default)]
+struct Bounds {
+    max_messages: Option<u64>,
+    max_bytes: Option<u64>,
+    end_offsets: Option<HashMap<i32, i64>>,
+    messages: u64,
+    bytes: u64,
+}
 
+impl Bounds {
 
-    // Initialize the message counter
-    // - u8 is ok in this case since we only want to process a few messages
-    let mut message_counter: u8 = 0;
-    let mut retry_counter: u8 = 0;
+    fn new(options: &ConsumerOptions, end_offsets: Option<HashMap<i32, i64>>) -> Self {
+        Bounds {
+            max_messages: options.max_messages,
+            max_bytes: options.max_bytes,
+            end_offsets,
+            ..Default::default()
+        }
+    }
 
-    // Initialize a vector to store the bytes of consumed messages 
-    let mut message_bytes: Vec<u8> = Vec::new();
+    /// Counts the message and marks its partition as done once the high watermark is reached
+    fn record(&mut self, metadata: &MessageMetadata) {
+        self.messages += 1;
+        self.bytes += metadata.size;
+        self.advance(metadata.partition, metadata.offset + 1);
+    }
 
-    // Initialize a vector to store all messages as strings
-    let mut message_content: HashMap<u8, String> = HashMap::new();
+    /// Marks the partition as done if the position (next offset to read) reached the high watermark
+    /// - The position also moves past control records of transactions, which are never yielded as messages
+    fn advance(&mut self, partition: i32, position: i64) {
+        if let Some(end_offsets) = self.end_offsets.as_mut() {
+            if end_offsets.get(&partition).is_some_and(|high| position >= *high) {
+                end_offsets.remove(&partition);
+            }
+        }
+    }
 
+    /// Advances every partition to its position (see advance)
+    /// - positions: Position of every assigned partition, partitions without a position yet are missing
+    /// - Returns true if every partition left to read has a position, i.e. is assigned and fetching
+    fn advance_all(&mut self, positions: &HashMap<i32, i64>) -> bool {
+        for (partition, position) in positions {
+            self.advance(*partition, *position);
+        }
+        self.end_offsets.as_ref()
+            .is_some_and(|end_offsets| end_offsets.keys().all(|partition| positions.contains_key(partition)))
+    }
 
+    /// Returns true if the stream should end at the high watermarks (until_end)
+    fn until_end(&self) -> bool {
+        self.end_offsets.is_some()
+    }
 
-    // Subscribe to the topic
-    consumer.subscribe(&[topic]).expect("Error: Failed to subscribe to topic");
+    /// Returns the reason why the stream should end (if any bound is reached)
+    fn reached(&self) -> Option<&'static str> {
+        if self.max_messages.is_some_and(|max| self.messages >= max) {
+            Some("max messages reached")
+        } else if self.max_bytes.is_some_and(|max| self.bytes >= max) {
+            Some("max bytes reached")
+        } else if self.end_offsets.as_ref().is_some_and(|end_offsets| end_offsets.is_empty()) {
+            Some("end of partitions reached")
+        } else {
+            None
+        }
+    }
+}
 
-    info!("Subscribed to topic: {}", topic);
-    info!("Consumer starts...");
+/// Async consumer for a single topic (Kafka Consumer)
+/// - Wraps a StreamConsumer configured via kafka_config.json
+/// - Yields the messages of the topic as a Stream until the topic is idle or a shutdown is requested
+/// - Stores the offset of every yielded message, which is committed periodically and on close()
+pub struct KafkaSource {
+    consumer: StreamConsumer,
+    topic: String,
+    options: ConsumerOptions,
+    end_offsets: Option<HashMap<i32, i64>>,
+    shutdown: CancellationToken,
+}
 
-    loop {
-        // Poll the stream for a message
-        let message = consumer.poll(Duration::from_secs(time_to_live as u64));
+impl KafkaSource {
+
+    /// Creates a new consumer for the topic
+    /// - Subscribes to the topic if the committed offsets are used
+    /// - Otherwise assigns the partitions at the requested start position
+    /// - Fetches the high watermarks if the consumer should stop at the end of the partitions
+    pub fn new(kafka_details: &KafkaConfig, topic: &str, options: ConsumerOptions) -> KafkaResult<KafkaSource> {
+        let group_id = options.group_id.as_deref().unwrap_or(&kafka_details.group_id);
+        let consumer: StreamConsumer = consumer_config(kafka_details, group_id, options.commit).create()?;
+        let timeout = Duration::from_millis(kafka_details.message_timeout_ms as u64);
+
+        let assignment = match &options.start {
+            StartPosition::Committed => {
+                consumer.subscribe(&[topic])?;
+                info!("Subscribed to topic: {} (group: {})", topic, group_id);
+                None
+            },
+            StartPosition::Beginning => {
+                let mut assignment = topic_partitions(&consumer, topic, timeout)?;
+                assignment.set_all_offsets(Offset::Beginning)?;
+                consumer.assign(&assignment)?;
+                info!("Assigned topic: {} from the beginning (group: {})", topic, group_id);
+                Some(assignment)
+            },
+            StartPosition::Offset { partition, offset } => {
+                let mut assignment = TopicPartitionList::new();
+                assignment.add_partition_offset(topic, *partition, Offset::Offset(*offset))?;
+                consumer.assign(&assignment)?;
+                info!("Assigned topic: {} from partition {} at offset {} (group: {})", topic, partition, offset, group_id);
+                Some(assignment)
+            },
+            StartPosition::Timestamp(timestamp) => {
+                // offsets_for_times expects the timestamp in place of the offset
+                let mut timestamps = topic_partitions(&consumer, topic, timeout)?;
+                timestamps.set_all_offsets(Offset::Offset(*timestamp))?;
+                let assignment = consumer.offsets_for_times(timestamps, timeout)?;
+                consumer.assign(&assignment)?;
+                info!("Assigned topic: {} since timestamp {} (group: {})", topic, timestamp, group_id);
+                Some(assignment)
+            },
+        };
+
+        let end_offsets = if options.until_end {
+            // Without an assignment, the committed offsets of the group are the start position
+            let start_offsets = match assignment {
+                Some(assignment) => assignment,
+                None => consumer.committed_offsets(topic_partitions(&consumer, topic, timeout)?, timeout)?,
+            };
+            Some(end_offsets(&consumer, topic, &start_offsets, timeout)?)
+        } else {
+            None
+        };
+
+        Ok(KafkaSource {
+            consumer,
+            topic: topic.to_string(),
+            options,
+            end_offsets,
+            shutdown: CancellationToken::new(),
+        })
+    }
 
-        // Initialize variable to check idle state
-        let msg = message;
+    /// Returns the token that stops the message stream once cancelled
+    pub fn shutdown_token(&self) -> CancellationToken {
+        self.shutdown.clone()
+    }
 
-        // Check if the stream is idle
-        if msg.is_none() {
-            
-            info!("Listening for messages... (retry={})", retry_counter);
+    /// Cancels the shutdown token when Ctrl-C is pressed
+    /// - Returns the handle of the listener task, which should be aborted once the consumer is done
+    pub fn shutdown_on_ctrl_c(&self) -> JoinHandle<()> {
+        let shutdown = self.shutdown_token();
+        tokio::spawn(async move {
+            if tokio::signal::ctrl_c().await.is_ok() {
+                info!("Ctrl-C received: Shutting down consumer");
+                shutdown.cancel();
+            }
+        })
+    }
 
-            // If the stream is idle, increase the retry counter
-            retry_counter += 1;
+    /// Returns the messages of the topic as a Stream
+    /// - Ends once a bound of the ConsumerOptions (messages, bytes, end of partitions) is reached
+    /// - With until_end, an idle poll ends the stream once every partition left to read is assigned and has a position:
+    ///   The partitions have nothing left to read (the high watermark may follow a control record, e.g. the commit
+    ///   marker of a transaction). Idle polls before that (e.g. while joining the group) count as retries
+    /// - Ends after max_retries subsequent idle polls (each lasting idle_timeout)
+    /// - Ends immediately once the shutdown token is cancelled
+    /// - Errors are yielded and count as idle polls
+    pub fn messages(&self) -> impl Stream<Item = KafkaResult<ConsumedMessage>> + '_ {
+        let bounds = Bounds::new(&self.options, self.end_offsets.clone());
+
+        futures::stream::unfold((0u32, bounds), move |(mut retry_counter, mut bounds)| async move {
+            loop {
+                if let Some(reason) = bounds.reached() {
+                    info!("Terminate listening for messages... ({})", reason);
+                    return None;
+                }
 
-            // Put the thread to sleep for 2 seconds
-            tokio::time::sleep(Duration::from_secs(2)).await;
+                // If max retries, end the stream
+                if retry_counter >= self.options.max_retries {
+                    info!("Terminate listening for messages... (max retries reached)");
+                    return None;
+                }
 
-            // If max retries, break the loop to exit the consumer stream
-            if retry_counter > 5 {
-                break;
-            }
-        } else if msg.is_some() {
-
-            // If the stream is not idle, reset the retry counter
-            retry_counter = 0;
-            
-            // Increase the message counter
-            message_counter += 1;
-
-            // Convert the message to a readable string in stdout
-            match msg {
-                Some(Ok(m)) => {
-                    let message = m.payload().unwrap().to_owned();
-                    message_content.insert(message_counter, String::from_utf8(message.clone()).unwrap());
-
-                    let bytes_received = bytecount::num_chars(&message) as u8;
-                    message_bytes.push(bytes_received);
-
-                    // Print the message (debugging/development)
-                    // TODO: change this before 1.0.0 // to info!()
-                    info!("Message: {}", String::from_utf8(message).unwrap());
-                    consumer.commit_message(&m, CommitMode::Sync).unwrap();
-
-                },
-                Some(Err(e)) => {
-                    warn!("Error while reading from stream: {}", e);
-                },
-                None => {
-                    warn!("Unexpected empty message");
-                    warn!("Shutting down consumer");
-                    // Looking at implementations in other languages (e.g. Java)
-                    // There is no complement to wakeup() / close() function in rdkafka gracefully
-                    // Therefore, we break the loop with an empty message
-                    break;
+                let received = tokio::select! {
+                    _ = self.shutdown.cancelled() => {
+                        info!("Terminate listening for messages... (shutdown requested)");
+                        return None;
+                    },
+                    received = tokio::time::timeout(self.options.idle_timeout, self.consumer.recv()) => received,
+                };
+
+                match received {
+                    // The stream is idle, increase the retry counter
+                    Err(_) => {
+                        // The assigned partitions have nothing left before their high watermark
+                        if bounds.until_end() && self.advance_to_position(&mut bounds) {
+                            let reason = bounds.reached().unwrap_or("no message before the end of partitions");
+                            info!("Terminate listening for messages... ({})", reason);
+                            return None;
+                        }
+
+                        retry_counter += 1;
+                        info!("Listening for messages... (retry={})", retry_counter);
+                    },
+                    Ok(Ok(m)) => {
+                        let message = ConsumedMessage::from_message(&m);
+                        info!("Message: {} (partition: {}, offset: {})", message.payload_str(), message.metadata.partition, message.metadata.offset);
+
+                        // Mark the message as processed, it is committed with the next (auto) commit
+                        if self.options.commit == CommitPolicy::OnRead {
+                            if let Err(e) = self.consumer.store_offset_from_message(&m) {
+                                warn!("Error while storing offset: {}", e);
+                            }
+                        }
+
+                        bounds.record(&message.metadata);
+
+                        // If the stream is not idle, reset the retry counter
+                        return Some((Ok(message), (0, bounds)));
+                    },
+                    Ok(Err(e)) => {
+                        warn!("Error while reading from stream: {}", e);
+                        return Some((Err(e), (retry_counter + 1, bounds)));
+                    }
                 }
             }
-            // let message = msg.unwrap().unwrap().payload().unwrap().to_owned();
-            // let readable_message = String::from_utf8(message).unwrap();
+        })
+    }
+
+    /// Marks the partitions as done whose consumer position reached the high watermark (see Bounds::advance_all)
+    /// - Returns true if every partition left to read is assigned and has a position
+    fn advance_to_position(&self, bounds: &mut Bounds) -> bool {
+        match self.consumer.position() {
+            Ok(positions) => {
+                let positions: HashMap<i32, i64> = positions.elements_for_topic(&self.topic).iter()
+                    .filter_map(|element| match element.offset() {
+                        Offset::Offset(position) => Some((element.partition(), position)),
+                        _ => None,
+                    })
+                    .collect();
+                bounds.advance_all(&positions)
+            },
+            Err(e) => {
+                warn!("Error while reading the consumer position: {}", e);
+                false
+            }
+        }
+    }
+
+    /// Commits the exact offsets of the consumed messages (synchronously)
+    /// - partitions: Offset range per partition (e.g. of a ConsumeReport), the offset after last_offset is committed
+    pub fn commit(&self, partitions: &BTreeMap<i32, PartitionSummary>) -> KafkaResult<()> {
+        if partitions.is_empty() {
+            return Ok(());
+        }
+
+        let mut offsets = TopicPartitionList::new();
+        for (partition, summary) in partitions {
+            offsets.add_partition_offset(&self.topic, *partition, Offset::Offset(summary.last_offset + 1))?;
         }
+
+        self.consumer.commit(&offsets, CommitMode::Sync)?;
+        info!("Offsets committed: {:?}", partitions.iter().map(|(partition, summary)| (partition, summary.last_offset + 1)).collect::<Vec<_>>());
+        Ok(())
     }
 
-        // Clone the message vector to calculate the total byte size
-        let vector = message_bytes.clone();
+    /// Commits the offsets of all yielded messages
+    /// - Should be called before the consumer is dropped for a clean shutdown
+    /// - Does nothing if the offsets are committed manually
+    pub fn close(&self) -> KafkaResult<()> {
+        if self.options.commit == CommitPolicy::Manual {
+            return Ok(());
+        }
+
+        match self.consumer.commit_consumer_state(CommitMode::Sync) {
+            Ok(()) => {
+                info!("Final offsets committed");
+                Ok(())
+            },
+            // Nothing was consumed, therefore nothing has to be committed
+            Err(KafkaError::ConsumerCommit(RDKafkaErrorCode::NoOffset)) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Reads from the Kafka topic. (Kafka Consumer)
+/// - Establishes a connection to the Kafka broker as defined in the kafka_key.json file
+/// - Reads the messages from the topic until a bound is reached, it is idle (see ConsumerOptions) or Ctrl-C is pressed
+/// - Commits the offsets of the messages read
+/// - Returns a ConsumeReport (messages with metadata, per partition summary, total messages and bytes)
+pub async fn read_from_kafka(topic: &str, options: &ConsumerOptions) -> Result<ConsumeReport, KafkaError>{
+
+    let source = new_kafka_source(topic, options.clone())?;
+    let ctrl_c = source.shutdown_on_ctrl_c();
 
-        let mut total_bytes: u32 = 0;
+    // Initialize the report that collects all messages and their metadata
+    let mut report = ConsumeReport::default();
 
-        for i in vector {
-            total_bytes += i as u32;
+    info!("Consumer starts...");
+
+    let mut messages = Box::pin(source.messages());
+    while let Some(message) = messages.next().await {
+        // Errors are already logged by the stream
+        if let Ok(message) = message {
+            report.add(message);
         }
+    }
+    drop(messages);
+
+    ctrl_c.abort();
+    source.close()?;
 
-        info!("Terminate listening for messages... (max retries reached)");
-        info!("Message received: {}", message_counter);
-        Ok((message_counter, message_content, message_bytes, total_bytes))
+    info!("Message received: {}, bytes received: {}", report.total_messages, report.total_bytes);
+    Ok(report)
 }
 
-pub async fn new_kafka_consumer() -> BaseConsumer {
-    // read the kafka details from a file and store them in a vector
-    let kafka_details = get_kafka_details().unwrap();
+/// Creates a new KafkaSource
+/// - Reads the Kafka details from a file, an unreadable config is returned as ClientCreation error
+/// - Subscribes to the topic
+pub fn new_kafka_source(topic: &str, options: ConsumerOptions) -> KafkaResult<KafkaSource> {
+    // read the kafka details from a file
+    let kafka_details = get_kafka_details()
+        .map_err(|e| KafkaError::ClientCreation(e.to_string()))?;
 
-    // Assign details to variables (for readability)
-    let bootstrap_servers = kafka_details.bootstrap_servers;
-    let group_id = kafka_details.group_id;
-    let message_timeout_ms = kafka_details.message_timeout_ms;
+    KafkaSource::new(&kafka_details, topic, options)
+}
+
+/// Creates a new Kafka consumer
+/// - Reads the Kafka details from a file, an unreadable config is returned as ClientCreation error
+/// - Returns the consumer (not subscribed to any topic)
+pub async fn new_kafka_consumer() -> KafkaResult<StreamConsumer> {
+    // read the kafka details from a file
+    let kafka_details = get_kafka_details()
+        .map_err(|e| KafkaError::ClientCreation(e.to_string()))?;
 
     // Create a new Kafka consumer (if not already existing)
-    let consumer: BaseConsumer = ClientConfig::new()
-        .set("bootstrap.servers", &bootstrap_servers)
-        .set("group.id", &group_id)
-        .set("message.timeout.ms", &message_timeout_ms.to_string())
-        .set("enable.auto.commit", "true")
-        .set("connections.max.idle.ms", "1000")
-        .create()
-        .expect("Error: Failed to create Kafka consumer");
-
-    // return the consumer
-    consumer
-}
\ No newline at end of file
+    consumer_config(&kafka_details, &kafka_details.group_id, CommitPolicy::OnRead).create()
+}
+
+/// Returns all partitions of the topic (without offsets)
+fn topic_partitions(consumer: &StreamConsumer, topic: &str, timeout: Duration) -> KafkaResult<TopicPartitionList> {
+    let metadata = consumer.fetch_metadata(Some(topic), timeout)?;
+
+    let mut partitions = TopicPartitionList::new();
+    for metadata_topic in metadata.topics() {
+        for partition in metadata_topic.partitions() {
+            partitions.add_partition(metadata_topic.name(), partition.id());
+        }
+    }
+
+    if partitions.count() == 0 {
+        warn!("No partitions found for topic: {}", topic);
+    }
+
+    Ok(partitions)
+}
+
+/// Returns the high watermark of every partition that has messages left to read
+/// - start_offsets: Position the consumer starts reading from (per partition)
+fn end_offsets(consumer: &StreamConsumer, topic: &str, start_offsets: &TopicPartitionList, timeout: Duration) -> KafkaResult<HashMap<i32, i64>> {
+    let mut end_offsets = HashMap::new();
+
+    for element in start_offsets.elements() {
+        let (low, high) = consumer.fetch_watermarks(topic, element.partition(), timeout)?;
+
+        let start = match element.offset() {
+            Offset::Beginning => low,
+            Offset::Offset(offset) => offset.max(low),
+            // Without a (committed) offset, the consumer starts at the end of the partition
+            _ => high,
+        };
+
+        if start < high {
+            end_offsets.insert(element.partition(), high);
+        }
+    }
+
+    info!("Partition(s) with messages left to read: {}", end_offsets.len());
+    Ok(end_offsets)
+}
+
+/// Builds the client configuration shared by all consumers
+/// - OnRead: Offsets are stored explicitly once a message was yielded and committed automatically
+/// - Manual: Automatic commits are disabled
+/// - message.timeout.ms only applies to producers, the consumer uses the session timeout (if configured)
+fn consumer_config(kafka_details: &KafkaConfig, group_id: &str, commit: CommitPolicy) -> ClientConfig {
+    let auto_commit = match commit {
+        CommitPolicy::OnRead => "true",
+        CommitPolicy::Manual => "false",
+    };
+
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", &kafka_details.bootstrap_servers)
+        .set("group.id", group_id)
+        .set("connections.max.idle.ms", kafka_details.connection_max_idle_ms.to_string())
+        .set("enable.auto.commit", auto_commit)
+        .set("enable.auto.offset.store", "false");
+    if let Some(session_timeout_ms) = kafka_details.session_timeout_ms {
+        config.set("session.timeout.ms", session_timeout_ms.to_string());
+    }
+    config
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kafka::test_helper::metadata;
+
+    #[test]
+    fn test_parse_offset() {
+        let result = StartPosition::parse_offset("2:42");
+        assert_eq!(result, Ok(StartPosition::Offset { partition: 2, offset: 42 }));
+    }
+
+    #[test]
+    fn test_parse_invalid_offset() {
+        assert!(StartPosition::parse_offset("42").is_err());
+        assert!(StartPosition::parse_offset("a:42").is_err());
+    }
+
+    #[test]
+    fn test_parse_since() {
+        let result = StartPosition::parse_since("2023-03-01T00:00:00+01:00");
+        assert_eq!(result, Ok(StartPosition::Timestamp(1_677_625_200_000)));
+    }
+
+    #[test]
+    fn test_consumer_config() {
+        let mut kafka_details = KafkaConfig {
+            bootstrap_servers: "localhost:9092".to_string(),
+            group_id: "group".to_string(),
+            message_timeout_ms: 6000,
+            connection_max_idle_ms: 1000,
+            session_timeout_ms: None,
+        };
+        let config = consumer_config(&kafka_details, "group", CommitPolicy::Manual);
+        assert_eq!(config.get("message.timeout.ms"), None);
+        assert_eq!(config.get("session.timeout.ms"), None);
+        assert_eq!(config.get("enable.auto.commit"), Some("false"));
+
+        kafka_details.session_timeout_ms = Some(30000);
+        let config = consumer_config(&kafka_details, "group", CommitPolicy::OnRead);
+        assert_eq!(config.get("session.timeout.ms"), Some("30000"));
+    }
+
+    #[test]
+    fn test_bounds_max_messages() {
+        let options = ConsumerOptions { max_messages: Some(2), ..Default::default() };
+        let mut bounds = Bounds::new(&options, None);
+
+        bounds.record(&metadata(0, 0, 10));
+        assert!(bounds.reached().is_none());
+        bounds.record(&metadata(0, 1, 10));
+        assert_eq!(bounds.reached(), Some("max messages reached"));
+    }
+
+    #[test]
+    fn test_bounds_max_bytes() {
+        let options = ConsumerOptions { max_bytes: Some(25), ..Default::default() };
+        let mut bounds = Bounds::new(&options, None);
+
+        bounds.record(&metadata(0, 0, 20));
+        assert!(bounds.reached().is_none());
+        bounds.record(&metadata(0, 1, 20));
+        assert_eq!(bounds.reached(), Some("max bytes reached"));
+    }
+
+    #[test]
+    fn test_bounds_until_end() {
+        let options = ConsumerOptions { until_end: true, ..Default::default() };
+        let end_offsets = HashMap::from([(0, 2), (1, 1)]);
+        let mut bounds = Bounds::new(&options, Some(end_offsets));
+
+        bounds.record(&metadata(0, 0, 1));
+        bounds.record(&metadata(1, 0, 1));
+        assert!(bounds.reached().is_none());
+        bounds.record(&metadata(0, 1, 1));
+        assert_eq!(bounds.reached(), Some("end of partitions reached"));
+    }
+
+    #[test]
+    fn test_bounds_until_end_after_control_record() {
+        let options = ConsumerOptions { until_end: true, ..Default::default() };
+        // The last offset of the partition is the commit marker of a transaction
+        let mut bounds = Bounds::new(&options, Some(HashMap::from([(0, 3)])));
+
+        bounds.record(&metadata(0, 1, 1));
+        assert!(bounds.reached().is_none());
+        bounds.advance(0, 3);
+        assert_eq!(bounds.reached(), Some("end of partitions reached"));
+    }
+
+    #[test]
+    fn test_bounds_until_end_waits_for_assignment() {
+        let options = ConsumerOptions { until_end: true, ..Default::default() };
+        let mut bounds = Bounds::new(&options, Some(HashMap::from([(0, 3), (1, 5)])));
+
+        // Nothing assigned yet (e.g. joining the group)
+        assert!(!bounds.advance_all(&HashMap::new()));
+        // Partition 1 is not assigned yet
+        assert!(!bounds.advance_all(&HashMap::from([(0, 3)])));
+        assert!(bounds.reached().is_none());
+        assert!(bounds.advance_all(&HashMap::from([(1, 2)])));
+        assert!(bounds.reached().is_none());
+    }
+
+    #[test]
+    fn test_bounds_until_end_without_messages_left() {
+        let options = ConsumerOptions { until_end: true, ..Default::default() };
+        let bounds = Bounds::new(&options, Some(HashMap::new()));
+        assert!(bounds.reached().is_some());
+    }
+
+    #[test]
+    fn test_parse_invalid_since() {
+        assert!(StartPosition::parse_since("yesterday").is_err());
+    }
+}
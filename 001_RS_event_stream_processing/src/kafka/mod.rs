@@ -1,2 +1,6 @@
 pub mod consumer;
-pub mod producer;
\ No newline at end of file
+pub mod producer;
+pub mod report;
+
+#[cfg(test)]
+pub(crate) mod test_helper;
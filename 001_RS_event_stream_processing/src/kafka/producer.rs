@@ -3,80 +3,267 @@
     It mimics the producer in the project setup
 */
 
+use crate::config::KafkaConfig;
 use crate::get_kafka_details;
+use crate::kafka::report::{DeliveryFailure, MessageMetadata, ProduceReport};
 
-use std::time::Duration;
+use std::io::Read;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use futures::future::join_all;
 use log::{warn, info};
 use uuid::Uuid;
 
 use rdkafka::config::ClientConfig;
-use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::error::KafkaError;
+use rdkafka::message::{Header, OwnedHeaders};
+use rdkafka::producer::{FutureProducer, FutureRecord, Producer};
+use rdkafka::error::{KafkaError, KafkaResult};
+
+/// A single record that should be produced to Kafka
+/// - key: Key of the record (None produces a record without key)
+/// - headers: Name and value of every header (e.g. restored from an export)
+/// - payload: Content of the record (e.g. the saved API response)
+#[derive(Debug, Clone)]
+pub struct OutgoingRecord {
+    pub key: Option<String>,
+    pub headers: Vec<(String, Option<String>)>,
+    pub payload: Vec<u8>,
+}
+
+impl OutgoingRecord {
+    /// Creates a new record with a unique uuid as key
+    pub fn new(payload: impl Into<Vec<u8>>) -> Self {
+        OutgoingRecord {
+            key: Some(Uuid::new_v4().to_string()),
+            headers: Vec::new(),
+            payload: payload.into(),
+        }
+    }
+
+    /// Adds a header to the record
+    pub fn with_header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), Some(value.to_string())));
+        self
+    }
+
+    /// Returns the headers in the format of the producer
+    fn owned_headers(&self) -> Option<OwnedHeaders> {
+        if self.headers.is_empty() {
+            return None;
+        }
+
+        let headers = self.headers.iter().fold(OwnedHeaders::new_with_capacity(self.headers.len()), |headers, (name, value)| {
+            headers.insert(Header { key: name, value: value.as_deref() })
+        });
+        Some(headers)
+    }
+}
+
+/// Producer that is created once and reused for many records
+/// - Wraps a FutureProducer configured via kafka_config.json
+/// - Sends batches concurrently and awaits all delivery reports
+pub struct KafkaSink {
+    producer: FutureProducer,
+    queue_timeout: Duration,
+}
+
+impl KafkaSink {
+
+    /// Creates a new sink from the Kafka configuration
+    pub fn new(kafka_details: &KafkaConfig) -> KafkaResult<KafkaSink> {
+        let producer: FutureProducer = producer_config(kafka_details).create()?;
+
+        Ok(KafkaSink {
+            producer,
+            // Wait as long as a message may take to be delivered if the local queue is full
+            queue_timeout: Duration::from_millis(kafka_details.message_timeout_ms as u64),
+        })
+    }
+
+    /// Sends all records to the topic
+    /// - All records are enqueued at once, the delivery reports are awaited afterwards
+    /// - Returns the aggregated report of the batch
+    pub async fn send_batch(&self, topic_name: &str, records: &[OutgoingRecord]) -> ProduceReport {
+
+        // The timestamp is set explicitly to report it without another broker roundtrip
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as i64)
+            .unwrap_or_default();
+
+        let deliveries = records.iter().map(|record| {
+            let mut future_record: FutureRecord<str, Vec<u8>> = FutureRecord::to(topic_name)
+                .payload(&record.payload)
+                .timestamp(timestamp);
+            if let Some(key) = &record.key {
+                future_record = future_record.key(key);
+            }
+            if let Some(headers) = record.owned_headers() {
+                future_record = future_record.headers(headers);
+            }
+            self.producer.send(future_record, self.queue_timeout)
+        });
+
+        let results = join_all(deliveries).await;
+
+        let mut report = ProduceReport::default();
+        for (index, (record, result)) in records.iter().zip(results).enumerate() {
+            match result {
+                Ok((partition, offset)) => report.record_delivery(index, MessageMetadata {
+                    topic: topic_name.to_string(),
+                    partition,
+                    offset,
+                    key: record.key.clone(),
+                    timestamp: Some(timestamp),
+                    size: record.payload.len() as u64,
+                }),
+                Err((e, _message)) => {
+                    warn!("Error while sending message {}: {}", record.key.as_deref().unwrap_or_default(), e);
+                    report.failures.push(DeliveryFailure {
+                        index,
+                        key: record.key.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        info!("Message(s) sent: {}, failed: {}", report.total_messages, report.failures.len());
+        report
+    }
+
+    /// Waits until all outstanding messages are delivered
+    pub fn flush(&self, timeout: Duration) -> KafkaResult<()> {
+        self.producer.flush(timeout)
+    }
+}
 
 /// Pushes a message to the Kafka topic. (Kafka Producer)
 /// - Establishes a connection to the Kafka broker as defined in the kafka_key.json file
 /// - Creates a new record with the message content (e.g. the saved API response)
-/// - Sends the record
-/// - Returns the number of messages sent, number of bytes sent, key of the message
-pub async fn push_to_kafka(topic_name: &str, message_content: &str) -> Result<(u8, u8, String), KafkaError> {
+/// - Sends the record via a KafkaSink
+/// - Returns the report with the metadata (partition, offset, key, timestamp, size) of the message
+pub async fn push_to_kafka(topic_name: &str, message_content: &str) -> Result<ProduceReport, KafkaError> {
+    push_record_to_kafka(topic_name, OutgoingRecord::new(message_content)).await
+}
 
-    // Initialize the unique uuid as key (Maybe use a timestamp instead?)
-    let key =  Uuid::new_v4().to_string();
+/// Pushes a record (e.g. with headers) to the Kafka topic
+/// - See push_to_kafka
+pub async fn push_record_to_kafka(topic_name: &str, record: OutgoingRecord) -> Result<ProduceReport, KafkaError> {
 
     // Initialize the Kafka producer
-    let producer = new_kafka_producer().await;
-
-    let response_body: Vec<u8> = Vec::from(message_content);
+    let sink = new_kafka_sink()?;
 
-    // Create a new record
-    let record = FutureRecord::to(topic_name)
-        .payload(&response_body)
-        .key(&key);
-
-    // Send the record
-    let delivery_status = producer.send(record, Duration::from_secs(0)).await;
+    let report = sink.send_batch(topic_name, &[record]).await;
 
     // Return the result of the operation to CLI
-    match delivery_status {
-        Ok(_) => {
-            // Calculate the number of bytes sent
-            let bytes_sent: u8 = bytecount::num_chars(&response_body) as u8;
+    match report.messages.first() {
+        Some(message) => {
             info!("Message sent successfully");
-            info!("Key: {}", &key);
-            info!("Bytes sent: {}", bytes_sent);
+            info!("Key: {:?}", &message.key);
+            info!("Bytes sent: {}", message.size);
 
-            // Return the result
-            Ok((1, bytes_sent, key))
-            
+            Ok(report)
         },
-        Err(e) => {
-            warn!("Error while sending message: {:?}", e);
+        None => {
+            warn!("Error while sending message: {:?}", report.failures);
             Err(KafkaError::NoMessageReceived)
         }
     }
 }
 
+/// Reads the records that should be produced from a file
+/// - Reads from stdin if the path is "-"
+/// - Splits the content into records (see parse_records)
+/// - Returns the records in the order of the input
+pub fn read_records(path: &str) -> std::io::Result<Vec<String>> {
+
+    let mut content = String::new();
+
+    if path == "-" {
+        std::io::stdin().read_to_string(&mut content)?;
+    } else {
+        content = std::fs::read_to_string(path)?;
+    }
+
+    Ok(parse_records(&content))
+}
+
+/// Splits the content of a file into records
+/// - A single JSON document (e.g. a pretty printed API response) results in one record
+/// - Newline-delimited JSON results in one record per non-empty line
+pub fn parse_records(content: &str) -> Vec<String> {
+
+    // A single document may span multiple lines, therefore it is checked first
+    if let Ok(document) = serde_json::from_str::<serde_json::Value>(content) {
+        return vec![document.to_string()];
+    }
+
+    content
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| line.to_string())
+        .collect()
+}
+
 /// Creates a new Kafka producer
 /// - Reads the Kafka details from a file
 /// - Creates a new Kafka producer
 /// - Returns the producer
 pub async fn new_kafka_producer() -> FutureProducer {
-    // read the kafka details from a file and store them in a vector
+    // read the kafka details from a file
     let kafka_details = get_kafka_details().unwrap();
 
-    // Assign details to variables (for readability)
-    let bootstrap_servers = kafka_details.bootstrap_servers;
-    let group_id = kafka_details.group_id;
-    let message_timeout_ms = kafka_details.message_timeout_ms;
-
     // Create a new Kafka producer (if not already existing)
-    let producer: &FutureProducer = &ClientConfig::new()
-        .set("bootstrap.servers", &bootstrap_servers)
-        .set("group.id", &group_id)
-        .set("message.timeout.ms", &message_timeout_ms.to_string())
+    producer_config(&kafka_details)
         .create()
-        .expect("Error: Failed to create Kafka producer");
+        .expect("Error: Failed to create Kafka producer")
+}
+
+/// Creates a new KafkaSink
+/// - Reads the Kafka details from a file, an unreadable config is returned as ClientCreation error
+/// - Returns the sink, which should be reused for all records of a run
+pub fn new_kafka_sink() -> KafkaResult<KafkaSink> {
+    // read the kafka details from a file
+    let kafka_details = get_kafka_details()
+        .map_err(|e| KafkaError::ClientCreation(e.to_string()))?;
+
+    KafkaSink::new(&kafka_details)
+}
+
+/// Builds the client configuration shared by all producers
+fn producer_config(kafka_details: &KafkaConfig) -> ClientConfig {
+    let mut config = ClientConfig::new();
+    config
+        .set("bootstrap.servers", &kafka_details.bootstrap_servers)
+        .set("group.id", &kafka_details.group_id)
+        .set("message.timeout.ms", kafka_details.message_timeout_ms.to_string());
+    config
+}
 
-    // return the producer
-    producer.to_owned()
-}
\ No newline at end of file
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_single_document() {
+        let content = "{\n    \"base\": \"EUR\",\n    \"rates\": { \"USD\": 1.07 }\n}\n";
+        let records = parse_records(content);
+        assert_eq!(records, vec!["{\"base\":\"EUR\",\"rates\":{\"USD\":1.07}}"]);
+    }
+
+    #[test]
+    fn test_parse_ndjson() {
+        let content = "{\"id\": 1}\n\n{\"id\": 2}\n{\"id\": 3}\n";
+        let records = parse_records(content);
+        assert_eq!(records, vec!["{\"id\": 1}", "{\"id\": 2}", "{\"id\": 3}"]);
+    }
+
+    #[test]
+    fn test_parse_empty_content() {
+        assert!(parse_records("\n\n").is_empty());
+    }
+}
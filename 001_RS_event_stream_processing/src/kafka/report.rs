@@ -0,0 +1,198 @@
+/*
+    This file contains the reports returned by the Kafka producer and consumer
+    All counters are u64 to handle long running jobs and large messages
+*/
+
+use std::collections::BTreeMap;
+
+use rdkafka::message::{Headers, Message};
+
+/// Metadata of a single Kafka message
+/// - key: Key of the message (if present and valid utf-8)
+/// - timestamp: Create/append time in milliseconds since epoch (if known)
+/// - size: Payload size in bytes
+#[derive(Debug, Clone, PartialEq)]
+pub struct MessageMetadata {
+    pub topic: String,
+    pub partition: i32,
+    pub offset: i64,
+    pub key: Option<String>,
+    pub timestamp: Option<i64>,
+    pub size: u64,
+}
+
+impl MessageMetadata {
+    /// Reads the metadata from any rdkafka message
+    pub fn from_message<M: Message>(message: &M) -> Self {
+        MessageMetadata {
+            topic: message.topic().to_string(),
+            partition: message.partition(),
+            offset: message.offset(),
+            key: message.key().map(|key| String::from_utf8_lossy(key).to_string()),
+            timestamp: message.timestamp().to_millis(),
+            size: message.payload().map(|payload| payload.len()).unwrap_or_default() as u64,
+        }
+    }
+}
+
+/// A message read by the consumer
+/// - metadata: Topic, partition, offset, key, timestamp and size of the message
+/// - headers: Name and (lossy utf-8) value of every header
+/// - payload: Content of the message
+#[derive(Debug, Clone)]
+pub struct ConsumedMessage {
+    pub metadata: MessageMetadata,
+    pub headers: Vec<(String, Option<String>)>,
+    pub payload: Vec<u8>,
+}
+
+impl ConsumedMessage {
+    /// Copies metadata, headers and payload from any rdkafka message
+    pub fn from_message<M: Message>(message: &M) -> Self {
+        let headers = message.headers()
+            .map(|headers| headers.iter()
+                .map(|header| (header.key.to_string(), header.value.map(|value| String::from_utf8_lossy(value).to_string())))
+                .collect())
+            .unwrap_or_default();
+
+        ConsumedMessage {
+            metadata: MessageMetadata::from_message(message),
+            headers,
+            payload: message.payload().map(|payload| payload.to_vec()).unwrap_or_default(),
+        }
+    }
+
+    /// Returns the payload as (lossy) utf-8 string
+    pub fn payload_str(&self) -> String {
+        String::from_utf8_lossy(&self.payload).to_string()
+    }
+}
+
+/// Number of messages, bytes and the offset range of a single partition
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PartitionSummary {
+    pub messages: u64,
+    pub bytes: u64,
+    pub first_offset: i64,
+    pub last_offset: i64,
+}
+
+impl PartitionSummary {
+    fn add(&mut self, metadata: &MessageMetadata) {
+        if self.messages == 0 {
+            self.first_offset = metadata.offset;
+            self.last_offset = metadata.offset;
+        }
+        self.messages += 1;
+        self.bytes += metadata.size;
+        self.first_offset = self.first_offset.min(metadata.offset);
+        self.last_offset = self.last_offset.max(metadata.offset);
+    }
+}
+
+/// Details of a single record that could not be delivered
+/// - index: Position of the record in the batch
+#[derive(Debug, Clone)]
+pub struct DeliveryFailure {
+    pub index: usize,
+    pub key: Option<String>,
+    pub error: String,
+}
+
+/// Result of the producer
+/// - messages: Metadata of every delivered message
+/// - positions: Position of every delivered message in the batch (same order as messages)
+/// - failures: Records that could not be delivered
+/// - partitions: Messages, bytes and offset range per partition
+/// - total_messages/total_bytes: Totals of all delivered messages
+#[derive(Debug, Clone, Default)]
+pub struct ProduceReport {
+    pub messages: Vec<MessageMetadata>,
+    pub positions: Vec<usize>,
+    pub failures: Vec<DeliveryFailure>,
+    pub partitions: BTreeMap<i32, PartitionSummary>,
+    pub total_messages: u64,
+    pub total_bytes: u64,
+}
+
+impl ProduceReport {
+
+    /// Adds the delivered message at the given position of the batch to the report
+    pub fn record_delivery(&mut self, index: usize, metadata: MessageMetadata) {
+        self.partitions.entry(metadata.partition).or_default().add(&metadata);
+        self.total_messages += 1;
+        self.total_bytes += metadata.size;
+        self.messages.push(metadata);
+        self.positions.push(index);
+    }
+
+    /// Returns true if every record was delivered
+    pub fn is_complete(&self) -> bool {
+        self.failures.is_empty()
+    }
+}
+
+/// Result of the consumer
+/// - messages: Every message read (metadata and payload)
+/// - partitions: Messages, bytes and offset range per partition
+/// - total_messages/total_bytes: Totals of all messages read
+#[derive(Debug, Clone, Default)]
+pub struct ConsumeReport {
+    pub messages: Vec<ConsumedMessage>,
+    pub partitions: BTreeMap<i32, PartitionSummary>,
+    pub total_messages: u64,
+    pub total_bytes: u64,
+}
+
+impl ConsumeReport {
+
+    /// Adds a consumed message to the report
+    pub fn add(&mut self, message: ConsumedMessage) {
+        self.partitions.entry(message.metadata.partition).or_default().add(&message.metadata);
+        self.total_messages += 1;
+        self.total_bytes += message.metadata.size;
+        self.messages.push(message);
+    }
+
+    /// Returns true if no message was read
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kafka::test_helper::metadata;
+
+    #[test]
+    fn test_produce_report_totals() {
+        let mut report = ProduceReport::default();
+        report.record_delivery(0, metadata(0, 7, 300));
+        report.record_delivery(1, metadata(1, 3, 300));
+        report.record_delivery(2, metadata(0, 5, 300));
+
+        assert!(report.is_complete());
+        assert_eq!(report.total_messages, 3);
+        assert_eq!(report.total_bytes, 900);
+        assert_eq!(report.partitions[&0].messages, 2);
+        assert_eq!(report.partitions[&0].first_offset, 5);
+        assert_eq!(report.partitions[&0].last_offset, 7);
+        assert_eq!(report.partitions[&1].bytes, 300);
+    }
+
+    #[test]
+    fn test_consume_report_counters_exceed_u8() {
+        let mut report = ConsumeReport::default();
+        for offset in 0..300 {
+            report.add(ConsumedMessage { metadata: metadata(0, offset, 1000), headers: Vec::new(), payload: Vec::new() });
+        }
+
+        assert_eq!(report.total_messages, 300);
+        assert_eq!(report.total_bytes, 300_000);
+        assert_eq!(report.partitions[&0].last_offset, 299);
+    }
+}
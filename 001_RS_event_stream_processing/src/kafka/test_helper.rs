@@ -0,0 +1,17 @@
+/*
+    This file contains the fixtures shared by the unit tests of the Kafka reports and consumer
+*/
+
+use crate::kafka::report::MessageMetadata;
+
+/// Returns the metadata of a message of the topic test without key and timestamp
+pub fn metadata(partition: i32, offset: i64, size: u64) -> MessageMetadata {
+    MessageMetadata {
+        topic: "test".to_string(),
+        partition,
+        offset,
+        key: None,
+        timestamp: None,
+        size,
+    }
+}
@@ -1,16 +1,21 @@
-mod config;
-mod errors;
+pub mod config;
+pub mod errors;
 mod request;
 mod response;
 pub mod cli;
 pub mod kafka;
 pub mod azure;
+pub mod blobs;
+pub mod export;
+pub mod forward;
+pub mod replay;
+pub mod retention;
+pub mod storage;
 
 use anyhow::anyhow;
-use config::{ApiDetails, AzureConfig, KafkaConfig};
-use errors::KeyError;
+use config::{ApiDetails, AzureConfig, EncryptionConfig, KafkaConfig, RetentionConfig, SinkConfig};
+use errors::{CredentialError, EncryptionError, KeyError};
 use log::{info, warn, error};
-use reqwest::Error;
 use jsonschema::{Draft, JSONSchema};
 use std::{collections::HashMap};
 
@@ -107,31 +112,77 @@ fn get_api_url(api_name: &str) -> Result<String, KeyError> {
 
 /// Read the Azure details from a file
 // - Returns a AzureConfig struct
-fn get_azure_details() -> Result<AzureConfig, Error> {
+fn get_azure_details() -> Result<AzureConfig, CredentialError> {
 
     // expand the path to the config file
     let path = shellexpand::tilde("~/.config/exchange/azure_config.json").to_string();
     // Read the azure details from a file and store them in a vector
-    let azure_details = serde_json::from_str::<AzureConfig>(
-        &std::fs::read_to_string(path).unwrap(),
-    )
-    .unwrap();
-    Ok(azure_details)
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| CredentialError::MissingConfig(format!("{}: {}", path, e)))?;
+    serde_json::from_str::<AzureConfig>(&content)
+        .map_err(|e| CredentialError::MissingConfig(format!("{}: {}", path, e)))
 }
 
 /// Read the Kafka details from a file
+// - Any error while reading or parsing the file is returned
 // - Returns a KafkaConfig struct
-fn get_kafka_details() -> Result<KafkaConfig, Error> {
+fn get_kafka_details() -> Result<KafkaConfig, anyhow::Error> {
 
     // expand the path to the config file
     let path = shellexpand::tilde("~/.config/exchange/kafka_config.json").to_string();
 
-    // Read the kafka details from a file and store them in a vector
-    let kafka_details = serde_json::from_str::<KafkaConfig>(
-        &std::fs::read_to_string(path).unwrap(),
-    )
-    .unwrap();
-    Ok(kafka_details)
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| anyhow!("Error reading {}: {}", path, e))?;
+    serde_json::from_str::<KafkaConfig>(&content)
+        .map_err(|e| anyhow!("Error parsing {}: {}", path, e))
+}
+
+/// Read the sink details from a file
+// - The file is optional, Azure Blob Storage is used if it does not exist
+// - Any other error while reading or parsing the file is returned
+// - Returns a SinkConfig struct
+fn get_sink_details() -> Result<SinkConfig, anyhow::Error> {
+
+    // expand the path to the config file
+    let path = shellexpand::tilde("~/.config/exchange/sink_config.json").to_string();
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str::<SinkConfig>(&content)
+            .map_err(|e| anyhow!("Error parsing {}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(SinkConfig::default()),
+        Err(e) => Err(anyhow!("Error reading {}: {}", path, e)),
+    }
+}
+
+/// Read the retention rules from a file
+// - The file is optional, there are no rules if it does not exist
+// - Any other error while reading the file (e.g. permissions) is returned
+// - Returns a RetentionConfig struct
+fn get_retention_details() -> Result<RetentionConfig, anyhow::Error> {
+
+    // expand the path to the config file
+    let path = shellexpand::tilde("~/.config/exchange/retention_config.json").to_string();
+
+    match std::fs::read_to_string(&path) {
+        Ok(content) => serde_json::from_str::<RetentionConfig>(&content)
+            .map_err(|e| anyhow!("Error parsing {}: {}", path, e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(RetentionConfig::default()),
+        Err(e) => Err(anyhow!("Error reading {}: {}", path, e)),
+    }
+}
+
+/// Read the encryption details from a file
+// - The file is only needed to write encrypted blobs and to read them
+// - Returns a EncryptionConfig struct
+fn get_encryption_details() -> Result<EncryptionConfig, EncryptionError> {
+
+    // expand the path to the config file
+    let path = shellexpand::tilde("~/.config/exchange/encryption_config.json").to_string();
+
+    let content = std::fs::read_to_string(&path)
+        .map_err(|e| EncryptionError::MissingConfig(format!("{}: {}", path, e)))?;
+    serde_json::from_str::<EncryptionConfig>(&content)
+        .map_err(|e| EncryptionError::MissingConfig(format!("{}: {}", path, e)))
 }
 
 // --------------------
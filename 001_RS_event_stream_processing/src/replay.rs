@@ -0,0 +1,311 @@
+/*
+    This file contains the replay pipeline (Read -> Produce)
+    Exported blobs are produced back into a topic, the progress is kept in a checkpoint file
+*/
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use log::{info, warn, error};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::azure::encryption::DecryptionKeys;
+use crate::export::envelope::Envelope;
+use crate::kafka::producer::{new_kafka_sink, KafkaSink, OutgoingRecord};
+use crate::storage::{read_blob, BlobStore};
+
+/// Options for the replay pipeline
+/// - batch_size: Number of records produced at once (at most rate)
+/// - rate: Maximum number of records per second (no limit if None)
+/// - checkpoint: File the progress is stored in, a replay with the same file resumes where it stopped
+#[derive(Debug, Clone)]
+pub struct ReplayOptions {
+    pub batch_size: usize,
+    pub rate: Option<u64>,
+    pub checkpoint: Option<PathBuf>,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            batch_size: 500,
+            rate: None,
+            checkpoint: None,
+        }
+    }
+}
+
+/// Result of the replay pipeline
+/// - blobs: Number of blobs replayed completely
+/// - messages/bytes: Number of records and payload bytes produced
+/// - skipped: Number of records skipped because the checkpoint marks them as produced
+#[derive(Debug, Clone, Default)]
+pub struct ReplayReport {
+    pub blobs: u64,
+    pub messages: u64,
+    pub bytes: u64,
+    pub skipped: u64,
+}
+
+/// Progress of a replay
+/// - container/prefix: Blobs the checkpoint belongs to, it can not be used for another replay
+/// - completed: Blobs that were produced completely
+/// - current/records: Blob that is produced and the number of its records already produced
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ReplayCheckpoint {
+    pub container: String,
+    pub prefix: Option<String>,
+    pub completed: BTreeSet<String>,
+    pub current: Option<String>,
+    pub records: usize,
+}
+
+impl ReplayCheckpoint {
+
+    /// Loads the checkpoint, a missing file results in an empty checkpoint
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        match std::fs::read(path) {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ReplayCheckpoint::default()),
+            Err(e) => Err(anyhow!("Error reading checkpoint {}: {}", path.display(), e)),
+        }
+    }
+
+    /// Saves the checkpoint (via a temporary file, so that it is never partially written)
+    pub fn save(&self, path: &Path) -> Result<(), anyhow::Error> {
+        let temporary = path.with_extension("tmp");
+        std::fs::write(&temporary, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Returns an error if the checkpoint belongs to another container or prefix
+    /// - An empty checkpoint (e.g. a new file) is taken over for the replay
+    fn claim(&mut self, container_name: &str, prefix: Option<&str>) -> Result<(), anyhow::Error> {
+        if self.container.is_empty() && self.completed.is_empty() && self.current.is_none() {
+            self.container = container_name.to_string();
+            self.prefix = prefix.map(str::to_string);
+        }
+
+        if self.container != container_name || self.prefix.as_deref() != prefix {
+            return Err(anyhow!("Checkpoint belongs to container {} (prefix: {:?}), please use another checkpoint file", self.container, self.prefix));
+        }
+        Ok(())
+    }
+
+    /// Returns the number of records of the blob that were already produced
+    fn produced(&self, blob_name: &str) -> usize {
+        match &self.current {
+            Some(current) if current == blob_name => self.records,
+            _ => 0,
+        }
+    }
+}
+
+/// Splits an exported blob into records
+/// - Envelopes (ndjson or json-array format) restore key, headers and payload of the original message
+/// - Other JSON documents and lines (e.g. raw format) are produced as payload without key
+pub fn parse_export(content: &[u8]) -> Vec<OutgoingRecord> {
+
+    // A single document (json-array format or a written file) may span multiple lines
+    if let Ok(document) = serde_json::from_slice::<Value>(content) {
+        return match document {
+            Value::Array(values) => values.into_iter().map(value_to_record).collect(),
+            value => vec![value_to_record(value)],
+        };
+    }
+
+    String::from_utf8_lossy(content)
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| match serde_json::from_str::<Value>(line) {
+            Ok(value) => value_to_record(value),
+            Err(_) => raw_record(line.as_bytes().to_vec()),
+        })
+        .collect()
+}
+
+/// Converts a JSON value into a record, envelopes are unwrapped
+fn value_to_record(value: Value) -> OutgoingRecord {
+    match serde_json::from_value::<Envelope>(value.clone()) {
+        Ok(envelope) => envelope.into_record(),
+        Err(_) => raw_record(value.to_string().into_bytes()),
+    }
+}
+
+/// Record without key and headers
+fn raw_record(payload: Vec<u8>) -> OutgoingRecord {
+    OutgoingRecord {
+        key: None,
+        headers: Vec::new(),
+        payload,
+    }
+}
+
+/// Replays the exported blobs of a container into a topic
+/// - Reads every blob matching the prefix in the order of the blob names
+/// - Restores keys and headers of the exported messages (see parse_export)
+/// - Produces the records in batches, limited to the given rate (a batch holds at most the records of one second)
+/// - Updates the checkpoint after every batch, a replay with the same checkpoint skips produced records
+/// - Returns an error if a record could not be delivered, the checkpoint then points to the failed batch
+pub async fn replay_to_kafka(store: &dyn BlobStore, container_name: &str, prefix: Option<&str>, topic: &str, options: &ReplayOptions) -> Result<ReplayReport, anyhow::Error> {
+
+    let mut checkpoint = match &options.checkpoint {
+        Some(path) => ReplayCheckpoint::load(path)?,
+        None => ReplayCheckpoint::default(),
+    };
+    checkpoint.claim(container_name, prefix)?;
+
+    let mut blobs: Vec<String> = store.list(container_name, prefix).await?
+        .into_iter()
+        .map(|blob| blob.name)
+        .collect();
+    blobs.sort();
+
+    // A batch is sent at once, so it never holds more records than the rate allows per second
+    let batch_size = match options.rate {
+        Some(rate) if rate > 0 => options.batch_size.max(1).min(usize::try_from(rate).unwrap_or(usize::MAX)),
+        _ => options.batch_size.max(1),
+    };
+
+    let sink = new_kafka_sink()?;
+    let keys = DecryptionKeys::default();
+    let mut report = ReplayReport::default();
+    let started = Instant::now();
+
+    for blob_name in blobs {
+        if checkpoint.completed.contains(&blob_name) {
+            info!("Blob {} already replayed: Skipping", &blob_name);
+            continue;
+        }
+
+        let content = read_blob(store, container_name, &blob_name, &keys).await?;
+        let records = parse_export(&content);
+
+        let produced = checkpoint.produced(&blob_name).min(records.len());
+        report.skipped += produced as u64;
+        info!("Replaying blob {}: {} record(s), {} already produced", &blob_name, records.len(), produced);
+
+        for (index, batch) in records[produced..].chunks(batch_size).enumerate() {
+            send_batch(&sink, topic, batch, &mut report).await?;
+
+            checkpoint.current = Some(blob_name.clone());
+            checkpoint.records = produced + index * batch_size + batch.len();
+            save_checkpoint(&checkpoint, options)?;
+
+            throttle(started, report.messages, options.rate).await;
+        }
+
+        checkpoint.completed.insert(blob_name.clone());
+        checkpoint.current = None;
+        checkpoint.records = 0;
+        save_checkpoint(&checkpoint, options)?;
+        report.blobs += 1;
+    }
+
+    if report.messages == 0 && report.skipped == 0 {
+        warn!("No records found in container {} (prefix: {:?})", container_name, prefix);
+    }
+
+    Ok(report)
+}
+
+/// Produces a batch, any delivery failure stops the replay
+async fn send_batch(sink: &KafkaSink, topic: &str, batch: &[OutgoingRecord], report: &mut ReplayReport) -> Result<(), anyhow::Error> {
+
+    let produced = sink.send_batch(topic, batch).await;
+
+    report.messages += produced.total_messages;
+    report.bytes += produced.total_bytes;
+
+    if !produced.is_complete() {
+        for failure in &produced.failures {
+            error!("Error while replaying record {} of the batch: {}", failure.index + 1, failure.error);
+        }
+        return Err(anyhow!("{} record(s) could not be delivered to {}: The batch is replayed again on resume", produced.failures.len(), topic));
+    }
+
+    Ok(())
+}
+
+/// Saves the checkpoint (if any)
+fn save_checkpoint(checkpoint: &ReplayCheckpoint, options: &ReplayOptions) -> Result<(), anyhow::Error> {
+    match &options.checkpoint {
+        Some(path) => checkpoint.save(path),
+        None => Ok(()),
+    }
+}
+
+/// Waits until the number of produced records matches the rate
+async fn throttle(started: Instant, produced: u64, rate: Option<u64>) {
+    if let Some(rate) = rate.filter(|rate| *rate > 0) {
+        let target = Duration::from_secs_f64(produced as f64 / rate as f64);
+        let elapsed = started.elapsed();
+        if target > elapsed {
+            tokio::time::sleep(target - elapsed).await;
+        }
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENVELOPE: &str = "{\"topic\":\"rates\",\"partition\":0,\"offset\":7,\"key\":\"k1\",\"timestamp\":null,\"headers\":[[\"source\",\"api\"]],\"payload\":{\"base\":\"EUR\"}}";
+
+    #[test]
+    fn test_parse_ndjson_envelopes() {
+        let content = format!("{}\n{}\n", ENVELOPE, ENVELOPE.replace("k1", "k2"));
+        let records = parse_export(content.as_bytes());
+
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].key.as_deref(), Some("k2"));
+        assert_eq!(records[0].headers, vec![("source".to_string(), Some("api".to_string()))]);
+        assert_eq!(records[0].payload, b"{\"base\":\"EUR\"}");
+    }
+
+    #[test]
+    fn test_parse_json_array_and_raw() {
+        let records = parse_export(format!("[{}, {{\"id\": 1}}]", ENVELOPE).as_bytes());
+        assert_eq!(records[0].key.as_deref(), Some("k1"));
+        assert_eq!(records[1].key, None);
+        assert_eq!(records[1].payload, b"{\"id\":1}");
+
+        let records = parse_export(b"first line\nsecond line\n");
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].payload, b"first line");
+    }
+
+    #[test]
+    fn test_checkpoint_roundtrip() {
+        let path = std::env::temp_dir().join(format!("exchange-checkpoint-{}.json", uuid::Uuid::new_v4()));
+        assert_eq!(ReplayCheckpoint::load(&path).unwrap(), ReplayCheckpoint::default());
+
+        let mut checkpoint = ReplayCheckpoint::default();
+        checkpoint.claim("test", Some("rates/")).unwrap();
+        checkpoint.completed.insert("rates/0-0.ndjson".to_string());
+        checkpoint.current = Some("rates/0-500.ndjson".to_string());
+        checkpoint.records = 250;
+        checkpoint.save(&path).unwrap();
+
+        let loaded = ReplayCheckpoint::load(&path).unwrap();
+        assert_eq!(loaded, checkpoint);
+        assert_eq!(loaded.produced("rates/0-500.ndjson"), 250);
+        assert_eq!(loaded.produced("rates/0-1000.ndjson"), 0);
+
+        // The checkpoint can not be used for another container or prefix
+        let mut loaded = ReplayCheckpoint::load(&path).unwrap();
+        assert!(loaded.claim("test", Some("rates/")).is_ok());
+        assert!(loaded.claim("other", Some("rates/")).is_err());
+        assert!(loaded.claim("test", None).is_err());
+
+        let _ = std::fs::remove_file(path);
+    }
+}
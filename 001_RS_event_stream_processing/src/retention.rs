@@ -0,0 +1,345 @@
+/*
+    This file contains the retention of exported blobs (exchange blobs prune)
+    Blobs are deleted by age and/or count, according to the rules given on the command line or in retention_config.json
+*/
+
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use futures::{stream, StreamExt};
+use log::{info, error};
+use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
+
+use crate::config::RetentionRuleConfig;
+use crate::export::metadata::INGESTED_AT_METADATA;
+use crate::get_retention_details;
+use crate::storage::{BlobInfo, BlobStore};
+
+/// Retention rule for the blobs below a prefix
+/// - prefix: Blobs the rule applies to (all blobs if empty)
+/// - older_than: Blobs older than this age are deleted
+/// - keep_last: Number of newest blobs that are always kept (even if they are older)
+/// - Without older_than, every blob but the newest keep_last is deleted
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RetentionRule {
+    pub prefix: String,
+    pub older_than: Option<Duration>,
+    pub keep_last: Option<usize>,
+}
+
+impl RetentionRule {
+
+    /// Converts a rule of retention_config.json
+    /// - Fails if the age is malformed or the rule has neither an age nor a count
+    pub fn from_config(config: &RetentionRuleConfig) -> Result<Self, anyhow::Error> {
+        let older_than = config.older_than.as_deref()
+            .map(parse_age)
+            .transpose()
+            .map_err(|e| anyhow!("Invalid retention rule for prefix {:?}: {}", config.prefix, e))?;
+
+        RetentionRule::new(&config.prefix, older_than, config.keep_last)
+    }
+
+    /// Creates a rule, it needs an age, a count or both
+    pub fn new(prefix: &str, older_than: Option<Duration>, keep_last: Option<usize>) -> Result<Self, anyhow::Error> {
+        if older_than.is_none() && keep_last.is_none() {
+            return Err(anyhow!("Retention rule for prefix {:?} needs older_than, keep_last or both", prefix));
+        }
+
+        Ok(RetentionRule { prefix: prefix.to_string(), older_than, keep_last })
+    }
+}
+
+/// Loads the rules of a container from retention_config.json
+/// - Rules without a container_name apply to every container
+pub fn configured_rules(container_name: &str) -> Result<Vec<RetentionRule>, anyhow::Error> {
+    get_retention_details()?.rules.iter()
+        .filter(|rule| match rule.container_name.as_deref() {
+            Some(name) => name == container_name,
+            None => true,
+        })
+        .map(RetentionRule::from_config)
+        .collect()
+}
+
+/// Parses an age (e.g. 30d): a number followed by s, m, h, d or w
+pub fn parse_age(age: &str) -> Result<Duration, String> {
+    let age = age.trim();
+    let (value, unit) = age.split_at(age.find(|character: char| !character.is_ascii_digit()).unwrap_or(age.len()));
+
+    let value: u64 = value.parse()
+        .map_err(|_| format!("Invalid age {}: Expected a number followed by s, m, h, d or w (e.g. 30d)", age))?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return Err(format!("Invalid age {}: Expected a number followed by s, m, h, d or w (e.g. 30d)", age)),
+    };
+
+    let seconds = value.checked_mul(seconds)
+        .ok_or_else(|| format!("Invalid age {}: Too large", age))?;
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Options of a prune run
+/// - dry_run: Only report the blobs that would be deleted
+/// - parallelism: Number of blobs deleted at the same time
+#[derive(Debug, Clone)]
+pub struct PruneOptions {
+    pub dry_run: bool,
+    pub parallelism: usize,
+}
+
+impl Default for PruneOptions {
+    fn default() -> Self {
+        PruneOptions {
+            dry_run: false,
+            parallelism: 8,
+        }
+    }
+}
+
+/// Result of a prune run
+/// - deleted: Names of the deleted blobs (the blobs that would be deleted in a dry run)
+/// - bytes: Size of the deleted blobs
+/// - kept: Number of blobs kept
+/// - failures: Name and error of every blob that could not be deleted
+#[derive(Debug, Clone, Default)]
+pub struct PruneReport {
+    pub deleted: Vec<String>,
+    pub bytes: u64,
+    pub kept: u64,
+    pub failures: Vec<(String, String)>,
+}
+
+/// Selects the blobs that are deleted by the rules
+/// - Every blob is handled by the rule with the longest matching prefix, blobs without a rule are kept
+/// - The newest keep_last blobs of a rule are kept, the rest is deleted if it is older than older_than (if set)
+/// - The age is measured from the write of the blob (see written_at)
+/// - Returns the blobs to delete, oldest first
+pub fn expired_blobs(blobs: Vec<BlobInfo>, rules: &[RetentionRule], now: OffsetDateTime) -> Vec<BlobInfo> {
+
+    // Group the blobs by the index of their rule
+    let mut groups: BTreeMap<usize, Vec<BlobInfo>> = BTreeMap::new();
+    for blob in blobs {
+        let rule = rules.iter()
+            .enumerate()
+            .filter(|(_, rule)| blob.name.starts_with(&rule.prefix))
+            .max_by_key(|(_, rule)| rule.prefix.len())
+            .map(|(index, _)| index);
+        if let Some(rule) = rule {
+            groups.entry(rule).or_default().push(blob);
+        }
+    }
+
+    let mut expired = Vec::new();
+    for (rule, mut group) in groups {
+        let rule = &rules[rule];
+
+        // Newest first, the name keeps the order stable for equal times
+        group.sort_by(|a, b| written_at(b).cmp(&written_at(a)).then_with(|| a.name.cmp(&b.name)));
+        expired.extend(group.into_iter()
+            .skip(rule.keep_last.unwrap_or(0))
+            .filter(|blob| match rule.older_than {
+                Some(age) => now - written_at(blob) > age,
+                None => true,
+            }));
+    }
+
+    expired.sort_by(|a, b| written_at(a).cmp(&written_at(b)).then_with(|| a.name.cmp(&b.name)));
+    expired
+}
+
+/// Returns the time the blob was written
+/// - Taken from the ingested_at metadata, since updating the metadata (e.g. a key rotation) changes last_modified
+/// - Falls back to last_modified for blobs without (valid) ingested_at
+fn written_at(blob: &BlobInfo) -> OffsetDateTime {
+    blob.properties.metadata.get(INGESTED_AT_METADATA)
+        .and_then(|ingested_at| OffsetDateTime::parse(ingested_at, &Rfc3339).ok())
+        .unwrap_or(blob.last_modified)
+}
+
+/// Deletes the blobs of the container (matching the prefix) that are expired by the rules (see expired_blobs)
+/// - Deletes up to parallelism blobs at the same time
+/// - A failed blob is reported and skipped, the prune continues with the next one
+/// - In a dry run, nothing is deleted
+pub async fn prune_blobs(store: &dyn BlobStore, container_name: &str, prefix: Option<&str>, rules: &[RetentionRule], options: &PruneOptions) -> Result<PruneReport, anyhow::Error> {
+
+    let blobs = store.list(container_name, prefix).await?;
+    let total = blobs.len() as u64;
+    let expired = expired_blobs(blobs, rules, OffsetDateTime::now_utc());
+    info!("Blob(s) expired in {}: {} of {}", container_name, expired.len(), total);
+
+    let mut report = PruneReport {
+        kept: total - expired.len() as u64,
+        ..Default::default()
+    };
+
+    if options.dry_run {
+        report.bytes = expired.iter().map(|blob| blob.size).sum();
+        report.deleted = expired.into_iter().map(|blob| blob.name).collect();
+        return Ok(report);
+    }
+
+    let mut deletes = stream::iter(expired)
+        .map(|blob| async move {
+            let result = store.delete(container_name, &blob.name).await;
+            (blob, result)
+        })
+        .buffer_unordered(options.parallelism.max(1));
+
+    while let Some((blob, result)) = deletes.next().await {
+        match result {
+            Ok(()) => {
+                report.bytes += blob.size;
+                report.deleted.push(blob.name);
+            },
+            Err(e) => {
+                error!("Error while deleting blob {}: {}", &blob.name, e);
+                report.kept += 1;
+                report.failures.push((blob.name, e.to_string()));
+            }
+        }
+    }
+    report.deleted.sort();
+
+    Ok(report)
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use base64::Engine;
+    use base64::engine::general_purpose::STANDARD;
+
+    use super::*;
+    use crate::azure::encryption::KeyRing;
+    use crate::azure::writer::WriteOptions;
+    use crate::blobs::rotate_keys;
+    use crate::config::EncryptionConfig;
+    use crate::storage::local::LocalBlobStore;
+    use crate::storage::{write_blob, BlobProperties};
+
+    fn blob(name: &str, age_days: i64, now: OffsetDateTime) -> BlobInfo {
+        BlobInfo {
+            name: name.to_string(),
+            size: 10,
+            last_modified: now - time::Duration::days(age_days),
+            etag: None,
+            properties: BlobProperties::default(),
+        }
+    }
+
+    fn names(blobs: &[BlobInfo]) -> Vec<&str> {
+        blobs.iter().map(|blob| blob.name.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_age() {
+        assert_eq!(parse_age("30d"), Ok(Duration::from_secs(30 * 24 * 60 * 60)));
+        assert_eq!(parse_age("12h"), Ok(Duration::from_secs(12 * 60 * 60)));
+        assert_eq!(parse_age("2w"), Ok(Duration::from_secs(14 * 24 * 60 * 60)));
+        assert!(parse_age("30").is_err());
+        assert!(parse_age("d").is_err());
+        assert!(parse_age("1y").is_err());
+        assert!(parse_age("999999999999999d").is_err());
+    }
+
+    #[test]
+    fn test_expired_blobs() {
+        let now = OffsetDateTime::now_utc();
+        let blobs = vec![
+            blob("rates/a.ndjson", 40, now),
+            blob("rates/b.ndjson", 35, now),
+            blob("rates/c.ndjson", 10, now),
+            blob("rates/raw/d.txt", 50, now),
+            blob("other.json", 100, now),
+        ];
+        let rules = vec![
+            RetentionRule::new("rates/", Some(parse_age("30d").unwrap()), Some(1)).unwrap(),
+            RetentionRule::new("rates/raw/", None, Some(0)).unwrap(),
+        ];
+
+        // other.json has no rule, rates/raw/ is handled by its own (longer) rule
+        assert_eq!(names(&expired_blobs(blobs.clone(), &rules, now)), vec!["rates/raw/d.txt", "rates/a.ndjson", "rates/b.ndjson"]);
+
+        // keep_last keeps the newest blobs even if they are older
+        let rules = vec![RetentionRule::new("rates/", Some(parse_age("30d").unwrap()), Some(2)).unwrap()];
+        assert_eq!(names(&expired_blobs(blobs, &rules, now)), vec!["rates/raw/d.txt", "rates/a.ndjson"]);
+
+        assert!(RetentionRule::new("rates/", None, None).is_err());
+    }
+
+    #[test]
+    fn test_expired_blobs_by_ingested_at() {
+        let now = OffsetDateTime::now_utc();
+        let mut rotated = blob("rates/a.ndjson", 0, now);
+        rotated.properties.metadata.insert(INGESTED_AT_METADATA.to_string(), (now - time::Duration::days(40)).format(&Rfc3339).unwrap());
+        let mut invalid = blob("rates/b.ndjson", 35, now);
+        invalid.properties.metadata.insert(INGESTED_AT_METADATA.to_string(), "yesterday".to_string());
+        let rules = vec![RetentionRule::new("rates/", Some(parse_age("30d").unwrap()), None).unwrap()];
+
+        assert_eq!(names(&expired_blobs(vec![rotated, invalid], &rules, now)), vec!["rates/a.ndjson", "rates/b.ndjson"]);
+    }
+
+    #[tokio::test]
+    async fn test_prune_rotated_blob() {
+        let key_ring = |active_key: &str| KeyRing::from_config(&EncryptionConfig {
+            active_key: active_key.to_string(),
+            keys: BTreeMap::from([
+                ("kek-1".to_string(), STANDARD.encode([1u8; 32])),
+                ("kek-2".to_string(), STANDARD.encode([2u8; 32])),
+            ]),
+        }).unwrap();
+
+        let root = std::env::temp_dir().join(format!("exchange-test-{}", uuid::Uuid::new_v4()));
+        let store = LocalBlobStore::new(&root);
+        let ingested_at = (OffsetDateTime::now_utc() - time::Duration::days(40)).format(&Rfc3339).unwrap();
+        let options = WriteOptions {
+            metadata: BTreeMap::from([(INGESTED_AT_METADATA.to_string(), ingested_at)]),
+            encryption: Some(key_ring("kek-1")),
+            ..Default::default()
+        };
+        let blob_name = write_blob(&store, "test", "rates/0-1.ndjson", b"{}\n", &options).await.unwrap();
+
+        // The rotation rewrites the metadata, the blob keeps the age of its write
+        let rotation = rotate_keys(&store, "test", None, &key_ring("kek-2")).await.unwrap();
+        assert_eq!(rotation.rotated, vec![blob_name.clone()]);
+
+        let rules = vec![RetentionRule::new("rates/", Some(parse_age("30d").unwrap()), None).unwrap()];
+        let report = prune_blobs(&store, "test", None, &rules, &PruneOptions::default()).await.unwrap();
+        assert_eq!(report.deleted, vec![blob_name]);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_prune_blobs() {
+        let root = std::env::temp_dir().join(format!("exchange-test-{}", uuid::Uuid::new_v4()));
+        let store = LocalBlobStore::new(&root);
+        for name in ["rates/0-1.ndjson", "rates/0-2.ndjson", "rates/0-3.ndjson", "other.json"] {
+            write_blob(&store, "test", name, b"{}\n", &WriteOptions::default()).await.unwrap();
+        }
+        let rules = vec![RetentionRule::new("", None, Some(1)).unwrap()];
+
+        let dry_run = PruneOptions { dry_run: true, ..Default::default() };
+        let report = prune_blobs(&store, "test", Some("rates/"), &rules, &dry_run).await.unwrap();
+        assert_eq!(report.deleted.len(), 2);
+        assert_eq!(report.kept, 1);
+        assert_eq!(store.list("test", None).await.unwrap().len(), 4);
+
+        let report = prune_blobs(&store, "test", Some("rates/"), &rules, &PruneOptions::default()).await.unwrap();
+        assert_eq!(report.deleted.len(), 2);
+        assert_eq!(report.bytes, 6);
+        assert!(report.failures.is_empty());
+        assert_eq!(store.list("test", None).await.unwrap().len(), 2);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+}
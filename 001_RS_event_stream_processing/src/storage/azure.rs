@@ -0,0 +1,237 @@
+/*
+    This file contains the Azure Blob Storage implementation of the BlobStore
+    It wraps the functions of the azure module (connection via azure_config.json)
+*/
+
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use azure_core::prelude::IfMatchCondition;
+use azure_core::request_options::Metadata;
+use azure_storage_blobs::blob::{Blob, BlobType};
+use azure_storage_blobs::prelude::BlobClient;
+use futures::StreamExt;
+use log::{info, warn};
+
+use crate::azure::integrity::{stored_md5, MD5_METADATA};
+use crate::azure::helper::{append_azure_blob, committed_blocks_context, create_azure_container, delete_azure_blob, get_az_client, is_append_position, is_block_limit, is_changed, is_conflict, is_invalid_blob_type, is_not_found, CommittedBlocks, UploadProperties};
+use crate::azure::upload::{upload_blob, upload_file, BlockOptions};
+use crate::errors::{AppendError, ConflictError};
+use super::{AppendState, BlobInfo, BlobProperties, BlobStore, StoredBlob, WriteCondition};
+
+// Number of reads of a blob that is overwritten while it is read (see AzureBlobStore::get)
+const MAX_READ_ATTEMPTS: u32 = 3;
+
+/// Reads the content of the blob in chunks, fails with PreconditionFailed if the blob no longer has the etag
+async fn read_content(blob_client: &BlobClient, etag: &str) -> azure_core::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    let mut chunks = blob_client.get().if_match(IfMatchCondition::Match(etag.to_string())).into_stream();
+    while let Some(chunk) = chunks.next().await {
+        content.extend(&chunk?.data.collect().await?);
+    }
+    Ok(content)
+}
+
+/// Converts the properties and metadata of an Azure blob
+/// - The Content-MD5 of blobs written by other tools is used as the MD5 metadata entry
+fn blob_properties(blob: &Blob) -> BlobProperties {
+    let mut metadata: BTreeMap<String, String> = blob.metadata.clone().unwrap_or_default().into_iter().collect();
+    if let Some(md5) = stored_md5(blob) {
+        metadata.insert(MD5_METADATA.to_string(), md5);
+    }
+
+    BlobProperties {
+        content_type: blob.properties.content_type.clone(),
+        content_encoding: blob.properties.content_encoding.clone(),
+        metadata,
+        tags: blob.tags.clone().map(|tags| tags.into_iter().collect()).unwrap_or_default(),
+    }
+}
+
+/// Converts an Azure blob (as listed or from get_properties)
+fn blob_info(blob: &Blob) -> BlobInfo {
+    BlobInfo {
+        name: blob.name.clone(),
+        size: blob.properties.content_length,
+        last_modified: blob.properties.last_modified,
+        etag: Some(blob.properties.etag.to_string()),
+        properties: blob_properties(blob),
+    }
+}
+
+/// Converts the blob properties into the properties of an upload
+fn upload_properties(properties: &BlobProperties, condition: WriteCondition) -> UploadProperties {
+    UploadProperties {
+        content_type: properties.content_type.clone(),
+        content_encoding: properties.content_encoding.clone(),
+        metadata: properties.metadata.clone(),
+        tags: properties.tags.clone(),
+        if_not_exists: condition == WriteCondition::IfNotExists,
+    }
+}
+
+/// Converts the error of an upload, a rejected conditional write becomes ConflictError::BlobExists
+/// - Only a write with WriteCondition::IfNotExists can be rejected because the blob exists,
+///   a 409/412 of an unconditional write (e.g. a lease conflict) is passed through unchanged
+fn upload_error(e: azure_core::Error, blob_name: &str, condition: WriteCondition) -> anyhow::Error {
+    if condition == WriteCondition::IfNotExists && is_conflict(&e) {
+        ConflictError::BlobExists(blob_name.to_string()).into()
+    } else {
+        e.into()
+    }
+}
+
+/// Sink writing to Azure Blob Storage
+/// - blocks: Blobs larger than the block size are uploaded as staged blocks
+#[derive(Debug, Clone, Default)]
+pub struct AzureBlobStore {
+    blocks: BlockOptions,
+}
+
+impl AzureBlobStore {
+
+    pub fn new() -> Self {
+        AzureBlobStore::default()
+    }
+
+    /// Uses the given block size and parallelism for large uploads
+    pub fn with_blocks(blocks: BlockOptions) -> Self {
+        AzureBlobStore { blocks }
+    }
+}
+
+#[async_trait]
+impl BlobStore for AzureBlobStore {
+
+    async fn put(&self, container_name: &str, blob_name: &str, content: Vec<u8>, properties: &BlobProperties, condition: WriteCondition) -> Result<(), anyhow::Error> {
+        upload_blob(container_name, blob_name, content, &upload_properties(properties, condition), &self.blocks).await
+            .map_err(|e| upload_error(e, blob_name, condition))
+    }
+
+    async fn put_file(&self, container_name: &str, blob_name: &str, path: &Path, properties: &BlobProperties, condition: WriteCondition) -> Result<(), anyhow::Error> {
+        upload_file(container_name, blob_name, path, &upload_properties(properties, condition), &self.blocks).await
+            .map_err(|e| upload_error(e, blob_name, condition))
+    }
+
+    async fn append_state(&self, container_name: &str, blob_name: &str) -> Result<Option<AppendState>, anyhow::Error> {
+        let blocks = Arc::new(CommittedBlocks::default());
+        let blob = match get_az_client()?.blob_client(container_name, blob_name).get_properties().context(committed_blocks_context(&blocks)).await {
+            Ok(response) => response.blob,
+            Err(e) if is_not_found(&e) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        if blob.properties.blob_type != BlobType::AppendBlob {
+            return Err(AppendError::NotAppendBlob(blob_name.to_string()).into());
+        }
+        Ok(Some(AppendState {
+            size: blob.properties.content_length,
+            blocks: blocks.get().unwrap_or_default(),
+        }))
+    }
+
+    async fn append(&self, container_name: &str, blob_name: &str, block: Vec<u8>, properties: &BlobProperties, position: u64) -> Result<(), anyhow::Error> {
+        append_azure_blob(container_name, blob_name, block, &upload_properties(properties, WriteCondition::Always), position).await
+            .map_err(|e| if is_block_limit(&e) {
+                AppendError::BlockLimit(blob_name.to_string()).into()
+            } else if is_append_position(&e) {
+                AppendError::PositionMismatch(blob_name.to_string()).into()
+            } else if is_invalid_blob_type(&e) {
+                AppendError::NotAppendBlob(blob_name.to_string()).into()
+            } else {
+                e.into()
+            })
+    }
+
+    async fn get(&self, container_name: &str, blob_name: &str) -> Result<StoredBlob, anyhow::Error> {
+        let blob_client = get_az_client()?.blob_client(container_name, blob_name);
+
+        // The content is read in chunks, every chunk is pinned to the etag of the properties (MD5, encryption),
+        // a blob that is overwritten in between is read again
+        let mut attempt = 1;
+        loop {
+            let blob = blob_client.get_properties().await?.blob;
+            match read_content(&blob_client, blob.properties.etag.as_ref()).await {
+                Ok(content) => {
+                    info!("Successfully retrieved blob: {:?}", blob_name);
+                    return Ok(StoredBlob {
+                        content,
+                        properties: blob_properties(&blob),
+                    });
+                },
+                Err(e) if is_changed(&e) && attempt < MAX_READ_ATTEMPTS => {
+                    warn!("Blob {} changed while it was read, reading it again", blob_name);
+                    attempt += 1;
+                },
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    async fn info(&self, container_name: &str, blob_name: &str) -> Result<BlobInfo, anyhow::Error> {
+        let blob_client = get_az_client()?.blob_client(container_name, blob_name);
+        let mut info = blob_info(&blob_client.get_properties().await?.blob);
+
+        // Reading the tags needs an extra permission (e.g. 't' in a SAS token)
+        match blob_client.get_tags().await {
+            Ok(response) => info.properties.tags = response.tags.into_iter().collect(),
+            Err(e) => warn!("Error reading the tags of blob {}: {}", blob_name, e),
+        }
+
+        Ok(info)
+    }
+
+    async fn list(&self, container_name: &str, prefix: Option<&str>) -> Result<Vec<BlobInfo>, anyhow::Error> {
+        let container_client = get_az_client()?.container_client(container_name);
+
+        let mut builder = container_client.list_blobs().include_metadata(true);
+        if let Some(prefix) = prefix {
+            builder = builder.prefix(prefix.to_string());
+        }
+
+        // The blobs are returned in pages
+        let mut blobs = Vec::new();
+        let mut pages = builder.into_stream();
+        while let Some(page) = pages.next().await {
+            blobs.extend(page?.blobs.blobs().map(blob_info));
+        }
+
+        Ok(blobs)
+    }
+
+    async fn set_metadata(&self, container_name: &str, blob_name: &str, metadata: &BTreeMap<String, String>, etag: Option<&str>) -> Result<(), anyhow::Error> {
+        let mut blob_metadata = Metadata::new();
+        for (name, value) in metadata {
+            blob_metadata.insert(name.clone(), value.clone());
+        }
+
+        let mut builder = get_az_client()?.blob_client(container_name, blob_name).set_metadata().metadata(blob_metadata);
+        if let Some(etag) = etag {
+            builder = builder.if_match(IfMatchCondition::Match(etag.to_string()));
+        }
+        builder.await?;
+
+        info!("Successfully updated metadata of blob: {:?}", blob_name);
+        Ok(())
+    }
+
+    async fn delete(&self, container_name: &str, blob_name: &str) -> Result<(), anyhow::Error> {
+        delete_azure_blob(container_name, blob_name).await?;
+        Ok(())
+    }
+
+    async fn exists(&self, container_name: &str, blob_name: &str) -> Result<bool, anyhow::Error> {
+        Ok(get_az_client()?.blob_client(container_name, blob_name).exists().await?)
+    }
+
+    async fn create_container(&self, container_name: &str) -> Result<(), anyhow::Error> {
+        let container_exists = get_az_client()?.container_client(container_name).exists().await?;
+        if !container_exists {
+            warn!("Container {} does not exist. Creating...", container_name);
+            create_azure_container(container_name).await?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,470 @@
+/*
+    This file contains the local directory implementation of the BlobStore
+    Every container is a subdirectory of the root, the blob properties are kept in <container>/.properties
+*/
+
+use std::collections::BTreeMap;
+use std::path::{Component, Path, PathBuf};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::info;
+use time::OffsetDateTime;
+use tokio::io::AsyncWriteExt;
+
+use crate::errors::{AppendError, ConflictError};
+use super::{AppendState, BlobInfo, BlobProperties, BlobStore, StoredBlob, WriteCondition};
+
+// Directory (inside of a container) holding the properties of the blobs
+const PROPERTIES_DIR: &str = ".properties";
+
+/// Sink writing to a local directory (e.g. for offline runs and CI)
+#[derive(Debug, Clone)]
+pub struct LocalBlobStore {
+    root: PathBuf,
+}
+
+impl LocalBlobStore {
+
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        LocalBlobStore { root: root.as_ref().to_path_buf() }
+    }
+
+    /// Returns the directory of the container
+    fn container_path(&self, container_name: &str) -> Result<PathBuf, anyhow::Error> {
+        Ok(self.root.join(checked_path(container_name)?))
+    }
+
+    /// Returns the path of the blob content
+    fn blob_path(&self, container_name: &str, blob_name: &str) -> Result<PathBuf, anyhow::Error> {
+        Ok(self.container_path(container_name)?.join(checked_path(blob_name)?))
+    }
+
+    /// Returns the path of the blob properties
+    fn properties_path(&self, container_name: &str, blob_name: &str) -> Result<PathBuf, anyhow::Error> {
+        let path = self.container_path(container_name)?.join(PROPERTIES_DIR).join(checked_path(blob_name)?);
+        Ok(path.with_file_name(format!("{}.json", path.file_name().unwrap_or_default().to_string_lossy())))
+    }
+
+    /// Reads the properties of the blob, defaults are used for files that were not written by the store
+    async fn read_properties(&self, container_name: &str, blob_name: &str) -> Result<BlobProperties, anyhow::Error> {
+        match tokio::fs::read(self.properties_path(container_name, blob_name)?).await {
+            Ok(properties) => Ok(serde_json::from_slice(&properties)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BlobProperties::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+/// Checks that a container or blob name stays inside of the root
+/// - Rejects empty names, absolute paths and '..'
+pub(crate) fn checked_path(name: &str) -> Result<&Path, anyhow::Error> {
+    let path = Path::new(name);
+    let valid = !name.is_empty() && path.components().all(|component| matches!(component, Component::Normal(_)));
+
+    if valid {
+        Ok(path)
+    } else {
+        Err(anyhow!("Invalid name for the local sink: {}", name))
+    }
+}
+
+/// Writes the file, creating the parent directories
+/// - The content is written to a temporary file first, so that readers never see a partial blob
+async fn write_file(path: &Path, content: &[u8]) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let temporary = temporary_path(path);
+    tokio::fs::write(&temporary, content).await?;
+    tokio::fs::rename(&temporary, path).await?;
+    Ok(())
+}
+
+/// Writes the file only if it does not exist yet, creating the parent directories
+/// - The temporary file is linked to the path, which fails atomically if the path exists
+/// - Returns ConflictError::BlobExists if the file exists
+async fn create_file(path: &Path, blob_name: &str, content: &[u8]) -> Result<(), anyhow::Error> {
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    let temporary = temporary_path(path);
+    tokio::fs::write(&temporary, content).await?;
+    let linked = tokio::fs::hard_link(&temporary, path).await;
+    tokio::fs::remove_file(&temporary).await?;
+
+    match linked {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(ConflictError::BlobExists(blob_name.to_string()).into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Returns the path of the temporary file used while writing (skipped by list)
+/// - The name is unique per write, so that concurrent writers of the same blob never share a temporary file
+fn temporary_path(path: &Path) -> PathBuf {
+    path.with_file_name(format!(".{}.{}.tmp", path.file_name().unwrap_or_default().to_string_lossy(), uuid::Uuid::new_v4().simple()))
+}
+
+#[async_trait]
+impl BlobStore for LocalBlobStore {
+
+    async fn put(&self, container_name: &str, blob_name: &str, content: Vec<u8>, properties: &BlobProperties, condition: WriteCondition) -> Result<(), anyhow::Error> {
+        let path = self.blob_path(container_name, blob_name)?;
+        let properties_path = self.properties_path(container_name, blob_name)?;
+
+        match condition {
+            WriteCondition::Always => {
+                write_file(&properties_path, &serde_json::to_vec(properties)?).await?;
+                write_file(&path, &content).await?;
+            },
+            // The content claims the name, the properties are only written if the blob is new
+            WriteCondition::IfNotExists => {
+                create_file(&path, blob_name, &content).await?;
+                write_file(&properties_path, &serde_json::to_vec(properties)?).await?;
+            },
+        }
+
+        info!("Successfully created blob {} in {}", blob_name, path.display());
+        Ok(())
+    }
+
+    /// Local files have no block limit, the block count is always 0
+    async fn append_state(&self, container_name: &str, blob_name: &str) -> Result<Option<AppendState>, anyhow::Error> {
+        let path = self.blob_path(container_name, blob_name)?;
+        match tokio::fs::metadata(&path).await {
+            Ok(metadata) => Ok(Some(AppendState { size: metadata.len(), blocks: 0 })),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Local files have no block limit, every append goes to the same file
+    async fn append(&self, container_name: &str, blob_name: &str, block: Vec<u8>, properties: &BlobProperties, position: u64) -> Result<(), anyhow::Error> {
+        let path = self.blob_path(container_name, blob_name)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        // The properties are only written when the blob is created
+        if !tokio::fs::try_exists(&path).await? {
+            write_file(&self.properties_path(container_name, blob_name)?, &serde_json::to_vec(properties)?).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&path).await?;
+        if file.metadata().await?.len() != position {
+            return Err(AppendError::PositionMismatch(blob_name.to_string()).into());
+        }
+        file.write_all(&block).await?;
+        file.flush().await?;
+
+        info!("Successfully appended {} bytes to blob {}", block.len(), path.display());
+        Ok(())
+    }
+
+    async fn get(&self, container_name: &str, blob_name: &str) -> Result<StoredBlob, anyhow::Error> {
+        let path = self.blob_path(container_name, blob_name)?;
+
+        let content = tokio::fs::read(&path).await
+            .map_err(|e| anyhow!("Error reading blob {}: {}", path.display(), e))?;
+        info!("Successfully retrieved blob: {:?}", blob_name);
+
+        Ok(StoredBlob {
+            content,
+            properties: self.read_properties(container_name, blob_name).await?,
+        })
+    }
+
+    async fn info(&self, container_name: &str, blob_name: &str) -> Result<BlobInfo, anyhow::Error> {
+        let path = self.blob_path(container_name, blob_name)?;
+
+        let metadata = tokio::fs::metadata(&path).await
+            .map_err(|e| anyhow!("Error reading blob {}: {}", path.display(), e))?;
+
+        Ok(BlobInfo {
+            name: blob_name.to_string(),
+            size: metadata.len(),
+            last_modified: metadata.modified().map(OffsetDateTime::from).unwrap_or_else(|_| OffsetDateTime::now_utc()),
+            etag: None,
+            properties: self.read_properties(container_name, blob_name).await?,
+        })
+    }
+
+    async fn list(&self, container_name: &str, prefix: Option<&str>) -> Result<Vec<BlobInfo>, anyhow::Error> {
+        let container = self.container_path(container_name)?;
+        if !tokio::fs::try_exists(&container).await? {
+            return Err(anyhow!("Container {} does not exist in {}", container_name, self.root.display()));
+        }
+
+        // Walk the container, blob names use '/' on every platform
+        let mut blobs = Vec::new();
+        let mut directories = vec![container.clone()];
+        while let Some(directory) = directories.pop() {
+            let mut entries = tokio::fs::read_dir(&directory).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let file_name = entry.file_name().to_string_lossy().to_string();
+                // Skip the properties and temporary files
+                if file_name == PROPERTIES_DIR || (file_name.starts_with('.') && file_name.ends_with(".tmp")) {
+                    continue;
+                }
+
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    directories.push(entry.path());
+                    continue;
+                }
+
+                let name = entry.path().strip_prefix(&container)?
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy().to_string())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if prefix.is_some_and(|prefix| !name.starts_with(prefix)) {
+                    continue;
+                }
+
+                blobs.push(BlobInfo {
+                    properties: self.read_properties(container_name, &name).await?,
+                    name,
+                    size: metadata.len(),
+                    last_modified: metadata.modified().map(OffsetDateTime::from).unwrap_or_else(|_| OffsetDateTime::now_utc()),
+                    etag: None,
+                });
+            }
+        }
+
+        blobs.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(blobs)
+    }
+
+    async fn set_metadata(&self, container_name: &str, blob_name: &str, metadata: &BTreeMap<String, String>, _etag: Option<&str>) -> Result<(), anyhow::Error> {
+        let path = self.blob_path(container_name, blob_name)?;
+        if !tokio::fs::try_exists(&path).await? {
+            return Err(anyhow!("Blob {} does not exist in {}", blob_name, path.display()));
+        }
+
+        let mut properties = self.read_properties(container_name, blob_name).await?;
+        properties.metadata = metadata.clone();
+        write_file(&self.properties_path(container_name, blob_name)?, &serde_json::to_vec(&properties)?).await?;
+
+        info!("Successfully updated metadata of blob: {:?}", blob_name);
+        Ok(())
+    }
+
+    async fn delete(&self, container_name: &str, blob_name: &str) -> Result<(), anyhow::Error> {
+        tokio::fs::remove_file(self.blob_path(container_name, blob_name)?).await
+            .map_err(|e| anyhow!("Error deleting blob {}: {}", blob_name, e))?;
+
+        // Blobs written by other tools have no properties
+        match tokio::fs::remove_file(self.properties_path(container_name, blob_name)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {},
+        }
+
+        info!("Successfully deleted blob: {:?}", blob_name);
+        Ok(())
+    }
+
+    async fn exists(&self, container_name: &str, blob_name: &str) -> Result<bool, anyhow::Error> {
+        Ok(tokio::fs::try_exists(self.blob_path(container_name, blob_name)?).await?)
+    }
+
+    async fn create_container(&self, container_name: &str) -> Result<(), anyhow::Error> {
+        tokio::fs::create_dir_all(self.container_path(container_name)?).await?;
+        Ok(())
+    }
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::azure::compression::Compression;
+    use crate::azure::writer::{ConflictPolicy, WriteOptions};
+    use crate::errors::IntegrityError;
+    use crate::azure::encryption::DecryptionKeys;
+    use crate::storage::{append_blob, is_blob_exists, read_blob, write_blob, write_file_blob, AppendProgress, APPEND_BLOCK_SIZE};
+
+    fn temporary_store() -> (LocalBlobStore, PathBuf) {
+        let root = std::env::temp_dir().join(format!("exchange-test-{}", uuid::Uuid::new_v4()));
+        (LocalBlobStore::new(&root), root)
+    }
+
+    #[tokio::test]
+    async fn test_put_get_list_delete() {
+        let (store, root) = temporary_store();
+        let mut properties = BlobProperties { content_type: "application/x-ndjson".to_string(), ..Default::default() };
+        properties.metadata.insert("source".to_string(), "test".to_string());
+
+        store.create_container("test").await.unwrap();
+        store.put("test", "rates/0-42.ndjson", b"{}\n".to_vec(), &properties, WriteCondition::Always).await.unwrap();
+        store.put("test", "other.json", b"{}".to_vec(), &BlobProperties::default(), WriteCondition::Always).await.unwrap();
+
+        assert!(store.exists("test", "rates/0-42.ndjson").await.unwrap());
+        let blob = store.get("test", "rates/0-42.ndjson").await.unwrap();
+        assert_eq!(blob.content, b"{}\n");
+        assert_eq!(blob.properties, properties);
+        assert_eq!(store.info("test", "rates/0-42.ndjson").await.unwrap().size, 3);
+
+        let names: Vec<String> = store.list("test", None).await.unwrap().into_iter().map(|blob| blob.name).collect();
+        assert_eq!(names, vec!["other.json", "rates/0-42.ndjson"]);
+        assert_eq!(store.list("test", Some("rates/")).await.unwrap().len(), 1);
+
+        store.delete("test", "rates/0-42.ndjson").await.unwrap();
+        assert!(!store.exists("test", "rates/0-42.ndjson").await.unwrap());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_write_and_read_compressed() {
+        let (store, root) = temporary_store();
+        let options = WriteOptions { compression: Compression::Zstd, ..Default::default() };
+
+        let blob_name = write_blob(&store, "test", "export.json", b"{\"base\": \"EUR\"}", &options).await.unwrap();
+        assert_eq!(blob_name, "export.json.zst");
+        assert_eq!(read_blob(&store, "test", &blob_name, &DecryptionKeys::default()).await.unwrap(), b"{\"base\": \"EUR\"}");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_write_file_blob() {
+        let (store, root) = temporary_store();
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("input.json"), b"{\"base\": \"EUR\"}").unwrap();
+
+        let blob_name = write_file_blob(&store, "test", "input.json", &root.join("input.json"), &WriteOptions::default()).await.unwrap();
+        assert_eq!(blob_name, "input.json");
+        assert_eq!(read_blob(&store, "test", "input.json", &DecryptionKeys::default()).await.unwrap(), b"{\"base\": \"EUR\"}");
+
+        assert!(write_file_blob(&store, "test", "missing.json", &root.join("missing.json"), &WriteOptions::default()).await.is_err());
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_conflict_policies() {
+        let (store, root) = temporary_store();
+        let policy = |conflict| WriteOptions { conflict, ..Default::default() };
+
+        write_blob(&store, "test", "rates/0-42.ndjson", b"first", &WriteOptions::default()).await.unwrap();
+
+        // IfNotExists keeps the existing blob
+        let error = write_blob(&store, "test", "rates/0-42.ndjson", b"second", &policy(ConflictPolicy::IfNotExists)).await.unwrap_err();
+        assert!(is_blob_exists(&error));
+        assert_eq!(read_blob(&store, "test", "rates/0-42.ndjson", &DecryptionKeys::default()).await.unwrap(), b"first");
+
+        // AppendSuffix writes to the first free name
+        let blob_name = write_blob(&store, "test", "rates/0-42.ndjson", b"second", &policy(ConflictPolicy::AppendSuffix)).await.unwrap();
+        assert_eq!(blob_name, "rates/0-42-1.ndjson");
+        let blob_name = write_blob(&store, "test", "rates/0-42.ndjson", b"third", &policy(ConflictPolicy::AppendSuffix)).await.unwrap();
+        assert_eq!(blob_name, "rates/0-42-2.ndjson");
+
+        // Overwrite replaces the blob
+        write_blob(&store, "test", "rates/0-42.ndjson", b"fourth", &policy(ConflictPolicy::Overwrite)).await.unwrap();
+        assert_eq!(read_blob(&store, "test", "rates/0-42.ndjson", &DecryptionKeys::default()).await.unwrap(), b"fourth");
+        assert_eq!(store.list("test", None).await.unwrap().len(), 3);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_if_not_exists() {
+        let (store, root) = temporary_store();
+        let options = WriteOptions { conflict: ConflictPolicy::IfNotExists, ..Default::default() };
+        let contents: Vec<String> = (0..8).map(|index| format!("{{\"writer\": {}}}", index)).collect();
+
+        let writes = contents.iter().map(|content| write_blob(&store, "test", "rates.json", content.as_bytes(), &options));
+        let results = futures::future::join_all(writes).await;
+
+        // Exactly one writer wins, the blob has its content and hash (verified by read_blob)
+        let winners: Vec<usize> = results.iter().enumerate().filter(|(_, result)| result.is_ok()).map(|(index, _)| index).collect();
+        assert_eq!(winners.len(), 1);
+        assert!(results.iter().filter_map(|result| result.as_ref().err()).all(is_blob_exists));
+        assert_eq!(read_blob(&store, "test", "rates.json", &DecryptionKeys::default()).await.unwrap(), contents[winners[0]].as_bytes());
+        assert_eq!(store.list("test", None).await.unwrap().len(), 1);
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_append_blob() {
+        let (store, root) = temporary_store();
+        let options = |day: &str| WriteOptions {
+            compression: Compression::Gzip,
+            metadata: BTreeMap::from([("created".to_string(), day.to_string())]),
+            ..Default::default()
+        };
+
+        let mut progress = AppendProgress::default();
+        append_blob(&store, "test", "rates/2023-03-01.ndjson", b"{\"offset\": 1}\n", &options("first"), &mut progress).await.unwrap();
+        assert_eq!(progress.blob_names, vec!["rates/2023-03-01.ndjson.gz"]);
+        append_blob(&store, "test", "rates/2023-03-01.ndjson", b"{\"offset\": 2}\n{\"offset\": 3}\n", &options("second"), &mut AppendProgress::default()).await.unwrap();
+
+        // Every append is a gzip member of its own, the properties are kept from the creation
+        assert_eq!(read_blob(&store, "test", "rates/2023-03-01.ndjson.gz", &DecryptionKeys::default()).await.unwrap(), b"{\"offset\": 1}\n{\"offset\": 2}\n{\"offset\": 3}\n");
+        let blob = store.info("test", "rates/2023-03-01.ndjson.gz").await.unwrap();
+        assert_eq!(blob.properties.metadata["created"], "first");
+        assert_eq!(blob.properties.content_encoding.as_deref(), Some("gzip"));
+
+        // A retry with the same progress does not append the content again
+        append_blob(&store, "test", "rates/2023-03-01.ndjson", b"{\"offset\": 1}\n", &options("first"), &mut progress).await.unwrap();
+        assert_eq!(read_blob(&store, "test", "rates/2023-03-01.ndjson.gz", &DecryptionKeys::default()).await.unwrap(), b"{\"offset\": 1}\n{\"offset\": 2}\n{\"offset\": 3}\n");
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_append_position() {
+        let (store, root) = temporary_store();
+        let properties = BlobProperties::default();
+
+        store.append("test", "rates.ndjson", b"first\n".to_vec(), &properties, 0).await.unwrap();
+        assert_eq!(store.append_state("test", "rates.ndjson").await.unwrap(), Some(AppendState { size: 6, blocks: 0 }));
+
+        // A block at another position is rejected, nothing is appended
+        let error = store.append("test", "rates.ndjson", b"second\n".to_vec(), &properties, 0).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<AppendError>(), Some(AppendError::PositionMismatch(_))));
+
+        // append_blob continues in the last existing blob of the name
+        store.append("test", "rates-1.ndjson", b"other\n".to_vec(), &properties, 0).await.unwrap();
+        let mut progress = AppendProgress::default();
+        append_blob(&store, "test", "rates.ndjson", b"second\n", &WriteOptions::default(), &mut progress).await.unwrap();
+        assert_eq!(progress.blob_names, vec!["rates-1.ndjson"]);
+        assert_eq!(store.get("test", "rates-1.ndjson").await.unwrap().content, b"other\nsecond\n");
+
+        // A line that does not fit into a block is rejected
+        let error = append_blob(&store, "test", "rates.ndjson", &vec![b'x'; APPEND_BLOCK_SIZE + 1], &WriteOptions::default(), &mut AppendProgress::default()).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<AppendError>(), Some(AppendError::LineTooLong(..))));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[tokio::test]
+    async fn test_read_detects_modified_blob() {
+        let (store, root) = temporary_store();
+
+        write_blob(&store, "test", "rates.json", b"{\"base\": \"EUR\"}", &WriteOptions::default()).await.unwrap();
+        assert!(store.info("test", "rates.json").await.unwrap().properties.metadata.contains_key("content_md5"));
+
+        // Modify the content without updating the stored hash
+        std::fs::write(root.join("test/rates.json"), b"{\"base\": \"USD\"}").unwrap();
+        let error = read_blob(&store, "test", "rates.json", &DecryptionKeys::default()).await.unwrap_err();
+        assert!(matches!(error.downcast_ref::<IntegrityError>(), Some(IntegrityError::Mismatch { .. })));
+
+        let _ = std::fs::remove_dir_all(root);
+    }
+
+    #[test]
+    fn test_reject_names_outside_of_root() {
+        assert!(checked_path("../etc/passwd").is_err());
+        assert!(checked_path("/etc/passwd").is_err());
+        assert!(checked_path("").is_err());
+        assert!(checked_path("data/test.json").is_ok());
+    }
+}
@@ -0,0 +1,426 @@
+/*
+    This file contains the storage abstraction used by the sinks (write, read, forward)
+    A BlobStore is implemented for Azure Blob Storage and for a local directory
+*/
+
+pub mod azure;
+pub mod local;
+
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::azure::compression::{Compression, COMPRESSION_METADATA};
+use crate::azure::encryption::{decrypt_blob, encrypt, DecryptionKeys};
+use crate::azure::integrity::{content_md5, verify_md5, MD5_METADATA};
+use crate::azure::upload::BlockOptions;
+use crate::azure::writer::{name_stem, ConflictPolicy, WriteOptions};
+use crate::errors::{AppendError, ConflictError};
+use crate::get_sink_details;
+use self::azure::AzureBlobStore;
+use self::local::LocalBlobStore;
+
+// Maximum size of a block appended by append_blob
+// - Azure Blob Storage accepts append blocks of up to 4 MiB, the margin covers the overhead of the compression
+pub(crate) const APPEND_BLOCK_SIZE: usize = 4 * 1024 * 1024 - 64 * 1024;
+
+// Maximum number of blocks of an append blob in Azure Blob Storage
+const MAX_APPEND_BLOCKS: u64 = 50_000;
+
+/// Available sinks
+/// - Azure: Azure Blob Storage (see azure_config.json)
+/// - Local: Local directory, every container is a subdirectory
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum SinkKind {
+    #[default]
+    Azure,
+    Local,
+}
+
+/// Properties stored with a blob
+/// - content_type: MIME type of the (uncompressed) content
+/// - content_encoding: Compression of the content (e.g. gzip), if any
+/// - metadata: User defined name/value pairs stored with the blob
+/// - tags: Blob index tags (searchable name/value pairs)
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlobProperties {
+    pub content_type: String,
+    pub content_encoding: Option<String>,
+    #[serde(default)]
+    pub metadata: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+}
+
+impl Default for BlobProperties {
+    fn default() -> Self {
+        BlobProperties {
+            content_type: "application/json".to_string(),
+            content_encoding: None,
+            metadata: BTreeMap::new(),
+            tags: BTreeMap::new(),
+        }
+    }
+}
+
+/// A blob listed in a container
+/// - size: Size of the stored (possibly compressed) content in bytes
+/// - etag: Version of the blob, if the sink provides one (see BlobStore::set_metadata)
+#[derive(Debug, Clone)]
+pub struct BlobInfo {
+    pub name: String,
+    pub size: u64,
+    pub last_modified: OffsetDateTime,
+    pub etag: Option<String>,
+    pub properties: BlobProperties,
+}
+
+/// Content and properties of a blob
+#[derive(Debug, Clone)]
+pub struct StoredBlob {
+    pub content: Vec<u8>,
+    pub properties: BlobProperties,
+}
+
+/// Condition of a write
+/// - Always: An existing blob is overwritten
+/// - IfNotExists: The write fails with ConflictError::BlobExists if the blob exists (checked atomically by the sink)
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WriteCondition {
+    Always,
+    IfNotExists,
+}
+
+impl From<ConflictPolicy> for WriteCondition {
+    fn from(policy: ConflictPolicy) -> Self {
+        if policy.if_not_exists() {
+            WriteCondition::IfNotExists
+        } else {
+            WriteCondition::Always
+        }
+    }
+}
+
+/// Operations every sink has to provide
+/// - Blob names may contain '/' to categorize blobs in subdirectories
+/// - create_container succeeds if the container already exists
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+
+    /// Writes the blob, an existing blob is handled according to the condition
+    async fn put(&self, container_name: &str, blob_name: &str, content: Vec<u8>, properties: &BlobProperties, condition: WriteCondition) -> Result<(), anyhow::Error>;
+
+    /// Writes the content of a local file as the blob, an existing blob is handled according to the condition
+    /// - The default reads the whole file, sinks that can stream large files override it
+    /// - The MD5 hash of the file is recorded in the metadata (see write_blob)
+    async fn put_file(&self, container_name: &str, blob_name: &str, path: &Path, properties: &BlobProperties, condition: WriteCondition) -> Result<(), anyhow::Error> {
+        let content = tokio::fs::read(path).await
+            .map_err(|e| anyhow!("Error reading file {}: {}", path.display(), e))?;
+
+        let mut properties = properties.clone();
+        properties.metadata.insert(MD5_METADATA.to_string(), content_md5(&content));
+        self.put(container_name, blob_name, content, &properties, condition).await
+    }
+
+    /// Returns size and block count of an append blob, None if the blob does not exist
+    /// - Fails with AppendError::NotAppendBlob if the blob exists, but is not an append blob
+    async fn append_state(&self, container_name: &str, blob_name: &str) -> Result<Option<AppendState>, anyhow::Error>;
+
+    /// Appends a block to an append blob, the blob is created with the properties if it does not exist
+    /// - position: Expected size of the blob (see AppendState), a blob of another size is not appended to
+    ///   (AppendError::PositionMismatch), so a retried request never appends the block twice
+    /// - Fails with AppendError::BlockLimit if the blob can not take another block (nothing is appended)
+    /// - Fails with AppendError::NotAppendBlob if the blob exists, but is not an append blob
+    async fn append(&self, container_name: &str, blob_name: &str, block: Vec<u8>, properties: &BlobProperties, position: u64) -> Result<(), anyhow::Error>;
+
+    /// Reads content and properties of the blob
+    async fn get(&self, container_name: &str, blob_name: &str) -> Result<StoredBlob, anyhow::Error>;
+
+    /// Reads size, last modification, properties and metadata of the blob (without the content)
+    async fn info(&self, container_name: &str, blob_name: &str) -> Result<BlobInfo, anyhow::Error>;
+
+    /// Lists the blobs of the container, optionally filtered by a name prefix
+    async fn list(&self, container_name: &str, prefix: Option<&str>) -> Result<Vec<BlobInfo>, anyhow::Error>;
+
+    /// Replaces the metadata of the blob, the content and the other properties stay untouched
+    /// - etag: The update fails if the blob was changed since it was listed (sinks without versions ignore it)
+    async fn set_metadata(&self, container_name: &str, blob_name: &str, metadata: &BTreeMap<String, String>, etag: Option<&str>) -> Result<(), anyhow::Error>;
+
+    /// Deletes the blob
+    async fn delete(&self, container_name: &str, blob_name: &str) -> Result<(), anyhow::Error>;
+
+    /// Returns true if the blob exists
+    async fn exists(&self, container_name: &str, blob_name: &str) -> Result<bool, anyhow::Error>;
+
+    /// Creates the container if it does not exist yet
+    async fn create_container(&self, container_name: &str) -> Result<(), anyhow::Error>;
+}
+
+/// State of an append blob
+/// - size: Size in bytes, the position of the next block (see BlobStore::append)
+/// - blocks: Number of committed blocks (sinks without a block limit report 0)
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct AppendState {
+    pub size: u64,
+    pub blocks: u64,
+}
+
+/// Progress of an append (see append_blob), kept between the attempts of a batch
+/// - appended: Bytes of the content that were appended, a retry continues behind them
+/// - blob_names: Names of the blobs written (more than one if a blob was full)
+#[derive(Debug, Clone, Default)]
+pub struct AppendProgress {
+    pub appended: usize,
+    pub blob_names: Vec<String>,
+}
+
+/// Opens the sink used by a command
+/// - sink/path: Values given on the command line, they take precedence over sink_config.json
+/// - blocks: Block size and parallelism of large uploads (Azure Blob Storage only)
+/// - Without any configuration, Azure Blob Storage is used
+/// - Fails if sink_config.json exists but can not be read or parsed
+pub fn open_blob_store(sink: Option<SinkKind>, path: Option<&str>, blocks: BlockOptions) -> Result<Box<dyn BlobStore>, anyhow::Error> {
+    let config = get_sink_details()?;
+
+    let sink = sink.unwrap_or(config.sink);
+    let path = path.unwrap_or(&config.local_path);
+
+    Ok(match sink {
+        SinkKind::Azure => Box::new(AzureBlobStore::with_blocks(blocks)),
+        SinkKind::Local => {
+            info!("Using local sink: {}", path);
+            Box::new(LocalBlobStore::new(path))
+        }
+    })
+}
+
+/// Writes the content to the sink
+/// - Compresses the content (if requested) and appends the extension of the compression to the blob name
+/// - Encrypts the compressed content (if requested), the key id, nonce and wrapped data key are stored in the metadata
+/// - Records the MD5 hash of the stored (compressed, encrypted) bytes in the metadata
+/// - Creates the container if it does not exist
+/// - Handles an existing blob according to the conflict policy (see ConflictPolicy)
+/// - Returns the name of the blob written
+pub async fn write_blob(store: &dyn BlobStore, container_name: &str, blob_name: &str, content: &[u8], options: &WriteOptions) -> Result<String, anyhow::Error> {
+
+    let blob_name = options.compression.blob_name(blob_name);
+
+    let data = options.compression.compress(content)?;
+    if options.compression != Compression::None {
+        info!("Compressed {} bytes to {} bytes ({:?})", content.len(), data.len(), options.compression);
+    }
+
+    let mut properties = write_properties(options);
+    let data = match &options.encryption {
+        Some(keys) => {
+            let (encrypted, encryption_metadata) = encrypt(&data, keys)?;
+            properties.metadata.extend(encryption_metadata);
+            encrypted
+        },
+        None => data,
+    };
+    properties.metadata.insert(MD5_METADATA.to_string(), content_md5(&data));
+
+    store.create_container(container_name).await?;
+
+    // Existing blobs are handled according to the conflict policy
+    for candidate in options.conflict.candidates(&blob_name) {
+        match store.put(container_name, &candidate, data.clone(), &properties, options.conflict.into()).await {
+            Ok(()) => return Ok(candidate),
+            Err(e) if options.conflict == ConflictPolicy::AppendSuffix && is_blob_exists(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(anyhow!("No free name found for blob {} in {}", blob_name, container_name))
+}
+
+/// Writes the content of a local file to the sink
+/// - Uncompressed files are passed to the sink as a file, so that large files are streamed (see BlobStore::put_file)
+/// - Compressed and encrypted files are read completely (see write_blob)
+/// - Handles an existing blob according to the conflict policy (see ConflictPolicy)
+/// - Returns the name of the blob written
+pub async fn write_file_blob(store: &dyn BlobStore, container_name: &str, blob_name: &str, path: &Path, options: &WriteOptions) -> Result<String, anyhow::Error> {
+
+    if options.compression != Compression::None || options.encryption.is_some() {
+        let content = tokio::fs::read(path).await
+            .map_err(|e| anyhow!("Error reading file {}: {}", path.display(), e))?;
+        return write_blob(store, container_name, blob_name, &content, options).await;
+    }
+
+    store.create_container(container_name).await?;
+
+    // Existing blobs are handled according to the conflict policy
+    for candidate in options.conflict.candidates(blob_name) {
+        match store.put_file(container_name, &candidate, path, &write_properties(options), options.conflict.into()).await {
+            Ok(()) => return Ok(candidate),
+            Err(e) if options.conflict == ConflictPolicy::AppendSuffix && is_blob_exists(&e) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+
+    Err(anyhow!("No free name found for blob {} in {}", blob_name, container_name))
+}
+
+/// Appends the content to an append blob (see BlobStore::append)
+/// - The content is split into blocks of at most APPEND_BLOCK_SIZE bytes that end at a line break,
+///   a line is never split across blocks or blobs
+/// - Fails with AppendError::LineTooLong if a line does not fit into a block (nothing is appended)
+/// - Every block is compressed on its own (gzip members and zstd frames can be concatenated)
+/// - Creates the container and the blob if they do not exist, the properties are only set on creation
+/// - Starts with the last existing blob of the name (e.g. 2023-03-01-2.ndjson) and continues in the next one
+///   before a blob exceeds MAX_APPEND_BLOCKS blocks
+/// - Every block is appended at the size read before, a block of another writer moves the block behind it
+/// - progress: Bytes appended and blobs written, a retry with the same progress continues behind the appended blocks
+pub async fn append_blob(store: &dyn BlobStore, container_name: &str, blob_name: &str, content: &[u8], options: &WriteOptions, progress: &mut AppendProgress) -> Result<(), anyhow::Error> {
+
+    if options.encryption.is_some() {
+        return Err(anyhow!("Encrypted blobs can not be appended to"));
+    }
+
+    let blob_name = options.compression.blob_name(blob_name);
+    let properties = write_properties(options);
+
+    let blocks = append_blocks(&content[progress.appended..], APPEND_BLOCK_SIZE)
+        .map_err(|length| AppendError::LineTooLong(blob_name.clone(), length, APPEND_BLOCK_SIZE))?;
+    if blocks.is_empty() {
+        return Ok(());
+    }
+
+    store.create_container(container_name).await?;
+
+    // The search for a blob with free blocks starts at the last existing blob of the name
+    let existing: Vec<String> = store.list(container_name, Some(name_stem(&blob_name))).await?
+        .into_iter()
+        .map(|blob| blob.name)
+        .collect();
+    let names: Vec<String> = ConflictPolicy::AppendSuffix.candidates(&blob_name).collect();
+    let mut index = names.iter().rposition(|name| existing.contains(name)).unwrap_or(0);
+    let mut state = store.append_state(container_name, &names[index]).await?.unwrap_or_default();
+
+    for block in blocks {
+        let compressed = options.compression.compress(block)?;
+        loop {
+            if state.blocks >= MAX_APPEND_BLOCKS {
+                info!("Blob {} is full: Continuing in the next blob", names[index]);
+                index += 1;
+                let name = names.get(index)
+                    .ok_or_else(|| anyhow!("No free name found for blob {} in {}", blob_name, container_name))?;
+                state = store.append_state(container_name, name).await?.unwrap_or_default();
+                continue;
+            }
+
+            let position = state.size;
+            match store.append(container_name, &names[index], compressed.clone(), &properties, position).await {
+                Ok(()) => {
+                    state.size += compressed.len() as u64;
+                    state.blocks += 1;
+                    break;
+                },
+                Err(e) => match e.downcast_ref::<AppendError>() {
+                    Some(AppendError::BlockLimit(_)) => state.blocks = MAX_APPEND_BLOCKS,
+                    Some(AppendError::PositionMismatch(_)) => {
+                        state = store.append_state(container_name, &names[index]).await?.unwrap_or_default();
+                        // A request that was sent again after a lost response finds its own block at the position
+                        if state.size == position + compressed.len() as u64 {
+                            warn!("Block of {} bytes was already appended to blob {}", compressed.len(), names[index]);
+                            break;
+                        }
+                        info!("Blob {} was appended to by another writer: Appending at {}", names[index], state.size);
+                    },
+                    _ => return Err(e),
+                },
+            }
+        }
+
+        progress.appended += block.len();
+        if !progress.blob_names.contains(&names[index]) {
+            progress.blob_names.push(names[index].clone());
+        }
+    }
+
+    Ok(())
+}
+
+/// Splits the content into blocks of at most block_size bytes that end at a line break (the last one may end without)
+/// - Returns the length of the first line that is longer than block_size as error
+fn append_blocks(content: &[u8], block_size: usize) -> Result<Vec<&[u8]>, usize> {
+    let mut blocks = Vec::new();
+    let mut rest = content;
+    while rest.len() > block_size {
+        let end = match rest[..block_size].iter().rposition(|byte| *byte == b'\n') {
+            Some(index) => index + 1,
+            None => return Err(rest.iter().position(|byte| *byte == b'\n').map(|index| index + 1).unwrap_or(rest.len())),
+        };
+        let (block, remaining) = rest.split_at(end);
+        blocks.push(block);
+        rest = remaining;
+    }
+    if !rest.is_empty() {
+        blocks.push(rest);
+    }
+    Ok(blocks)
+}
+
+/// Returns true if a write failed because the blob exists (see WriteCondition::IfNotExists)
+pub fn is_blob_exists(e: &anyhow::Error) -> bool {
+    e.downcast_ref::<ConflictError>().is_some()
+}
+
+/// Returns the properties of a blob written with the options
+/// - Encrypted blobs have no Content-Encoding (a client would try to decompress the encrypted bytes),
+///   their compression is stored in the metadata (see COMPRESSION_METADATA)
+fn write_properties(options: &WriteOptions) -> BlobProperties {
+    let content_encoding = options.compression.content_encoding().map(|encoding| encoding.to_string());
+    let mut metadata = options.metadata.clone();
+    let content_encoding = match (&options.encryption, content_encoding) {
+        (Some(_), Some(encoding)) => {
+            metadata.insert(COMPRESSION_METADATA.to_string(), encoding);
+            None
+        },
+        (_, content_encoding) => content_encoding,
+    };
+
+    BlobProperties {
+        content_type: options.content_type.clone(),
+        content_encoding,
+        metadata,
+        tags: options.tags.clone(),
+    }
+}
+
+/// Reads the content from the sink
+/// - Verifies the stored bytes against the MD5 hash in the metadata (fails with IntegrityError::Mismatch)
+/// - Decrypts encrypted blobs (keys loaded once per command, see DecryptionKeys)
+/// - Decompresses the content based on the compression of the blob (gzip, zstd, see Compression::from_properties)
+pub async fn read_blob(store: &dyn BlobStore, container_name: &str, blob_name: &str, keys: &DecryptionKeys) -> Result<Vec<u8>, anyhow::Error> {
+
+    let blob = store.get(container_name, blob_name).await?;
+    verify_md5(blob_name, &blob.content, blob.properties.metadata.get(MD5_METADATA).map(String::as_str))?;
+    let content = decrypt_blob(blob_name, blob.content, &blob.properties.metadata, keys)?;
+    let compression = Compression::from_properties(blob.properties.content_encoding.as_deref(), &blob.properties.metadata);
+
+    Ok(compression.decompress(&content)?)
+}
+
+// -----------
+// Unit Tests
+// -----------
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_blocks() {
+        assert_eq!(append_blocks(b"", 8), Ok(Vec::<&[u8]>::new()));
+        assert_eq!(append_blocks(b"a\nbb\nccc\n", 8), Ok(vec![&b"a\nbb\n"[..], &b"ccc\n"[..]]));
+        // Lines longer than a block are rejected
+        assert_eq!(append_blocks(b"a\n0123456789\nb\n", 4), Err(11));
+    }
+}
@@ -5,15 +5,21 @@
         - create_and_delete_container
         - push_data
         - pull_data
+    - The tests run against a local Azurite emulator if azure_config.json contains
+      "connection_string": "UseDevelopmentStorage=true"
 */
 
 // Test the pull/push functions (request_data and push_data)
 // - The functions are tested by calling them and checking if the result is Ok
 #[cfg(test)]
 mod tests {
-    use exchange::azure::writer::{push_to_azure};
+    use exchange::azure::compression::Compression;
+    use exchange::azure::writer::{push_to_azure, WriteOptions};
     use exchange::azure::helper::{create_azure_container, delete_azure_blob, delete_azure_container};
+    use exchange::azure::encryption::DecryptionKeys;
     use exchange::azure::reader::{pull_from_azure};
+    use exchange::azure::upload::{upload_blob, BlockOptions};
+    use exchange::azure::helper::UploadProperties;
 
     // Initalize the container name and filename as constants
     const TEST_CONTAINER_NAME: &str = "test";
@@ -34,7 +40,7 @@ mod tests {
     #[tokio::test]
     async fn test_delete_from_azure() {
 
-        let result_create = push_to_azure(TEST_CONTAINER_NAME, TEST_FILENAME, TEST_BLOB_NAME).await;
+        let result_create = push_to_azure(TEST_CONTAINER_NAME, TEST_FILENAME, TEST_BLOB_NAME, &WriteOptions::default(), &BlockOptions::default()).await;
         let result_delete = delete_azure_blob(TEST_CONTAINER_NAME, TEST_FILENAME).await;
 
         assert!(result_create.is_ok());
@@ -43,16 +49,45 @@ mod tests {
 
     #[tokio::test]
     async fn test_push_to_azure() {
-        let result = push_to_azure(TEST_CONTAINER_NAME, TEST_FILENAME, TEST_BLOB_NAME).await;
+        let result = push_to_azure(TEST_CONTAINER_NAME, TEST_FILENAME, TEST_BLOB_NAME, &WriteOptions::default(), &BlockOptions::default()).await;
         let _ = delete_azure_blob(TEST_CONTAINER_NAME, TEST_BLOB_NAME).await;
         assert!(result.is_ok());
     }
 
     #[tokio::test]
     async fn test_pull_from_azure() {
-        let _ = push_to_azure(TEST_CONTAINER_NAME, TEST_FILENAME, TEST_BLOB_NAME).await;
-        let result = pull_from_azure(TEST_CONTAINER_NAME, TEST_BLOB_NAME).await;
+        let _ = push_to_azure(TEST_CONTAINER_NAME, TEST_FILENAME, TEST_BLOB_NAME, &WriteOptions::default(), &BlockOptions::default()).await;
+        let result = pull_from_azure(TEST_CONTAINER_NAME, TEST_BLOB_NAME, &DecryptionKeys::default()).await;
         let _ = delete_azure_blob(TEST_CONTAINER_NAME, TEST_BLOB_NAME).await;
         assert!(result.is_ok());
     }
+
+    #[tokio::test]
+    async fn test_pull_compressed_from_azure() {
+        let content = b"{\"base\": \"EUR\"}".to_vec();
+        let options = WriteOptions { compression: Compression::Gzip, ..Default::default() };
+
+        let blob_name = push_to_azure(TEST_CONTAINER_NAME, "test_compressed.json", &content, &options, &BlockOptions::default()).await.unwrap();
+        let result = pull_from_azure(TEST_CONTAINER_NAME, &blob_name, &DecryptionKeys::default()).await;
+        let _ = delete_azure_blob(TEST_CONTAINER_NAME, &blob_name).await;
+
+        assert_eq!(blob_name, "test_compressed.json.gz");
+        assert_eq!(result.unwrap(), content);
+    }
+
+    #[tokio::test]
+    async fn test_staged_upload_and_resume() {
+        let content: Vec<u8> = (0..10_000u32).flat_map(|i| i.to_le_bytes()).collect();
+        let options = BlockOptions { block_size: 4096, parallelism: 3, ..Default::default() };
+
+        // The second upload finds the blocks of the first one and skips them
+        let first = upload_blob(TEST_CONTAINER_NAME, "test_staged.bin", content.clone(), &UploadProperties::default(), &options).await;
+        let second = upload_blob(TEST_CONTAINER_NAME, "test_staged.bin", content.clone(), &UploadProperties::default(), &options).await;
+        let result = pull_from_azure(TEST_CONTAINER_NAME, "test_staged.bin", &DecryptionKeys::default()).await;
+        let _ = delete_azure_blob(TEST_CONTAINER_NAME, "test_staged.bin").await;
+
+        assert!(first.is_ok());
+        assert!(second.is_ok());
+        assert_eq!(result.unwrap(), content);
+    }
 }
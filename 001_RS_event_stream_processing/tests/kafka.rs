@@ -4,6 +4,7 @@
     - The kafka related functions are:
     - get_kafka_details
     - push_to_kafka
+    - forward_to_store (local sink, no Azure account required)
 */
 
 #[cfg(test)]
@@ -12,8 +13,12 @@ mod tests {
     use std::time::Duration;
 
     use exchange::kafka::producer::{new_kafka_producer, push_to_kafka};
-    use exchange::kafka::consumer::{new_kafka_consumer, read_from_kafka};
+    use exchange::kafka::consumer::{read_from_kafka, ConsumerOptions, StartPosition};
+    use exchange::forward::{forward_to_store, ForwardOptions};
     use exchange::request_data;
+    use exchange::storage::local::LocalBlobStore;
+    use exchange::storage::read_blob;
+    use exchange::azure::encryption::DecryptionKeys;
 
     use rdkafka::producer::FutureRecord;
     use rdkafka::util::Timeout;
@@ -21,7 +26,7 @@ mod tests {
     const TEST_API: &str = "test_api";
     const TEST_TOPIC: &str = "test";
     const TEST_MESSAGE: &str = "test_message";
-    const TEST_MESSAGE_BYTES: u8 = 12;
+    const TEST_MESSAGE_BYTES: u64 = 12;
     const TEST_KEY : &str = "00000000-0000-0000-0000-000000000000";
     const TEST_FILE: &str = "data/example_response.json";
 
@@ -30,18 +35,18 @@ mod tests {
     async fn test_push_to_kafka() {
 
         let test_message = std::fs::read_to_string(TEST_FILE).unwrap();
-        let test_message_bytes = bytecount::num_chars(test_message.as_bytes()) as u8;
+        let test_message_bytes = test_message.len() as u64;
 
         let result = push_to_kafka(TEST_TOPIC, &test_message).await.expect("Error: Failed to push to Kafka");
 
+        let message = &result.messages[0];
 
-        let message_count = result.0;
-        let message_size = result.1;
-        let key = result.2;
-
-        assert_eq!(message_count, 1);
-        assert_eq!(message_size, test_message_bytes);
-        assert_eq!(key.len(), 36);
+        assert_eq!(result.total_messages, 1);
+        assert_eq!(result.total_bytes, test_message_bytes);
+        assert_eq!(message.size, test_message_bytes);
+        assert_eq!(message.topic, TEST_TOPIC);
+        assert!(message.timestamp.is_some());
+        assert_eq!(message.key.as_ref().unwrap().len(), 36);
     }
 
     #[tokio::test]
@@ -49,19 +54,11 @@ mod tests {
 
         // Initialize the topic
         // This topic is used solely for this test
-        // If the topic is also test, the read test will fail. (2+ messages are in the topic)
         let test_read_topic = "test_read";
 
-        // Create a new Kafka producer (Consumer is not working here, yet)
+        // Create a new Kafka producer
         let push = new_kafka_producer().await;
 
-        // Send consumer in a background thread to avoid blocking
-        // Capture the result in a variable for testing
-        // TTL is set to 2 second to run quickly
-        let consumer = tokio::spawn(async move {
-            let result = read_from_kafka(test_read_topic, 2).await;
-            result
-        });
         // Push a message to the topic
         // Capture result to test for successful producer push
         let producer = push.send(
@@ -70,21 +67,25 @@ mod tests {
                 .key(TEST_KEY),
             Timeout::After(Duration::from_secs(1)),
         ).await;
+        let (partition, offset) = producer.expect("Error: Failed to push to Kafka");
 
-        // Wait for the consumer to finish by timeout (idle mode)
-        let result = consumer.await.unwrap().expect("Error: Consumer failed to read from Kafka");
+        // Read exactly the pushed message (no idle timeout involved)
+        let options = ConsumerOptions {
+            start: StartPosition::Offset { partition, offset },
+            max_messages: Some(1),
+            ..Default::default()
+        };
+        let result = read_from_kafka(test_read_topic, &options).await.expect("Error: Consumer failed to read from Kafka");
 
-        let message_count = result.0;
-        let message_content = result.1.get(&1).unwrap(); // since message_count is 1, the message index starts with 1
-        let message_size = result.2;
-        let total_size = result.3;
+        let message = &result.messages[0];
 
         // Check if tasks were successful
-        assert!(producer.is_ok());
-        assert_eq!(message_count, 1);
-        assert_eq!(message_content, TEST_MESSAGE);
-        assert_eq!(message_size[0], TEST_MESSAGE_BYTES); // Only one message is pushed atm.
-        assert_eq!(total_size, TEST_MESSAGE_BYTES as u32); // Should therefore be the same as the message size
+        assert_eq!(result.total_messages, 1);
+        assert_eq!(message.metadata.offset, offset);
+        assert_eq!(message.payload_str(), TEST_MESSAGE);
+        assert_eq!(message.metadata.key.as_deref(), Some(TEST_KEY));
+        assert_eq!(message.metadata.size, TEST_MESSAGE_BYTES); // Only one message is pushed atm.
+        assert_eq!(result.total_bytes, TEST_MESSAGE_BYTES); // Should therefore be the same as the message size
     }
 
     #[tokio::test]
@@ -92,12 +93,59 @@ mod tests {
 
         // This test is for the ingestion of data from the API & push to Kafka
         let response = Arc::new(request_data(TEST_API).await.expect("Error: Failed to get data from API"));
-        let message_size = bytecount::num_chars(response.to_string().as_bytes()) as u8;
+        let message_size = response.to_string().len() as u64;
         let produce = push_to_kafka(TEST_TOPIC, &response.to_string()).await.expect("Error: Failed to push to Kafka");
     
         assert!(response.is_object());
-        assert_eq!(produce.0, 1);
-        assert_eq!(produce.1, message_size);
-        assert_eq!(produce.2.len(), 36);
+        assert_eq!(produce.total_messages, 1);
+        assert_eq!(produce.total_bytes, message_size);
+        assert_eq!(produce.messages[0].key.as_ref().unwrap().len(), 36);
+    }
+
+    #[tokio::test]
+    async fn test_read_until_end() {
+
+        // Every message of the topic is read, but the consumer does not wait for new ones
+        let test_message = std::fs::read_to_string(TEST_FILE).unwrap();
+        let _ = push_to_kafka(TEST_TOPIC, &test_message).await.expect("Error: Failed to push to Kafka");
+
+        let options = ConsumerOptions {
+            start: StartPosition::Beginning,
+            until_end: true,
+            ..Default::default()
+        };
+        let result = read_from_kafka(TEST_TOPIC, &options).await.expect("Error: Consumer failed to read from Kafka");
+
+        assert!(result.total_messages >= 1);
+        assert!(result.messages.iter().any(|message| message.payload_str() == test_message));
+    }
+
+    #[tokio::test]
+    async fn test_forward_to_local_store() {
+
+        // The whole forward pipeline runs against a local directory instead of Azure Blob Storage
+        let root = std::env::temp_dir().join(format!("exchange-forward-{}", uuid::Uuid::new_v4()));
+        let store = LocalBlobStore::new(&root);
+
+        let test_message = std::fs::read_to_string(TEST_FILE).unwrap();
+        let _ = push_to_kafka(TEST_TOPIC, &test_message).await.expect("Error: Failed to push to Kafka");
+
+        let options = ForwardOptions {
+            consumer: ConsumerOptions {
+                group_id: Some(format!("forward-{}", uuid::Uuid::new_v4())),
+                start: StartPosition::Beginning,
+                until_end: true,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let report = forward_to_store(&store, TEST_TOPIC, "test", "forward.ndjson", &options).await.expect("Error: Failed to forward");
+
+        let content = read_blob(&store, "test", "forward.ndjson", &DecryptionKeys::default()).await.expect("Error: Blob not written");
+        let _ = std::fs::remove_dir_all(&root);
+
+        assert!(report.messages >= 1);
+        assert_eq!(report.blobs, vec!["forward.ndjson"]);
+        assert_eq!(String::from_utf8_lossy(&content).lines().count() as u64, report.messages);
     }
-}
\ No newline at end of file
+}